@@ -0,0 +1,321 @@
+//! CPU-only renderer of documents, for thumbnails and previews made without a browser.
+//!
+//! It draws the same scene as the SVG export, so both agree on which objects are visible and where.
+
+mod pixmap;
+
+pub use pixmap::Pixmap;
+
+use system::euclid::default::{Box2D, Point2D, Transform2D};
+use system::{
+    build_scene, scene_view_box, Color, DocumentReadable, Materialize, ObjectId, PropReadable,
+    SceneNode,
+};
+
+const FRAME_FILL: Color = Color {
+    r: 255,
+    g: 255,
+    b: 255,
+};
+
+/// Upper bound of `width * height` of an image, so a large scale can't exhaust memory.
+const MAX_PIXELS: u64 = 4096 * 4096;
+
+/// Sample points in a pixel. Coverage of a pixel is the ratio of samples inside of the shape.
+const SAMPLE_OFFSETS: [(f32, f32); 4] = [(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)];
+
+#[derive(Debug)]
+pub enum RenderError {
+    ObjectNotFound,
+    /// Scale is not a positive finite number
+    InvalidScale,
+    /// The image would have more than `MAX_PIXELS` pixels
+    TooLarge,
+    Encoding(png::EncodingError),
+}
+
+/// Renders the document or an object in it. One unit of the document becomes `scale` pixels.
+///
+/// If the object is a frame, the image is cropped to the frame. Otherwise the image is sized to fit
+/// everything in it.
+pub fn render<R, M>(
+    materialize: &M,
+    object_id: &ObjectId,
+    scale: f32,
+) -> Result<Pixmap, RenderError>
+where
+    R: PropReadable + DocumentReadable,
+    M: Materialize<R>,
+{
+    if !scale.is_finite() || scale <= 0.0 {
+        return Err(RenderError::InvalidScale);
+    }
+    let nodes = build_scene(materialize, object_id).map_err(|_| RenderError::ObjectNotFound)?;
+    let view_box = scene_view_box(&nodes);
+
+    let width = ((view_box.width() * scale).ceil() as u32).max(1);
+    let height = ((view_box.height() * scale).ceil() as u32).max(1);
+    if width as u64 * height as u64 > MAX_PIXELS {
+        return Err(RenderError::TooLarge);
+    }
+
+    let mut canvas = Canvas {
+        pixmap: Pixmap::new(width, height),
+        origin: view_box.min,
+        scale,
+    };
+    let mut clips = Vec::new();
+    for node in &nodes {
+        canvas.draw_node(node, &mut clips);
+    }
+    Ok(canvas.pixmap)
+}
+
+pub fn render_png<R, M>(
+    materialize: &M,
+    object_id: &ObjectId,
+    scale: f32,
+) -> Result<Vec<u8>, RenderError>
+where
+    R: PropReadable + DocumentReadable,
+    M: Materialize<R>,
+{
+    render(materialize, object_id, scale)?
+        .encode_png()
+        .map_err(RenderError::Encoding)
+}
+
+/// Renders a PNG whose longer side is `max_size` pixels.
+pub fn render_thumbnail_png<R, M>(
+    materialize: &M,
+    object_id: &ObjectId,
+    max_size: u32,
+) -> Result<Vec<u8>, RenderError>
+where
+    R: PropReadable + DocumentReadable,
+    M: Materialize<R>,
+{
+    let nodes = build_scene(materialize, object_id).map_err(|_| RenderError::ObjectNotFound)?;
+    let view_box = scene_view_box(&nodes);
+    let longer_side = view_box.width().max(view_box.height());
+    let scale = if longer_side > 0.0 {
+        max_size as f32 / longer_side
+    } else {
+        1.0
+    };
+    render_png(materialize, object_id, scale)
+}
+
+/// Area of an ancestor frame. Descendants are drawn only inside of it.
+struct Clip {
+    inverse: Transform2D<f32>,
+    w: f32,
+    h: f32,
+}
+
+impl Clip {
+    fn contains(&self, point: &Point2D<f32>) -> bool {
+        contains_rect(&self.inverse, self.w, self.h, point)
+    }
+}
+
+fn contains_rect(inverse: &Transform2D<f32>, w: f32, h: f32, point: &Point2D<f32>) -> bool {
+    let local = inverse.transform_point(*point);
+    local.x >= 0.0 && local.x <= w && local.y >= 0.0 && local.y <= h
+}
+
+fn contains_oval(inverse: &Transform2D<f32>, r_h: f32, r_v: f32, point: &Point2D<f32>) -> bool {
+    let local = inverse.transform_point(*point);
+    (local.x / r_h).powi(2) + (local.y / r_v).powi(2) <= 1.0
+}
+
+struct Canvas {
+    pixmap: Pixmap,
+    /// Point of the document at the top left corner of the image
+    origin: Point2D<f32>,
+    scale: f32,
+}
+
+impl Canvas {
+    fn draw_node(&mut self, node: &SceneNode, clips: &mut Vec<Clip>) {
+        match node {
+            SceneNode::Oval {
+                transform,
+                r_h,
+                r_v,
+                fill_color,
+                ..
+            } => {
+                if *r_h <= 0.0 || *r_v <= 0.0 {
+                    return;
+                }
+                if let Some(inverse) = transform.inverse() {
+                    self.fill(&node.bounds(), clips, fill_color, |point| {
+                        contains_oval(&inverse, *r_h, *r_v, point)
+                    });
+                }
+            }
+            SceneNode::Frame {
+                transform,
+                w,
+                h,
+                children,
+                ..
+            } => {
+                let inverse = match transform.inverse() {
+                    Some(inverse) => inverse,
+                    None => return,
+                };
+                self.fill(&node.bounds(), clips, &FRAME_FILL, |point| {
+                    contains_rect(&inverse, *w, *h, point)
+                });
+                clips.push(Clip {
+                    inverse,
+                    w: *w,
+                    h: *h,
+                });
+                for child in children {
+                    self.draw_node(child, clips);
+                }
+                clips.pop();
+            }
+        }
+    }
+
+    /// Fills pixels inside of `bounds` where the shape and every clip contain the sample points.
+    fn fill<F>(&mut self, bounds: &Box2D<f32>, clips: &[Clip], color: &Color, contains: F)
+    where
+        F: Fn(&Point2D<f32>) -> bool,
+    {
+        let to_pixel_x = |x: f32| (x - self.origin.x) * self.scale;
+        let to_pixel_y = |y: f32| (y - self.origin.y) * self.scale;
+        let min_x = to_pixel_x(bounds.min.x).floor().max(0.0) as u32;
+        let min_y = to_pixel_y(bounds.min.y).floor().max(0.0) as u32;
+        let max_x = (to_pixel_x(bounds.max.x).ceil().max(0.0) as u32).min(self.pixmap.width());
+        let max_y = (to_pixel_y(bounds.max.y).ceil().max(0.0) as u32).min(self.pixmap.height());
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let inside = SAMPLE_OFFSETS
+                    .iter()
+                    .filter(|(dx, dy)| {
+                        let point = Point2D::new(
+                            self.origin.x + (x as f32 + dx) / self.scale,
+                            self.origin.y + (y as f32 + dy) / self.scale,
+                        );
+                        contains(&point) && clips.iter().all(|clip| clip.contains(&point))
+                    })
+                    .count();
+                let coverage = inside as f32 / SAMPLE_OFFSETS.len() as f32;
+                self.pixmap.blend(x, y, color, coverage);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use system::uuid::Uuid;
+    use system::{Document, DocumentMutation, ObjectKind, PropKind, PropValue, Transaction};
+
+    fn float(object_id: ObjectId, prop_kind: PropKind, value: f32) -> DocumentMutation {
+        DocumentMutation::UpsertProp(object_id, prop_kind, Some(PropValue::Float(value)))
+    }
+
+    /// A 20x10 frame at (10, 10), with a red oval of radius 5 at its left edge.
+    fn sample_document() -> (Document, ObjectId) {
+        let mut document = Document::new();
+        let document_id = document.document_id();
+        let frame_id = Uuid::new_v4();
+        let oval_id = Uuid::new_v4();
+        document
+            .process(Transaction::new(vec![
+                DocumentMutation::CreateObject(frame_id, ObjectKind::Frame),
+                DocumentMutation::UpsertProp(
+                    frame_id,
+                    PropKind::Parent,
+                    Some(PropValue::Reference(document_id)),
+                ),
+                float(frame_id, PropKind::PosX, 10.0),
+                float(frame_id, PropKind::PosY, 10.0),
+                float(frame_id, PropKind::Width, 20.0),
+                float(frame_id, PropKind::Height, 10.0),
+                DocumentMutation::CreateObject(oval_id, ObjectKind::Oval),
+                DocumentMutation::UpsertProp(
+                    oval_id,
+                    PropKind::Parent,
+                    Some(PropValue::Reference(frame_id)),
+                ),
+                float(oval_id, PropKind::PosY, 5.0),
+                float(oval_id, PropKind::RadiusH, 5.0),
+                float(oval_id, PropKind::RadiusV, 5.0),
+                DocumentMutation::UpsertProp(
+                    oval_id,
+                    PropKind::FillColor,
+                    Some(PropValue::Color(Color { r: 255, g: 0, b: 0 })),
+                ),
+            ]))
+            .expect("should work");
+        (document, frame_id)
+    }
+
+    #[test]
+    fn it_should_render_frame_with_clipped_children() {
+        let (document, frame_id) = sample_document();
+
+        let pixmap = render(&document, &frame_id, 2.0).expect("should render");
+        assert_eq!((pixmap.width(), pixmap.height()), (40, 20));
+        // Center of the oval
+        assert_eq!(pixmap.pixel(2, 10), [255, 0, 0, 255]);
+        // Rest of the frame
+        assert_eq!(pixmap.pixel(30, 10), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn it_should_render_document_to_fit_everything() {
+        let (mut document, _) = sample_document();
+        let document_id = document.document_id();
+        let oval_id = Uuid::new_v4();
+        document
+            .process(Transaction::new(vec![
+                DocumentMutation::CreateObject(oval_id, ObjectKind::Oval),
+                DocumentMutation::UpsertProp(
+                    oval_id,
+                    PropKind::Parent,
+                    Some(PropValue::Reference(document_id)),
+                ),
+                float(oval_id, PropKind::RadiusH, 5.0),
+                float(oval_id, PropKind::RadiusV, 5.0),
+                DocumentMutation::UpsertProp(
+                    oval_id,
+                    PropKind::FillColor,
+                    Some(PropValue::Color(Color { r: 0, g: 0, b: 255 })),
+                ),
+            ]))
+            .expect("should work");
+
+        let pixmap = render(&document, &document_id, 1.0).expect("should render");
+        assert_eq!((pixmap.width(), pixmap.height()), (35, 25));
+        assert_eq!(pixmap.pixel(5, 5), [0, 0, 255, 255]);
+        // The red oval sticks out of the frame to the left, but it is clipped.
+        assert_eq!(pixmap.pixel(12, 17), [0, 0, 0, 0]);
+        assert_eq!(pixmap.pixel(20, 20), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn it_should_encode_thumbnail_as_png() {
+        let (document, frame_id) = sample_document();
+
+        let content = render_thumbnail_png(&document, &frame_id, 64).expect("should render");
+        assert!(content.starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert!(matches!(
+            render(&document, &frame_id, 1e6),
+            Err(RenderError::TooLarge)
+        ));
+        assert!(matches!(
+            render(&document, &Uuid::new_v4(), 1.0),
+            Err(RenderError::ObjectNotFound)
+        ));
+    }
+}
@@ -0,0 +1,69 @@
+use system::Color;
+
+/// RGBA image with straight (not premultiplied) alpha, starting fully transparent.
+pub struct Pixmap {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+impl Pixmap {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; (width * height * 4) as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// RGBA of the pixel.
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let offset = self.offset(x, y);
+        [
+            self.data[offset],
+            self.data[offset + 1],
+            self.data[offset + 2],
+            self.data[offset + 3],
+        ]
+    }
+
+    /// Draws an opaque color over the pixel with the coverage in `[0, 1]`.
+    pub fn blend(&mut self, x: u32, y: u32, color: &Color, coverage: f32) {
+        if coverage <= 0.0 {
+            return;
+        }
+        let offset = self.offset(x, y);
+        let dst_alpha = self.data[offset + 3] as f32 / 255.0;
+        let out_alpha = coverage + dst_alpha * (1.0 - coverage);
+        for (i, src) in [color.r, color.g, color.b].iter().enumerate() {
+            let dst = self.data[offset + i] as f32;
+            let out = (*src as f32 * coverage + dst * dst_alpha * (1.0 - coverage)) / out_alpha;
+            self.data[offset + i] = out.round() as u8;
+        }
+        self.data[offset + 3] = (out_alpha * 255.0).round() as u8;
+    }
+
+    pub fn encode_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        let mut content = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut content, self.width, self.height);
+            encoder.set_color(png::ColorType::RGBA);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&self.data)?;
+        }
+        Ok(content)
+    }
+
+    fn offset(&self, x: u32, y: u32) -> usize {
+        ((y * self.width + x) * 4) as usize
+    }
+}
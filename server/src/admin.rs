@@ -1,3 +1,4 @@
+use crate::recovery::RecoveryReport;
 use crate::session::SessionBehavior;
 use system::{FileId, SessionId};
 use tokio::sync::oneshot::Sender;
@@ -20,6 +21,8 @@ pub enum AdminCommand {
         file_id: FileId,
         tx: Sender<Result<(), ()>>,
     },
+    /// Returns what was recovered when the server started.
+    GetRecoveryReports { tx: Sender<Vec<RecoveryReport>> },
 }
 
 #[derive(Debug)]
@@ -0,0 +1,119 @@
+use crate::document_file::{DocumentFileError, DocumentRef};
+use crate::revision_history::now_millis;
+use crate::storage::{RecordKey, RecordKind, Storage};
+use serde::{Deserialize, Serialize};
+use system::uuid::Uuid;
+use system::{bincode, Document, DocumentReadable, DocumentSnapshot};
+
+pub type CheckpointId = Uuid;
+
+/// Named snapshot of a document, e.g. "sent to client v2".
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub id: CheckpointId,
+    pub label: String,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub author: String,
+    pub snapshot: DocumentSnapshot,
+}
+
+impl Checkpoint {
+    pub fn new(label: String, author: String, document: &Document) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            label,
+            timestamp: now_millis(),
+            author,
+            snapshot: document.snapshot(),
+        }
+    }
+}
+
+/// Returns checkpoints of the document, oldest first. A checkpoint which cannot be read is an
+/// error, so that it is noticed rather than silently missing.
+pub async fn read_checkpoints<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+) -> Result<Vec<Checkpoint>, DocumentFileError> {
+    let keys = storage.list().await.map_err(DocumentFileError::Storage)?;
+    let mut result = Vec::new();
+    for key in keys
+        .into_iter()
+        .filter(|key| &key.document_ref == document_ref)
+    {
+        if let RecordKind::Checkpoint(id) = key.kind {
+            result.push(read_checkpoint(storage, document_ref, &id).await?);
+        }
+    }
+    result.sort_by_key(|checkpoint| checkpoint.timestamp);
+    Ok(result)
+}
+
+pub async fn read_checkpoint<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+    checkpoint_id: &CheckpointId,
+) -> Result<Checkpoint, DocumentFileError> {
+    let kind = RecordKind::Checkpoint(*checkpoint_id);
+    let content = storage
+        .read(&RecordKey::new(document_ref, kind))
+        .await
+        .map_err(DocumentFileError::Storage)?;
+    bincode::deserialize::<Checkpoint>(&content).map_err(|err| DocumentFileError::Decode(kind, err))
+}
+
+/// Writes the checkpoint as a record of its own. Other checkpoints are not read or written.
+pub async fn add_checkpoint<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+    checkpoint: &Checkpoint,
+) -> Result<(), DocumentFileError> {
+    let content = bincode::serialize(checkpoint).expect("must succeed");
+    storage
+        .write(
+            &RecordKey::new(document_ref, RecordKind::Checkpoint(checkpoint.id)),
+            &content,
+        )
+        .await
+        .map_err(DocumentFileError::Storage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[tokio::test]
+    async fn it_should_keep_checkpoints_apart() {
+        let storage = MemoryStorage::new();
+        let document_ref = DocumentRef::main(Uuid::new_v4());
+        let document = Document::new();
+        let first = Checkpoint::new("first".into(), "test".into(), &document);
+        let broken = RecordKey::new(&document_ref, RecordKind::Checkpoint(first.id));
+        add_checkpoint(&storage, &document_ref, &first)
+            .await
+            .expect("should add");
+        storage
+            .write(&broken, b"broken")
+            .await
+            .expect("should write");
+
+        assert!(matches!(
+            read_checkpoints(&storage, &document_ref).await,
+            Err(DocumentFileError::Decode(..))
+        ));
+        let second = Checkpoint::new("second".into(), "test".into(), &document);
+        add_checkpoint(&storage, &document_ref, &second)
+            .await
+            .expect("should add");
+        assert_eq!(
+            storage.read(&broken).await.expect("should be kept"),
+            b"broken"
+        );
+        let checkpoint = read_checkpoint(&storage, &document_ref, &second.id)
+            .await
+            .expect("should read");
+        assert_eq!(checkpoint.label, "second");
+    }
+}
@@ -2,19 +2,23 @@ use actix::{Actor, ActorContext, AsyncContext, Handler, Message, Running, Stream
 use actix_web::{web, Error, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
 
-use system::{bincode, ConnectionId, FileId, IdentifiableCommand, IdentifiableEvent};
+use system::{bincode, ConnectionId, IdentifiableCommand, IdentifiableEvent, InitCompression};
 
 use crate::connection_tx_storage::ConnectionTx;
-use crate::document_file::get_document_file_meta;
+use crate::document_file::{BranchName, DocumentRef};
+use crate::file_command::FileCommand;
 use crate::server::{ServerCommand, ServerTx};
 use actix_web_actors::ws::{CloseCode, CloseReason};
+use system::serde::Deserialize;
 use system::uuid::Uuid;
 
 #[derive(Debug)]
 pub enum ConnectionCommand {
     Connect {
         tx: ConnectionTx,
-        file_id: FileId,
+        document_ref: DocumentRef,
+        /// Compression of `InitChunk`s, or `None` if the client receives `Init` as is
+        init_compression: Option<InitCompression>,
     },
     Disconnect {
         from: ConnectionId,
@@ -44,7 +48,8 @@ enum ConnectionState {
 struct ConnectionActor {
     state: ConnectionState,
     srv_tx: ServerTx,
-    file_id: FileId,
+    document_ref: DocumentRef,
+    init_compression: Option<InitCompression>,
 }
 
 impl Actor for ConnectionActor {
@@ -57,7 +62,8 @@ impl Actor for ConnectionActor {
             .try_send(ServerCommand::ConnectionCommand(
                 ConnectionCommand::Connect {
                     tx,
-                    file_id: self.file_id.clone(),
+                    document_ref: self.document_ref.clone(),
+                    init_compression: self.init_compression,
                 },
             ))
             .expect("server must not be not closed yet");
@@ -68,8 +74,10 @@ impl Actor for ConnectionActor {
             let addr = addr;
             log::info!("connection green thread - started");
             while let Some(msg) = rx.recv().await {
-                addr.try_send(ConnectionActorMessage(msg))
-                    .expect("should have enough buffer")
+                // Waits for room in the mailbox, since chunks of `Init` come in a burst.
+                if addr.send(ConnectionActorMessage(msg)).await.is_err() {
+                    break;
+                }
             }
             log::info!("connection green thread - terminated");
         });
@@ -155,19 +163,60 @@ impl Handler<ConnectionActorMessage> for ConnectionActor {
     }
 }
 
+#[derive(Deserialize)]
+pub struct WsQuery {
+    /// Connects to the main branch if not given.
+    branch: Option<BranchName>,
+    /// How the client receives a large `Init`. Clients which don't say receive it as is.
+    init: Option<InitTransfer>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InitTransfer {
+    /// `InitChunk`s without compression
+    Chunked,
+    /// `InitChunk`s compressed with deflate
+    Deflate,
+}
+
+impl InitTransfer {
+    fn compression(self) -> InitCompression {
+        match self {
+            InitTransfer::Chunked => InitCompression::None,
+            InitTransfer::Deflate => InitCompression::Deflate,
+        }
+    }
+}
+
 pub async fn ws_index(
     req: HttpRequest,
     stream: web::Payload,
+    query: web::Query<WsQuery>,
     srv_tx: web::Data<ServerTx>,
 ) -> Result<HttpResponse, Error> {
     let file_id_str = req.match_info().get("file_id").unwrap_or("").to_owned();
     if let Some(file_id) = file_id_str.parse::<Uuid>().ok() {
-        if let Ok(_) = get_document_file_meta(&file_id).await {
+        let WsQuery { branch, init } = query.into_inner();
+        let document_ref = DocumentRef::branch(file_id, branch)
+            .map_err(|_| actix_web::error::ErrorBadRequest("invalid branch name"))?;
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        srv_tx
+            .get_ref()
+            .clone()
+            .send(ServerCommand::FileCommand(FileCommand::GetDocumentMeta {
+                document_ref: document_ref.clone(),
+                tx,
+            }))
+            .await
+            .map_err(|_| actix_web::error::ErrorInternalServerError("Internal Server Error"))?;
+        if let Ok(Ok(_)) = rx.await {
             ws::start(
                 ConnectionActor {
                     srv_tx: srv_tx.get_ref().clone(),
                     state: ConnectionState::Idle,
-                    file_id,
+                    document_ref,
+                    init_compression: init.map(InitTransfer::compression),
                 },
                 &req,
                 stream,
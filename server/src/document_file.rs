@@ -1,55 +1,220 @@
-use system::{Document, DocumentReadable, DocumentSnapshot, FileId};
-use tokio::fs;
+use crate::storage::{RecordKey, RecordKind, RecordMeta, RecordOp, Storage, StorageError};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use system::{bincode, Document, DocumentReadable, DocumentSnapshot, FileId, SnapshotError};
 
-pub async fn write_document_file(file_id: &FileId, document: &Document) {
-    let file_name = create_file_name(file_id);
-    let snapshot = document.snapshot();
-    fs::write(file_name, snapshot.content())
+pub type BranchName = String;
+
+/// Name of the branch which is the file itself.
+pub const MAIN_BRANCH: &str = "main";
+
+/// Document of a file on a branch. `branch` is `None` for the main branch.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DocumentRef {
+    pub file_id: FileId,
+    pub branch: Option<BranchName>,
+}
+
+impl DocumentRef {
+    pub fn main(file_id: FileId) -> Self {
+        Self {
+            file_id,
+            branch: None,
+        }
+    }
+
+    /// `MAIN_BRANCH` is the same as no branch.
+    pub fn branch(file_id: FileId, branch: Option<BranchName>) -> Result<Self, DocumentFileError> {
+        match branch {
+            None => Ok(Self::main(file_id)),
+            Some(branch) if branch == MAIN_BRANCH => Ok(Self::main(file_id)),
+            Some(branch) if is_valid_branch_name(&branch) => Ok(Self {
+                file_id,
+                branch: Some(branch),
+            }),
+            Some(branch) => Err(DocumentFileError::InvalidBranchName(branch)),
+        }
+    }
+
+    pub fn branch_name(&self) -> &str {
+        self.branch.as_deref().unwrap_or(MAIN_BRANCH)
+    }
+}
+
+impl fmt::Display for DocumentRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.branch {
+            None => write!(f, "{}", self.file_id),
+            Some(branch) => write!(f, "{}@{}", self.file_id, branch),
+        }
+    }
+}
+
+/// Parses the `Display` form, e.g. a file name without its extension.
+impl FromStr for DocumentRef {
+    type Err = DocumentFileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (file_id, branch) = match s.find('@') {
+            Some(at) => (&s[..at], Some(s[at + 1..].to_string())),
+            None => (s, None),
+        };
+        let file_id = file_id
+            .parse::<FileId>()
+            .map_err(|_| DocumentFileError::InvalidFileName(s.to_string()))?;
+        Self::branch(file_id, branch)
+    }
+}
+
+/// Branch names become a part of file names and keys, so only a few characters are allowed.
+fn is_valid_branch_name(branch: &str) -> bool {
+    !branch.is_empty()
+        && branch.len() <= 64
+        && branch
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+pub async fn write_document_file<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+    document: &Document,
+) -> Result<(), DocumentFileError> {
+    let ops = document_file_ops(storage, document_ref, document).await;
+    storage
+        .write_batch(&ops)
         .await
-        .expect("must succeed");
-}
-
-pub async fn list_document_files() -> Vec<FileId> {
-    let mut result = Vec::new();
-
-    let dir = std::env::current_dir().expect("must succeed");
-    let mut entries = fs::read_dir(dir).await.expect("must succeed");
-    while let Some(entry) = entries.next_entry().await.expect("must succeed") {
-        let file_name = entry.file_name().into_string().expect("must succeed");
-        if file_name.ends_with(".rcs") {
-            if let Some(file_id) = file_name
-                .split(".")
-                .take(1)
-                .next()
-                .and_then(|s| s.parse::<FileId>().ok())
-            {
-                result.push(file_id);
-            }
+        .map_err(DocumentFileError::Storage)
+}
+
+/// Changes which write the document. The current snapshot becomes the backup first, only if it is
+/// intact, so the backup is always good.
+pub async fn document_file_ops<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+    document: &Document,
+) -> Vec<RecordOp> {
+    let key = RecordKey::new(document_ref, RecordKind::Snapshot);
+    let mut ops = Vec::new();
+    if let Ok(content) = storage.read(&key).await {
+        let snapshot = DocumentSnapshot::from_vec(content);
+        if snapshot.verify().is_ok() {
+            ops.push(RecordOp::Write(
+                RecordKey::new(document_ref, RecordKind::Backup),
+                snapshot.content().to_vec(),
+            ));
         }
     }
+    ops.push(RecordOp::Write(key, document.snapshot().content().to_vec()));
+    ops
+}
 
-    result
+/// Returns the files, i.e. main branches of documents.
+pub async fn list_document_files<S: Storage>(
+    storage: &S,
+) -> Result<Vec<FileId>, DocumentFileError> {
+    let mut result = storage
+        .list()
+        .await
+        .map_err(DocumentFileError::Storage)?
+        .into_iter()
+        .filter(|key| key.kind == RecordKind::Snapshot && key.document_ref.branch.is_none())
+        .map(|key| key.document_ref.file_id)
+        .collect::<Vec<_>>();
+    result.sort();
+    Ok(result)
 }
 
-pub async fn get_document_file_meta(file_id: &FileId) -> Result<(), ()> {
-    let file_name = create_file_name(file_id);
-    if let Ok(_) = fs::metadata(file_name).await {
-        Ok(())
-    } else {
-        Err(())
+/// Returns names of the branches of the file other than `MAIN_BRANCH`.
+pub async fn list_branches<S: Storage>(
+    storage: &S,
+    file_id: &FileId,
+) -> Result<Vec<BranchName>, DocumentFileError> {
+    let mut result = storage
+        .list()
+        .await
+        .map_err(DocumentFileError::Storage)?
+        .into_iter()
+        .filter(|key| key.kind == RecordKind::Snapshot && &key.document_ref.file_id == file_id)
+        .filter_map(|key| key.document_ref.branch)
+        .collect::<Vec<_>>();
+    result.sort();
+    Ok(result)
+}
+
+/// Deletes a branch with everything kept for it. The main branch cannot be deleted.
+pub async fn delete_branch<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+) -> Result<(), DocumentFileError> {
+    if document_ref.branch.is_none() {
+        return Err(DocumentFileError::InvalidBranchName(MAIN_BRANCH.into()));
     }
+    get_document_file_meta(storage, document_ref).await?;
+    let keys = storage.list().await.map_err(DocumentFileError::Storage)?;
+    for key in keys.iter().filter(|key| &key.document_ref == document_ref) {
+        storage
+            .delete(key)
+            .await
+            .map_err(DocumentFileError::Storage)?;
+    }
+    Ok(())
 }
 
-pub async fn read_document_file(file_id: &FileId) -> Result<Document, ()> {
-    let file_name = create_file_name(file_id);
-    if let Ok(v) = fs::read(file_name).await {
-        // TODO: DocumentSnapshot -> Document 변환시 에러 처리
-        Ok(Document::from(&DocumentSnapshot::from_vec(v)))
-    } else {
-        Err(())
+pub async fn get_document_file_meta<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+) -> Result<RecordMeta, DocumentFileError> {
+    storage
+        .metadata(&RecordKey::new(document_ref, RecordKind::Snapshot))
+        .await
+        .map_err(DocumentFileError::Storage)
+}
+
+#[derive(Debug)]
+pub enum DocumentFileError {
+    Storage(StorageError),
+    Snapshot(SnapshotError),
+    InvalidBranchName(String),
+    InvalidFileName(String),
+    /// The record exists but cannot be read. It is left as is, so that nothing overwrites it.
+    Decode(RecordKind, bincode::Error),
+}
+
+impl DocumentFileError {
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, DocumentFileError::Storage(StorageError::NotFound))
     }
 }
 
-fn create_file_name(file_id: &FileId) -> String {
-    format!("{}.rcs", file_id.to_string())
+/// Reads the document. If the snapshot is broken, e.g. half-written, the backup is read instead.
+pub async fn read_document_file<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+) -> Result<Document, DocumentFileError> {
+    let v = storage
+        .read(&RecordKey::new(document_ref, RecordKind::Snapshot))
+        .await
+        .map_err(DocumentFileError::Storage)?;
+    let err = match Document::try_from(&DocumentSnapshot::from_vec(v)) {
+        Ok(document) => return Ok(document),
+        Err(err) => err,
+    };
+    log::error!("Cannot read document file {}: {:?}", document_ref, err);
+
+    let backup = storage
+        .read(&RecordKey::new(document_ref, RecordKind::Backup))
+        .await
+        .ok()
+        .and_then(|v| Document::try_from(&DocumentSnapshot::from_vec(v)).ok());
+    if let Some(document) = backup {
+        log::warn!(
+            "Falling back to the backup of document file {}",
+            document_ref
+        );
+        Ok(document)
+    } else {
+        Err(DocumentFileError::Snapshot(err))
+    }
 }
@@ -0,0 +1,113 @@
+use crate::checkpoint::{Checkpoint, CheckpointId};
+use crate::document_file::{BranchName, DocumentFileError, DocumentRef};
+use crate::revision_history::{Revision, RevisionError, RevisionNumber};
+use crate::storage::RecordMeta;
+use system::{Document, FileId, MutationError, Transaction};
+use tokio::sync::oneshot::Sender;
+
+#[derive(Debug)]
+pub enum FileCommand {
+    /// Creates a file with an empty document, and returns its id.
+    CreateDocument {
+        tx: Sender<Result<FileId, FileCommandError>>,
+    },
+    ListDocuments {
+        tx: Sender<Result<Vec<FileId>, FileCommandError>>,
+    },
+    GetDocumentMeta {
+        document_ref: DocumentRef,
+        tx: Sender<Result<RecordMeta, FileCommandError>>,
+    },
+    /// Returns the latest document of the file, including changes of the session in progress.
+    GetDocument {
+        file_id: FileId,
+        tx: Sender<Result<Document, FileCommandError>>,
+    },
+    /// Overwrites the document of the file. Not allowed while a session is in progress.
+    ReplaceDocument {
+        file_id: FileId,
+        document: Document,
+        tx: Sender<Result<(), FileCommandError>>,
+    },
+    /// Applies the transaction to the document of the file. Not allowed while a session is in
+    /// progress.
+    ApplyTransaction {
+        file_id: FileId,
+        transaction: Transaction,
+        tx: Sender<Result<(), FileCommandError>>,
+    },
+    /// Copies the latest document of the `from` branch, or of the main branch if not given, into a
+    /// new branch.
+    ForkBranch {
+        file_id: FileId,
+        from: Option<BranchName>,
+        branch: BranchName,
+        tx: Sender<Result<(), FileCommandError>>,
+    },
+    /// Returns names of the branches other than the main branch.
+    ListBranches {
+        file_id: FileId,
+        tx: Sender<Result<Vec<BranchName>, FileCommandError>>,
+    },
+    /// Deletes the branch. Not allowed while a session is in progress on the branch.
+    DeleteBranch {
+        file_id: FileId,
+        branch: BranchName,
+        tx: Sender<Result<(), FileCommandError>>,
+    },
+    /// Returns at most `limit` committed transactions of the file after the revision `after`,
+    /// including those of the session in progress.
+    ListRevisions {
+        file_id: FileId,
+        after: RevisionNumber,
+        limit: usize,
+        tx: Sender<Result<Vec<Revision>, FileCommandError>>,
+    },
+    GetRevisionDocument {
+        file_id: FileId,
+        revision: RevisionNumber,
+        tx: Sender<Result<Document, FileCommandError>>,
+    },
+    /// Brings the document back to the revision by committing a new transaction. Returns the new
+    /// revision. Clients of the session in progress receive the transaction.
+    RestoreRevision {
+        file_id: FileId,
+        revision: RevisionNumber,
+        tx: Sender<Result<RevisionNumber, FileCommandError>>,
+    },
+    /// Saves the latest document of the file as a checkpoint.
+    CreateCheckpoint {
+        file_id: FileId,
+        label: String,
+        author: String,
+        tx: Sender<Result<Checkpoint, FileCommandError>>,
+    },
+    ListCheckpoints {
+        file_id: FileId,
+        tx: Sender<Result<Vec<Checkpoint>, FileCommandError>>,
+    },
+    GetCheckpoint {
+        file_id: FileId,
+        checkpoint_id: CheckpointId,
+        tx: Sender<Result<Checkpoint, FileCommandError>>,
+    },
+    /// Brings the document back to the checkpoint, like `RestoreRevision`.
+    RestoreCheckpoint {
+        file_id: FileId,
+        checkpoint_id: CheckpointId,
+        tx: Sender<Result<RevisionNumber, FileCommandError>>,
+    },
+}
+
+#[derive(Debug)]
+pub enum FileCommandError {
+    DocumentFile(DocumentFileError),
+    SessionInProgress,
+    InvalidMutation(MutationError),
+    BranchAlreadyExists,
+    Revision(RevisionError),
+    CheckpointNotFound,
+    /// What is restored or put is a version of another document than the one in the file, e.g. a
+    /// checkpoint taken before the file was replaced
+    DocumentMismatch,
+}
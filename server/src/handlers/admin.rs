@@ -1,5 +1,8 @@
 use crate::admin::{AdminCommand, FileDescription};
-use crate::document_file::{list_document_files, write_document_file};
+use crate::checkpoint::{Checkpoint, CheckpointId};
+use crate::file_command::{FileCommand, FileCommandError};
+use crate::recovery::RecoveryReport;
+use crate::revision_history::RevisionNumber;
 use crate::server::{ServerCommand, ServerTx};
 use crate::session::SessionBehavior;
 use actix_web::error;
@@ -8,13 +11,13 @@ use actix_web::Responder;
 use actix_web::Result;
 use askama_actix::Template;
 use system::serde::Deserialize;
-use system::uuid::Uuid;
-use system::{Document, FileId, SessionId};
+use system::{FileId, SessionId};
 
 #[derive(Template)]
 #[template(path = "admin-index.html")]
 pub struct AdminConsoleTemplate {
     documents_url: String,
+    recovery_reports: Vec<RecoveryReport>,
 }
 
 pub fn configure_admin_handlers(cfg: &mut web::ServiceConfig) {
@@ -46,22 +49,66 @@ pub fn configure_admin_handlers(cfg: &mut web::ServiceConfig) {
                 web::resource("/documents/{file_id}/commit_manually")
                     .name("admin_document_commit_manually")
                     .route(web::post().to(commit_manually)),
+            )
+            .service(
+                web::resource("/documents/{file_id}/checkpoints")
+                    .name("admin_document_create_checkpoint")
+                    .route(web::post().to(create_checkpoint)),
+            )
+            .service(
+                web::resource("/documents/{file_id}/checkpoints/{checkpoint_id}/restore")
+                    .name("admin_document_restore_checkpoint")
+                    .route(web::post().to(restore_checkpoint)),
             ),
     );
 }
 
-pub async fn admin_index(req: HttpRequest) -> Result<impl Responder> {
+pub async fn admin_index(req: HttpRequest, srv_tx: web::Data<ServerTx>) -> Result<impl Responder> {
     let documents_url = req
         .url_for("admin_documents", &[""])
         .expect("must match")
         .to_string();
-    Ok(AdminConsoleTemplate { documents_url })
+
+    let (tx, rx) = tokio::sync::oneshot::channel::<Vec<RecoveryReport>>();
+
+    srv_tx
+        .get_ref()
+        .clone()
+        .send(ServerCommand::AdminCommand(
+            AdminCommand::GetRecoveryReports { tx },
+        ))
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Internal Server Error"))?;
+
+    let recovery_reports = rx
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?;
+
+    Ok(AdminConsoleTemplate {
+        documents_url,
+        recovery_reports,
+    })
 }
 
-pub async fn create_document(req: HttpRequest) -> Result<impl Responder> {
-    let file_id = Uuid::new_v4();
-    let document = Document::new();
-    write_document_file(&file_id, &document).await;
+pub async fn create_document(
+    req: HttpRequest,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder> {
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<FileId, FileCommandError>>();
+
+    srv_tx
+        .get_ref()
+        .clone()
+        .send(ServerCommand::FileCommand(FileCommand::CreateDocument {
+            tx,
+        }))
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Internal Server Error"))?;
+
+    let file_id = rx
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(|_| error::ErrorInternalServerError("Cannot create file"))?;
     Ok(HttpResponse::Found()
         .header(
             "Location",
@@ -82,8 +129,25 @@ pub struct SimpleListTemplate {
     items: Vec<SimpleListItem>,
 }
 
-pub async fn list_documents(req: HttpRequest) -> Result<impl Responder> {
-    let entries = list_document_files().await;
+pub async fn list_documents(
+    req: HttpRequest,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder> {
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<Vec<FileId>, FileCommandError>>();
+
+    srv_tx
+        .get_ref()
+        .clone()
+        .send(ServerCommand::FileCommand(FileCommand::ListDocuments {
+            tx,
+        }))
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Internal Server Error"))?;
+
+    let entries = rx
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(|_| error::ErrorInternalServerError("Cannot list files"))?;
     Ok(SimpleListTemplate {
         items: entries
             .iter()
@@ -114,10 +178,25 @@ pub struct AdminShowFileTemplate {
     close_manual_session_action: String,
     commit_manually_action: String,
     has_pending_txs: bool,
+    create_checkpoint_action: String,
+    checkpoints: Vec<CheckpointListItem>,
+}
+
+struct CheckpointListItem {
+    label: String,
+    timestamp: u64,
+    author: String,
+    download_href: String,
+    restore_action: String,
 }
 
 impl AdminShowFileTemplate {
-    fn from_file_description(req: &HttpRequest, desc: FileDescription, file_id: &FileId) -> Self {
+    fn from_file_description(
+        req: &HttpRequest,
+        desc: FileDescription,
+        checkpoints: Vec<Checkpoint>,
+        file_id: &FileId,
+    ) -> Self {
         let (snapshot, online, manual, has_pending_txs) = match desc {
             FileDescription::Online {
                 debug,
@@ -154,6 +233,27 @@ impl AdminShowFileTemplate {
                 .expect("valid")
                 .to_string(),
             has_pending_txs,
+            create_checkpoint_action: req
+                .url_for("admin_document_create_checkpoint", &[file_id.to_string()])
+                .expect("valid")
+                .to_string(),
+            checkpoints: checkpoints
+                .into_iter()
+                .rev()
+                .map(|checkpoint| CheckpointListItem {
+                    download_href: format!("/files/{}/checkpoints/{}", file_id, checkpoint.id),
+                    restore_action: req
+                        .url_for(
+                            "admin_document_restore_checkpoint",
+                            &[file_id.to_string(), checkpoint.id.to_string()],
+                        )
+                        .expect("valid")
+                        .to_string(),
+                    label: checkpoint.label,
+                    timestamp: checkpoint.timestamp,
+                    author: checkpoint.author,
+                })
+                .collect(),
         }
     }
 }
@@ -190,8 +290,28 @@ pub async fn show_document(
         .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?;
     let desc = result.map_err(|err| error::ErrorInternalServerError(err))?;
 
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<Vec<Checkpoint>, FileCommandError>>();
+
+    srv_tx
+        .get_ref()
+        .clone()
+        .send(ServerCommand::FileCommand(FileCommand::ListCheckpoints {
+            file_id,
+            tx,
+        }))
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Internal Server Error"))?;
+
+    let checkpoints = rx
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(|_| error::ErrorInternalServerError("Internal Server Error"))?;
+
     Ok(AdminShowFileTemplate::from_file_description(
-        &req, desc, &file_id,
+        &req,
+        desc,
+        checkpoints,
+        &file_id,
     ))
 }
 
@@ -312,3 +432,94 @@ pub async fn commit_manually(
         .header("Location", redirect_to)
         .finish())
 }
+
+#[derive(Deserialize)]
+pub struct CreateCheckpointForm {
+    label: String,
+}
+
+pub async fn create_checkpoint(
+    req: HttpRequest,
+    path: web::Path<CreateManualSessionParam>,
+    form: web::Form<CreateCheckpointForm>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder> {
+    let file_id = path
+        .file_id
+        .parse::<FileId>()
+        .map_err(|_| error::ErrorBadRequest("invalid format"))?;
+
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<Checkpoint, FileCommandError>>();
+
+    srv_tx
+        .get_ref()
+        .clone()
+        .send(ServerCommand::FileCommand(FileCommand::CreateCheckpoint {
+            file_id,
+            label: form.into_inner().label,
+            author: "admin".into(),
+            tx,
+        }))
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Internal Server Error"))?;
+
+    rx.await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(|_| error::ErrorInternalServerError("Internal Server Error"))?;
+
+    let redirect_to = req
+        .url_for("admin_document", &[file_id.to_string()])
+        .map_err(|_| error::ErrorInternalServerError("Internal Server Error"))?
+        .to_string();
+
+    Ok(HttpResponse::Found()
+        .header("Location", redirect_to)
+        .finish())
+}
+
+#[derive(Deserialize)]
+pub struct RestoreCheckpointParam {
+    file_id: String,
+    checkpoint_id: String,
+}
+
+pub async fn restore_checkpoint(
+    req: HttpRequest,
+    path: web::Path<RestoreCheckpointParam>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder> {
+    let file_id = path
+        .file_id
+        .parse::<FileId>()
+        .map_err(|_| error::ErrorBadRequest("invalid format"))?;
+    let checkpoint_id = path
+        .checkpoint_id
+        .parse::<CheckpointId>()
+        .map_err(|_| error::ErrorBadRequest("invalid format"))?;
+
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<RevisionNumber, FileCommandError>>();
+
+    srv_tx
+        .get_ref()
+        .clone()
+        .send(ServerCommand::FileCommand(FileCommand::RestoreCheckpoint {
+            file_id,
+            checkpoint_id,
+            tx,
+        }))
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Internal Server Error"))?;
+
+    rx.await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(|_| error::ErrorInternalServerError("Internal Server Error"))?;
+
+    let redirect_to = req
+        .url_for("admin_document", &[file_id.to_string()])
+        .map_err(|_| error::ErrorInternalServerError("Internal Server Error"))?
+        .to_string();
+
+    Ok(HttpResponse::Found()
+        .header("Location", redirect_to)
+        .finish())
+}
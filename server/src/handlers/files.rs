@@ -1,26 +1,721 @@
 use crate::actix_web::Responder;
-use crate::document_file::{list_document_files, write_document_file};
-use actix_web::{web, HttpResponse};
-use system::serde_json::json;
-use system::uuid::Uuid;
-use system::Document;
+use crate::checkpoint::{Checkpoint, CheckpointId};
+use crate::document_file::{BranchName, DocumentFileError};
+use crate::file_command::{FileCommand, FileCommandError};
+use crate::revision_history::{Revision, RevisionError, RevisionNumber};
+use crate::server::{ServerCommand, ServerTx};
+use crate::storage::StorageError;
+use actix_web::error::BlockingError;
+use actix_web::{error, web, HttpResponse};
+use raster::RenderError;
+use std::convert::TryFrom;
+use system::serde::Deserialize;
+use system::serde_json::{self, json};
+use system::{
+    export_pdf, export_svg, import_svg, query_objects, Document, DocumentJson, ExcalidrawImport,
+    ExcalidrawScene, FileId, ObjectId, ObjectQuery, SvgImport, SvgImportError,
+};
 
 pub fn configure_file_handlers(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::resource("/files")
             .route(web::post().to(post))
             .route(web::get().to(get)),
-    );
+    )
+    .service(
+        web::resource("/files/{file_id}/json")
+            .route(web::get().to(get_json))
+            .route(web::put().to(put_json)),
+    )
+    .service(
+        web::resource("/files/{file_id}/excalidraw")
+            .route(web::get().to(get_excalidraw))
+            .route(web::put().to(put_excalidraw)),
+    )
+    .service(
+        web::resource("/files/{file_id}/branches")
+            .route(web::get().to(get_branches))
+            .route(web::post().to(post_branch)),
+    )
+    .service(
+        web::resource("/files/{file_id}/branches/{branch}").route(web::delete().to(delete_branch)),
+    )
+    .service(web::resource("/files/{file_id}/revisions").route(web::get().to(get_revisions)))
+    .service(
+        web::resource("/files/{file_id}/revisions/{revision}/json")
+            .route(web::get().to(get_revision_json)),
+    )
+    .service(
+        web::resource("/files/{file_id}/revisions/{revision}/restore")
+            .route(web::post().to(post_revision_restore)),
+    )
+    .service(
+        web::resource("/files/{file_id}/checkpoints")
+            .route(web::get().to(get_checkpoints))
+            .route(web::post().to(post_checkpoint)),
+    )
+    .service(
+        web::resource("/files/{file_id}/checkpoints/{checkpoint_id}")
+            .route(web::get().to(get_checkpoint)),
+    )
+    .service(
+        web::resource("/files/{file_id}/checkpoints/{checkpoint_id}/restore")
+            .route(web::post().to(post_checkpoint_restore)),
+    )
+    .service(web::resource("/files/{file_id}/query").route(web::post().to(post_query)))
+    .service(web::resource("/files/{file_id}/export.svg").route(web::get().to(get_svg)))
+    .service(web::resource("/files/{file_id}/import.svg").route(web::post().to(post_svg)))
+    .service(web::resource("/files/{file_id}/export.pdf").route(web::get().to(get_pdf)))
+    .service(web::resource("/files/{file_id}/export.png").route(web::get().to(get_png)))
+    .service(web::resource("/files/{file_id}/thumbnail.png").route(web::get().to(get_thumbnail)));
 }
 
-async fn post() -> Result<impl Responder, actix_web::error::Error> {
-    let file_id = Uuid::new_v4();
-    let document = Document::new();
-    write_document_file(&file_id, &document).await;
+const DEFAULT_THUMBNAIL_SIZE: u32 = 256;
+const MAX_THUMBNAIL_SIZE: u32 = 1024;
+const DEFAULT_REVISIONS_LIMIT: usize = 100;
+const MAX_REVISIONS_LIMIT: usize = 1000;
+
+async fn post(srv_tx: web::Data<ServerTx>) -> Result<impl Responder, actix_web::error::Error> {
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<FileId, FileCommandError>>();
+    send_file_command(&srv_tx, FileCommand::CreateDocument { tx }).await?;
+    let file_id = rx
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(file_command_error)?;
     Ok(HttpResponse::Ok().json(json!({ "fileId": file_id.to_string() })))
 }
 
-async fn get() -> Result<impl Responder, actix_web::error::Error> {
-    let entries = list_document_files().await;
+async fn get(srv_tx: web::Data<ServerTx>) -> Result<impl Responder, actix_web::error::Error> {
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<Vec<FileId>, FileCommandError>>();
+    send_file_command(&srv_tx, FileCommand::ListDocuments { tx }).await?;
+    let entries = rx
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(file_command_error)?;
     Ok(HttpResponse::Ok().json(json!(entries)))
 }
+
+#[derive(Deserialize)]
+pub struct FileParam {
+    file_id: String,
+}
+
+impl FileParam {
+    fn file_id(&self) -> Result<FileId, actix_web::error::Error> {
+        self.file_id
+            .parse::<FileId>()
+            .map_err(|_| error::ErrorBadRequest("invalid format"))
+    }
+}
+
+async fn get_json(
+    path: web::Path<FileParam>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let document = get_document(&srv_tx, path.file_id()?).await?;
+    let json = serde_json::to_string_pretty(&DocumentJson::from(&document))
+        .map_err(|_| error::ErrorInternalServerError("Internal Server Error"))?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(json))
+}
+
+async fn put_json(
+    path: web::Path<FileParam>,
+    body: web::Bytes,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let file_id = path.file_id()?;
+    let document_json = serde_json::from_slice::<DocumentJson>(&body)
+        .map_err(|err| error::ErrorBadRequest(err.to_string()))?;
+    let document = Document::try_from(&document_json)
+        .map_err(|err| error::ErrorBadRequest(format!("{:?}", err)))?;
+
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<(), FileCommandError>>();
+    send_file_command(
+        &srv_tx,
+        FileCommand::ReplaceDocument {
+            file_id,
+            document,
+            tx,
+        },
+    )
+    .await?;
+    rx.await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(file_command_error)?;
+
+    Ok(HttpResponse::Ok().json(json!({ "fileId": file_id.to_string() })))
+}
+
+async fn get_excalidraw(
+    path: web::Path<FileParam>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let document = get_document(&srv_tx, path.file_id()?).await?;
+    Ok(HttpResponse::Ok().json(ExcalidrawScene::from(&document)))
+}
+
+/// Replaces the document with the scene. Elements which cannot be imported are reported in the
+/// response.
+async fn put_excalidraw(
+    path: web::Path<FileParam>,
+    body: web::Bytes,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let file_id = path.file_id()?;
+    let scene = serde_json::from_slice::<ExcalidrawScene>(&body)
+        .map_err(|err| error::ErrorBadRequest(err.to_string()))?;
+    // The scene is imported as a version of the document in the file, if any, so that it is
+    // recorded as a revision of it.
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<Document, FileCommandError>>();
+    send_file_command(&srv_tx, FileCommand::GetDocument { file_id, tx }).await?;
+    let document_id = match rx
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+    {
+        Ok(document) => document.document_id(),
+        Err(FileCommandError::DocumentFile(err)) if err.is_not_found() => {
+            system::uuid::Uuid::new_v4()
+        }
+        Err(err) => return Err(file_command_error(err)),
+    };
+    let ExcalidrawImport { document, warnings } = scene
+        .to_document(document_id)
+        .map_err(|_| error::ErrorBadRequest("Scene cannot be imported"))?;
+
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<(), FileCommandError>>();
+    send_file_command(
+        &srv_tx,
+        FileCommand::ReplaceDocument {
+            file_id,
+            document,
+            tx,
+        },
+    )
+    .await?;
+    rx.await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(file_command_error)?;
+
+    Ok(HttpResponse::Ok().json(json!({ "fileId": file_id.to_string(), "warnings": warnings })))
+}
+
+async fn get_branches(
+    path: web::Path<FileParam>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let file_id = path.file_id()?;
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<Vec<BranchName>, FileCommandError>>();
+    send_file_command(&srv_tx, FileCommand::ListBranches { file_id, tx }).await?;
+    let branches = rx
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(file_command_error)?;
+    Ok(HttpResponse::Ok().json(branches))
+}
+
+#[derive(Deserialize)]
+pub struct ForkBranchBody {
+    name: BranchName,
+    /// Forks the main branch if not given.
+    from: Option<BranchName>,
+}
+
+/// Creates a branch with the latest document of another branch.
+async fn post_branch(
+    path: web::Path<FileParam>,
+    body: web::Json<ForkBranchBody>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let file_id = path.file_id()?;
+    let ForkBranchBody { name, from } = body.into_inner();
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<(), FileCommandError>>();
+    send_file_command(
+        &srv_tx,
+        FileCommand::ForkBranch {
+            file_id,
+            from,
+            branch: name.clone(),
+            tx,
+        },
+    )
+    .await?;
+    rx.await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(file_command_error)?;
+
+    Ok(HttpResponse::Ok().json(json!({ "fileId": file_id.to_string(), "branch": name })))
+}
+
+#[derive(Deserialize)]
+pub struct BranchParam {
+    file_id: String,
+    branch: BranchName,
+}
+
+async fn delete_branch(
+    path: web::Path<BranchParam>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let BranchParam { file_id, branch } = path.into_inner();
+    let file_id = file_id
+        .parse::<FileId>()
+        .map_err(|_| error::ErrorBadRequest("invalid format"))?;
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<(), FileCommandError>>();
+    send_file_command(
+        &srv_tx,
+        FileCommand::DeleteBranch {
+            file_id,
+            branch,
+            tx,
+        },
+    )
+    .await?;
+    rx.await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(file_command_error)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize)]
+pub struct RevisionsQuery {
+    /// Revision to list from, exclusive. 0 by default.
+    after: Option<RevisionNumber>,
+    limit: Option<usize>,
+}
+
+/// Lists a page of revisions, oldest first, without their transactions. The next page is after
+/// the last revision of the page.
+async fn get_revisions(
+    path: web::Path<FileParam>,
+    query: web::Query<RevisionsQuery>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let file_id = path.file_id()?;
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<Vec<Revision>, FileCommandError>>();
+    send_file_command(
+        &srv_tx,
+        FileCommand::ListRevisions {
+            file_id,
+            after: query.after.unwrap_or(0),
+            limit: query
+                .limit
+                .unwrap_or(DEFAULT_REVISIONS_LIMIT)
+                .min(MAX_REVISIONS_LIMIT),
+            tx,
+        },
+    )
+    .await?;
+    let revisions = rx
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(file_command_error)?;
+    let revisions = revisions
+        .iter()
+        .map(|revision| {
+            json!({
+                "revision": revision.number,
+                "timestamp": revision.timestamp,
+                "author": revision.author,
+                "transactionId": revision.transaction.id.to_string(),
+                "mutations": revision.transaction.items.len(),
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(revisions))
+}
+
+#[derive(Deserialize)]
+pub struct RevisionParam {
+    file_id: String,
+    revision: RevisionNumber,
+}
+
+impl RevisionParam {
+    fn file_id(&self) -> Result<FileId, actix_web::error::Error> {
+        self.file_id
+            .parse::<FileId>()
+            .map_err(|_| error::ErrorBadRequest("invalid format"))
+    }
+}
+
+async fn get_revision_json(
+    path: web::Path<RevisionParam>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<Document, FileCommandError>>();
+    send_file_command(
+        &srv_tx,
+        FileCommand::GetRevisionDocument {
+            file_id: path.file_id()?,
+            revision: path.revision,
+            tx,
+        },
+    )
+    .await?;
+    let document = rx
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(file_command_error)?;
+    let json = serde_json::to_string_pretty(&DocumentJson::from(&document))
+        .map_err(|_| error::ErrorInternalServerError("Internal Server Error"))?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(json))
+}
+
+/// Brings the document back to the revision with a new revision.
+async fn post_revision_restore(
+    path: web::Path<RevisionParam>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<RevisionNumber, FileCommandError>>();
+    send_file_command(
+        &srv_tx,
+        FileCommand::RestoreRevision {
+            file_id: path.file_id()?,
+            revision: path.revision,
+            tx,
+        },
+    )
+    .await?;
+    let revision = rx
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(file_command_error)?;
+    Ok(HttpResponse::Ok().json(json!({ "revision": revision })))
+}
+
+fn checkpoint_json(checkpoint: &Checkpoint) -> serde_json::Value {
+    json!({
+        "id": checkpoint.id.to_string(),
+        "label": checkpoint.label,
+        "timestamp": checkpoint.timestamp,
+        "author": checkpoint.author,
+    })
+}
+
+/// Lists checkpoints, oldest first, without their snapshots.
+async fn get_checkpoints(
+    path: web::Path<FileParam>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let file_id = path.file_id()?;
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<Vec<Checkpoint>, FileCommandError>>();
+    send_file_command(&srv_tx, FileCommand::ListCheckpoints { file_id, tx }).await?;
+    let checkpoints = rx
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(file_command_error)?;
+    Ok(HttpResponse::Ok().json(checkpoints.iter().map(checkpoint_json).collect::<Vec<_>>()))
+}
+
+#[derive(Deserialize)]
+pub struct CreateCheckpointBody {
+    label: String,
+    author: String,
+}
+
+async fn post_checkpoint(
+    path: web::Path<FileParam>,
+    body: web::Json<CreateCheckpointBody>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let file_id = path.file_id()?;
+    let CreateCheckpointBody { label, author } = body.into_inner();
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<Checkpoint, FileCommandError>>();
+    send_file_command(
+        &srv_tx,
+        FileCommand::CreateCheckpoint {
+            file_id,
+            label,
+            author,
+            tx,
+        },
+    )
+    .await?;
+    let checkpoint = rx
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(file_command_error)?;
+    Ok(HttpResponse::Ok().json(checkpoint_json(&checkpoint)))
+}
+
+#[derive(Deserialize)]
+pub struct CheckpointParam {
+    file_id: String,
+    checkpoint_id: String,
+}
+
+impl CheckpointParam {
+    fn ids(&self) -> Result<(FileId, CheckpointId), actix_web::error::Error> {
+        let file_id = self
+            .file_id
+            .parse::<FileId>()
+            .map_err(|_| error::ErrorBadRequest("invalid format"))?;
+        let checkpoint_id = self
+            .checkpoint_id
+            .parse::<CheckpointId>()
+            .map_err(|_| error::ErrorBadRequest("invalid format"))?;
+        Ok((file_id, checkpoint_id))
+    }
+}
+
+/// Downloads the document of the checkpoint as JSON.
+async fn get_checkpoint(
+    path: web::Path<CheckpointParam>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let (file_id, checkpoint_id) = path.ids()?;
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<Checkpoint, FileCommandError>>();
+    send_file_command(
+        &srv_tx,
+        FileCommand::GetCheckpoint {
+            file_id,
+            checkpoint_id,
+            tx,
+        },
+    )
+    .await?;
+    let checkpoint = rx
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(file_command_error)?;
+    let document = Document::try_from(&checkpoint.snapshot)
+        .map_err(|_| error::ErrorInternalServerError("Cannot read checkpoint"))?;
+    let json = serde_json::to_string_pretty(&DocumentJson::from(&document))
+        .map_err(|_| error::ErrorInternalServerError("Internal Server Error"))?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .header(
+            "Content-Disposition",
+            format!(r#"attachment; filename="{}.json""#, checkpoint_id),
+        )
+        .body(json))
+}
+
+async fn post_checkpoint_restore(
+    path: web::Path<CheckpointParam>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let (file_id, checkpoint_id) = path.ids()?;
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<RevisionNumber, FileCommandError>>();
+    send_file_command(
+        &srv_tx,
+        FileCommand::RestoreCheckpoint {
+            file_id,
+            checkpoint_id,
+            tx,
+        },
+    )
+    .await?;
+    let revision = rx
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(file_command_error)?;
+    Ok(HttpResponse::Ok().json(json!({ "revision": revision })))
+}
+
+/// Finds objects by the `ObjectQuery` in the body. Returns their ids in document order.
+async fn post_query(
+    path: web::Path<FileParam>,
+    body: web::Bytes,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let query = serde_json::from_slice::<ObjectQuery>(&body)
+        .map_err(|err| error::ErrorBadRequest(err.to_string()))?;
+    let document = get_document(&srv_tx, path.file_id()?).await?;
+    let object_ids = query_objects(&document, &query)
+        .map_err(|err| error::ErrorBadRequest(format!("{:?}", err)))?;
+    Ok(HttpResponse::Ok().json(object_ids))
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    /// Exports only this frame if given.
+    frame: Option<ObjectId>,
+}
+
+async fn get_svg(
+    path: web::Path<FileParam>,
+    query: web::Query<ExportQuery>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let document = get_document(&srv_tx, path.file_id()?).await?;
+    let object_id = query.frame.unwrap_or_else(|| document.document_id());
+    let svg =
+        export_svg(&document, &object_id).map_err(|_| error::ErrorNotFound("No such object"))?;
+    Ok(HttpResponse::Ok().content_type("image/svg+xml").body(svg))
+}
+
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    /// Imports into the document if not given.
+    parent: Option<ObjectId>,
+}
+
+async fn post_svg(
+    path: web::Path<FileParam>,
+    query: web::Query<ImportQuery>,
+    body: web::Bytes,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let file_id = path.file_id()?;
+    let svg = std::str::from_utf8(&body).map_err(|_| error::ErrorBadRequest("Not UTF-8"))?;
+    let document = get_document(&srv_tx, file_id).await?;
+    let parent_id = query.parent.unwrap_or_else(|| document.document_id());
+    let SvgImport {
+        transaction,
+        warnings,
+    } = import_svg(&document, &parent_id, svg).map_err(|err| match err {
+        SvgImportError::ParentNotFound => error::ErrorNotFound("No such parent"),
+        _ => error::ErrorBadRequest(format!("{:?}", err)),
+    })?;
+
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<(), FileCommandError>>();
+    send_file_command(
+        &srv_tx,
+        FileCommand::ApplyTransaction {
+            file_id,
+            transaction,
+            tx,
+        },
+    )
+    .await?;
+    rx.await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(file_command_error)?;
+
+    Ok(HttpResponse::Ok().json(json!({ "warnings": warnings })))
+}
+
+async fn get_pdf(
+    path: web::Path<FileParam>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let file_id = path.file_id()?;
+    let document = get_document(&srv_tx, file_id).await?;
+    let pdf = export_pdf(&document).map_err(|_| error::ErrorNotFound("No frames to export"))?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/pdf")
+        .header(
+            "Content-Disposition",
+            format!(r#"attachment; filename="{}.pdf""#, file_id),
+        )
+        .body(pdf))
+}
+
+#[derive(Deserialize)]
+pub struct PngQuery {
+    frame: Option<ObjectId>,
+    /// Pixels per unit of the document. 1 by default.
+    scale: Option<f32>,
+}
+
+async fn get_png(
+    path: web::Path<FileParam>,
+    query: web::Query<PngQuery>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let document = get_document(&srv_tx, path.file_id()?).await?;
+    let object_id = query.frame.unwrap_or_else(|| document.document_id());
+    let scale = query.scale.unwrap_or(1.0);
+    let png = web::block(move || raster::render_png(&document, &object_id, scale))
+        .await
+        .map_err(render_error)?;
+    Ok(HttpResponse::Ok().content_type("image/png").body(png))
+}
+
+#[derive(Deserialize)]
+pub struct ThumbnailQuery {
+    frame: Option<ObjectId>,
+    /// Length of the longer side in pixels
+    size: Option<u32>,
+}
+
+async fn get_thumbnail(
+    path: web::Path<FileParam>,
+    query: web::Query<ThumbnailQuery>,
+    srv_tx: web::Data<ServerTx>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let document = get_document(&srv_tx, path.file_id()?).await?;
+    let object_id = query.frame.unwrap_or_else(|| document.document_id());
+    let size = query
+        .size
+        .unwrap_or(DEFAULT_THUMBNAIL_SIZE)
+        .min(MAX_THUMBNAIL_SIZE);
+    let png = web::block(move || raster::render_thumbnail_png(&document, &object_id, size))
+        .await
+        .map_err(render_error)?;
+    Ok(HttpResponse::Ok().content_type("image/png").body(png))
+}
+
+fn render_error(err: BlockingError<RenderError>) -> actix_web::error::Error {
+    match err {
+        BlockingError::Error(RenderError::ObjectNotFound) => error::ErrorNotFound("No such object"),
+        BlockingError::Error(RenderError::InvalidScale) => error::ErrorBadRequest("Invalid scale"),
+        BlockingError::Error(RenderError::TooLarge) => error::ErrorBadRequest("Image is too large"),
+        BlockingError::Error(RenderError::Encoding(_)) | BlockingError::Canceled => {
+            error::ErrorInternalServerError("Cannot render image")
+        }
+    }
+}
+
+async fn get_document(
+    srv_tx: &web::Data<ServerTx>,
+    file_id: FileId,
+) -> Result<Document, actix_web::error::Error> {
+    let (tx, rx) = tokio::sync::oneshot::channel::<Result<Document, FileCommandError>>();
+    send_file_command(srv_tx, FileCommand::GetDocument { file_id, tx }).await?;
+    rx.await
+        .map_err(|_| error::ErrorInternalServerError("Receiver await error"))?
+        .map_err(file_command_error)
+}
+
+async fn send_file_command(
+    srv_tx: &web::Data<ServerTx>,
+    command: FileCommand,
+) -> Result<(), actix_web::error::Error> {
+    srv_tx
+        .get_ref()
+        .clone()
+        .send(ServerCommand::FileCommand(command))
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Internal Server Error"))
+}
+
+fn file_command_error(err: FileCommandError) -> actix_web::error::Error {
+    match err {
+        FileCommandError::DocumentFile(DocumentFileError::Storage(StorageError::NotFound)) => {
+            error::ErrorNotFound("No such file")
+        }
+        FileCommandError::DocumentFile(DocumentFileError::Storage(_)) => {
+            error::ErrorInternalServerError("Cannot access file")
+        }
+        FileCommandError::DocumentFile(DocumentFileError::Snapshot(_))
+        | FileCommandError::DocumentFile(DocumentFileError::Decode(..)) => {
+            error::ErrorInternalServerError("Cannot read file")
+        }
+        FileCommandError::DocumentFile(DocumentFileError::InvalidBranchName(_)) => {
+            error::ErrorBadRequest("Invalid branch name")
+        }
+        FileCommandError::DocumentFile(DocumentFileError::InvalidFileName(_)) => {
+            error::ErrorInternalServerError("Cannot read file")
+        }
+        FileCommandError::SessionInProgress => {
+            error::ErrorConflict("File is being edited in a session")
+        }
+        FileCommandError::InvalidMutation(_) => {
+            error::ErrorConflict("Changes cannot be applied to the document")
+        }
+        FileCommandError::BranchAlreadyExists => error::ErrorConflict("Branch already exists"),
+        FileCommandError::Revision(RevisionError::NotFound(_)) => {
+            error::ErrorNotFound("No such revision")
+        }
+        FileCommandError::Revision(_) => error::ErrorInternalServerError("Cannot read revision"),
+        FileCommandError::CheckpointNotFound => error::ErrorNotFound("No such checkpoint"),
+        FileCommandError::DocumentMismatch => error::ErrorConflict("File holds another document"),
+    }
+}
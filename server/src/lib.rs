@@ -1,10 +1,16 @@
 pub extern crate actix_web;
 
 mod admin;
+mod checkpoint;
 pub mod connection;
 mod connection_tx_storage;
 mod document_file;
+mod file_command;
 pub mod handlers;
+mod recovery;
+mod revision_history;
 pub mod server;
 mod server_state;
 mod session;
+pub mod storage;
+mod transaction_log;
@@ -1,12 +1,23 @@
 use actix_web::{App, HttpServer};
 use server::handlers::root;
 use server::server::spawn_server;
+use server::storage::{FsStorage, SqliteStorage};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
 
-    let srv_tx = spawn_server();
+    // Documents are kept in the SQLite database at `DATABASE_PATH` if given, or as files in the
+    // working directory otherwise.
+    let srv_tx = match std::env::var("DATABASE_PATH") {
+        Ok(path) => {
+            let storage = SqliteStorage::open(&path).map_err(|err| {
+                std::io::Error::other(format!("Cannot open database {}: {:?}", path, err))
+            })?;
+            spawn_server(storage)
+        }
+        Err(_) => spawn_server(FsStorage::new(std::env::current_dir()?)),
+    };
 
     HttpServer::new(move || {
         App::new()
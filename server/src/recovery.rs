@@ -0,0 +1,118 @@
+use crate::storage::{RecordKind, Storage};
+use crate::transaction_log::{read_transaction_log, recover_document};
+
+/// What was done at startup for a file whose last session didn't end cleanly.
+#[derive(Debug, Clone)]
+pub struct RecoveryReport {
+    pub target: String,
+    pub message: String,
+}
+
+/// Finds what a server which stopped in the middle of a session or a write left, and recovers
+/// it. Incomplete writes are cleaned up by the storage. Documents with a transaction log are
+/// rebuilt from their snapshot and the log.
+pub async fn recover_document_files<S: Storage>(storage: &S) -> Vec<RecoveryReport> {
+    let mut result = Vec::new();
+
+    match storage.recover_incomplete_writes().await {
+        Ok(messages) => {
+            result.extend(messages.into_iter().map(|message| RecoveryReport {
+                target: "storage".into(),
+                message,
+            }));
+        }
+        Err(err) => result.push(RecoveryReport {
+            target: "storage".into(),
+            message: format!("Cannot recover incomplete writes: {:?}", err),
+        }),
+    }
+
+    let keys = match storage.list().await {
+        Ok(keys) => keys,
+        Err(err) => {
+            log::error!("Cannot list documents to recover: {:?}", err);
+            Vec::new()
+        }
+    };
+    for key in keys.into_iter().filter(|key| key.kind == RecordKind::Log) {
+        let document_ref = key.document_ref;
+        let logged_count = read_transaction_log(storage, &document_ref)
+            .await
+            .map_or(0, |logged| logged.len());
+        let message = match recover_document(storage, &document_ref).await {
+            Ok((_, history)) => format!(
+                "Rebuilt with {} logged transactions, up to revision {}",
+                logged_count,
+                history.latest_number()
+            ),
+            Err(err) => format!("Cannot rebuild: {:?}", err),
+        };
+        result.push(RecoveryReport {
+            target: document_ref.to_string(),
+            message,
+        });
+    }
+
+    for report in &result {
+        log::warn!("Recovery of {}: {}", report.target, report.message);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document_file::{read_document_file, DocumentRef};
+    use crate::revision_history::RevisionHistory;
+    use crate::storage::FsStorage;
+    use crate::transaction_log::{append_transaction_log, compact_document};
+    use system::uuid::Uuid;
+    use system::{Document, DocumentMutation, PropKind, PropReadable, PropValue, Transaction};
+
+    #[tokio::test]
+    async fn it_should_recover_leftover_log_and_temp_file() {
+        let root = std::env::temp_dir().join(format!("recovery-test-{}", Uuid::new_v4()));
+        std::fs::create_dir(&root).expect("should create directory");
+        let storage = FsStorage::new(&root);
+        let document_ref = DocumentRef::main(Uuid::new_v4());
+
+        let mut document = Document::new();
+        let mut history = RevisionHistory::new(&document);
+        compact_document(&storage, &document_ref, &document, &mut history)
+            .await
+            .expect("should compact");
+        let transaction = Transaction::new(vec![DocumentMutation::UpsertProp(
+            document.document_id(),
+            PropKind::Name,
+            Some(PropValue::String("logged".into())),
+        )]);
+        document.process(transaction.clone()).expect("should work");
+        history.record(transaction, None);
+        let revision = history.unsaved().last().expect("just recorded");
+        append_transaction_log(&storage, &document_ref, revision)
+            .await
+            .expect("should append");
+        let temp_path = root.join(format!("{}.rcs.tmp", document_ref));
+        std::fs::write(&temp_path, b"half").expect("should write");
+
+        let reports = recover_document_files(&storage).await;
+        assert_eq!(reports.len(), 2, "{:?}", reports);
+        assert_eq!(reports[0].target, "storage");
+        assert_eq!(reports[1].target, document_ref.to_string());
+        assert_eq!(
+            reports[1].message,
+            "Rebuilt with 1 logged transactions, up to revision 1"
+        );
+        assert!(!temp_path.exists());
+        assert!(!root.join(format!("{}.rcs.log", document_ref)).exists());
+
+        let rebuilt = read_document_file(&storage, &document_ref)
+            .await
+            .expect("should read");
+        assert_eq!(
+            rebuilt.get_string_prop(&rebuilt.document_id(), &PropKind::Name),
+            Some("logged")
+        );
+        std::fs::remove_dir_all(&root).expect("should remove directory");
+    }
+}
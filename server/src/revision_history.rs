@@ -0,0 +1,503 @@
+use crate::document_file::{DocumentFileError, DocumentRef};
+use crate::storage::{RecordKey, RecordKind, RecordOp, Storage, StorageError};
+use serde::{Deserialize, Serialize};
+use std::convert::{TryFrom, TryInto};
+use std::time::{SystemTime, UNIX_EPOCH};
+use system::{
+    bincode, ConnectionId, Document, DocumentReadable, DocumentSnapshot, MutationError,
+    SnapshotError, Transaction,
+};
+
+pub type RevisionNumber = u64;
+
+/// Revisions are stored in chunks of this many, each with a snapshot of the document at its
+/// start, so that reading a revision replays at most a chunk.
+pub const REVISIONS_PER_CHUNK: RevisionNumber = 100;
+
+/// Committed transaction of a document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Revision {
+    pub number: RevisionNumber,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    /// Connection which sent the transaction. `None` if it is made by the server, e.g. through
+    /// the REST API. Connection ids are numbered per process and start over when the server
+    /// restarts, so they only tell apart authors of revisions made in the same run. There are no
+    /// user accounts yet to record instead.
+    pub author: Option<ConnectionId>,
+    pub transaction: Transaction,
+}
+
+/// Committed transactions of a document. Revision 0 is the base of the history, and each
+/// transaction makes the next revision.
+///
+/// Only revisions which are not saved yet are kept in memory. Saved ones are read from storage,
+/// where they are appended to chunks of `REVISIONS_PER_CHUNK` revisions, next to a snapshot of
+/// the document at the start of each chunk.
+#[derive(Debug, Clone)]
+pub struct RevisionHistory {
+    /// Latest revision in storage
+    saved: RevisionNumber,
+    /// Revision 0 until it is saved, i.e. of a new history
+    base: Option<DocumentSnapshot>,
+    /// Revisions after `saved`
+    unsaved: Vec<Revision>,
+}
+
+#[derive(Debug)]
+pub enum RevisionError {
+    NotFound(RevisionNumber),
+    Snapshot(SnapshotError),
+    InvalidMutation(MutationError),
+    /// Revisions or snapshots cannot be read from storage
+    DocumentFile(DocumentFileError),
+}
+
+impl RevisionHistory {
+    pub fn new(base: &Document) -> Self {
+        Self {
+            saved: 0,
+            base: Some(base.snapshot()),
+            unsaved: Vec::new(),
+        }
+    }
+
+    /// Revisions which are not saved yet, oldest first.
+    pub fn unsaved(&self) -> &[Revision] {
+        &self.unsaved
+    }
+
+    pub fn latest_number(&self) -> RevisionNumber {
+        self.unsaved
+            .last()
+            .map_or(self.saved, |revision| revision.number)
+    }
+
+    pub fn record(
+        &mut self,
+        transaction: Transaction,
+        author: Option<ConnectionId>,
+    ) -> RevisionNumber {
+        let number = self.latest_number() + 1;
+        self.unsaved.push(Revision {
+            number,
+            timestamp: now_millis(),
+            author,
+            transaction,
+        });
+        number
+    }
+
+    /// Forgets the unsaved revisions once the changes of `revision_history_ops` are applied.
+    pub fn mark_saved(&mut self) {
+        self.saved = self.latest_number();
+        self.base = None;
+        self.unsaved.clear();
+    }
+
+    /// Appends a revision recorded elsewhere, e.g. in the transaction log.
+    pub fn push(&mut self, revision: Revision) {
+        debug_assert_eq!(revision.number, self.latest_number() + 1);
+        self.unsaved.push(revision);
+    }
+}
+
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+/// Chunk which has the revision. Revision 0 is in none, as it is the snapshot of chunk 0.
+fn chunk_of(number: RevisionNumber) -> u64 {
+    (number - 1) / REVISIONS_PER_CHUNK
+}
+
+// Revisions are stored one after another. Each is the length of the bincode dump of the
+// `Revision` in 4 bytes of little endian, followed by the dump.
+
+const LENGTH_LEN: usize = 4;
+
+pub(crate) fn encode_revision(revision: &Revision) -> Vec<u8> {
+    let content = bincode::serialize(revision).expect("must succeed");
+    let mut record = (content.len() as u32).to_le_bytes().to_vec();
+    record.extend(content);
+    record
+}
+
+/// Revisions read from a record of them.
+pub(crate) struct DecodedRevisions {
+    pub revisions: Vec<Revision>,
+    /// Whether the record ends with a revision cut short, e.g. by a stop of the server while it
+    /// was appended
+    pub torn: bool,
+    /// Error of a whole revision which cannot be read. Nothing after it is read.
+    pub error: Option<bincode::Error>,
+}
+
+pub(crate) fn decode_revisions(content: &[u8]) -> DecodedRevisions {
+    let mut result = DecodedRevisions {
+        revisions: Vec::new(),
+        torn: false,
+        error: None,
+    };
+    let mut rest = content;
+    while !rest.is_empty() {
+        if rest.len() < LENGTH_LEN {
+            result.torn = true;
+            break;
+        }
+        let (length, body) = rest.split_at(LENGTH_LEN);
+        let length = u32::from_le_bytes(length.try_into().expect("must be 4 bytes")) as usize;
+        if body.len() < length {
+            result.torn = true;
+            break;
+        }
+        match bincode::deserialize::<Revision>(&body[..length]) {
+            Ok(revision) => result.revisions.push(revision),
+            Err(err) => {
+                result.error = Some(err);
+                break;
+            }
+        }
+        rest = &body[length..];
+    }
+    result
+}
+
+/// Reads the history of the document, i.e. which revision is the latest saved one. A document
+/// without history starts one with `document` as its base. A history which cannot be read is an
+/// error rather than a new history, which would replace it when the document is saved.
+pub async fn read_revision_history<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+    document: &Document,
+) -> Result<RevisionHistory, DocumentFileError> {
+    match storage
+        .read(&RecordKey::new(document_ref, RecordKind::History))
+        .await
+    {
+        Ok(content) => {
+            let saved = bincode::deserialize::<RevisionNumber>(&content)
+                .map_err(|err| DocumentFileError::Decode(RecordKind::History, err))?;
+            Ok(RevisionHistory {
+                saved,
+                base: None,
+                unsaved: Vec::new(),
+            })
+        }
+        Err(StorageError::NotFound) => Ok(RevisionHistory::new(document)),
+        Err(err) => Err(DocumentFileError::Storage(err)),
+    }
+}
+
+/// Reads the saved revisions of the chunk, up to `saved`, and whether the chunk ends with a
+/// revision cut short. A revision replaces those with the same or later numbers before it, which
+/// a stop of the server in the middle of saving left behind.
+async fn read_chunk<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+    chunk: u64,
+    saved: RevisionNumber,
+) -> Result<(Vec<Revision>, bool), DocumentFileError> {
+    let kind = RecordKind::Revisions(chunk);
+    let content = match storage.read(&RecordKey::new(document_ref, kind)).await {
+        Ok(content) => content,
+        Err(StorageError::NotFound) => return Ok((Vec::new(), false)),
+        Err(err) => return Err(DocumentFileError::Storage(err)),
+    };
+    let decoded = decode_revisions(&content);
+    if let Some(err) = decoded.error {
+        return Err(DocumentFileError::Decode(kind, err));
+    }
+    let mut result = Vec::<Revision>::new();
+    for revision in decoded.revisions.into_iter().filter(|r| r.number <= saved) {
+        let kept = result
+            .iter()
+            .take_while(|r| r.number < revision.number)
+            .count();
+        result.truncate(kept);
+        result.push(revision);
+    }
+    Ok((result, decoded.torn))
+}
+
+async fn read_revision_snapshot<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+    chunk: u64,
+) -> Result<DocumentSnapshot, DocumentFileError> {
+    let content = storage
+        .read(&RecordKey::new(
+            document_ref,
+            RecordKind::RevisionSnapshot(chunk),
+        ))
+        .await
+        .map_err(DocumentFileError::Storage)?;
+    let snapshot = DocumentSnapshot::from_vec(content);
+    snapshot.verify().map_err(DocumentFileError::Snapshot)?;
+    Ok(snapshot)
+}
+
+/// Returns at most `limit` revisions after the revision `after`, oldest first. Only the chunks
+/// which have them are read.
+pub async fn read_revisions<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+    history: &RevisionHistory,
+    after: RevisionNumber,
+    limit: usize,
+) -> Result<Vec<Revision>, DocumentFileError> {
+    let mut result = Vec::new();
+    let mut chunk = chunk_of(after + 1);
+    while chunk * REVISIONS_PER_CHUNK < history.saved && result.len() < limit {
+        let (revisions, _) = read_chunk(storage, document_ref, chunk, history.saved).await?;
+        let remaining = limit - result.len();
+        result.extend(
+            revisions
+                .into_iter()
+                .filter(|r| r.number > after)
+                .take(remaining),
+        );
+        chunk += 1;
+    }
+    let remaining = limit - result.len();
+    result.extend(
+        history
+            .unsaved
+            .iter()
+            .filter(|r| r.number > after)
+            .take(remaining)
+            .cloned(),
+    );
+    Ok(result)
+}
+
+/// Rebuilds the document at the revision from the latest snapshot before it, replaying at most
+/// a chunk of saved revisions.
+pub async fn read_document_at<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+    history: &RevisionHistory,
+    number: RevisionNumber,
+) -> Result<Document, RevisionError> {
+    if number > history.latest_number() {
+        return Err(RevisionError::NotFound(number));
+    }
+    let chunk = number.min(history.saved) / REVISIONS_PER_CHUNK;
+    let mut document = match &history.base {
+        Some(base) => Document::try_from(base),
+        None => {
+            let snapshot = read_revision_snapshot(storage, document_ref, chunk)
+                .await
+                .map_err(RevisionError::DocumentFile)?;
+            Document::try_from(&snapshot)
+        }
+    }
+    .map_err(RevisionError::Snapshot)?;
+
+    let start = chunk * REVISIONS_PER_CHUNK;
+    let revisions = read_revisions(
+        storage,
+        document_ref,
+        history,
+        start,
+        (number - start) as usize,
+    )
+    .await
+    .map_err(RevisionError::DocumentFile)?;
+    if revisions.last().map_or(start, |r| r.number) != number {
+        return Err(RevisionError::NotFound(number));
+    }
+    for revision in revisions {
+        document
+            .process(revision.transaction)
+            .map_err(RevisionError::InvalidMutation)?;
+    }
+    Ok(document)
+}
+
+/// Changes which save the revisions which are not saved yet. The latest saved revision is
+/// written last, so that whatever a partly applied batch leaves before it is ignored. Once they
+/// are applied, the history is to be marked as saved.
+pub async fn revision_history_ops<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+    history: &RevisionHistory,
+) -> Result<Vec<RecordOp>, DocumentFileError> {
+    let mut ops = Vec::new();
+    if let Some(base) = &history.base {
+        ops.push(RecordOp::Write(
+            RecordKey::new(document_ref, RecordKind::RevisionSnapshot(0)),
+            base.content().to_vec(),
+        ));
+    }
+    for revisions in history
+        .unsaved
+        .chunk_by(|a, b| chunk_of(a.number) == chunk_of(b.number))
+    {
+        ops.push(chunk_op(storage, document_ref, history.saved, revisions).await?);
+    }
+    ops.extend(revision_snapshot_ops(storage, document_ref, history).await?);
+    ops.push(RecordOp::Write(
+        RecordKey::new(document_ref, RecordKind::History),
+        bincode::serialize(&history.latest_number()).expect("must succeed"),
+    ));
+    Ok(ops)
+}
+
+/// Appends the revisions, which are all in one chunk, to the chunk. A chunk without saved
+/// revisions, or with a revision cut short at its end, is written anew instead.
+async fn chunk_op<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+    saved: RevisionNumber,
+    revisions: &[Revision],
+) -> Result<RecordOp, DocumentFileError> {
+    let chunk = chunk_of(revisions[0].number);
+    let key = RecordKey::new(document_ref, RecordKind::Revisions(chunk));
+    let (mut content, append) = if saved > chunk * REVISIONS_PER_CHUNK {
+        match read_chunk(storage, document_ref, chunk, saved).await? {
+            (stored, true) => (stored.iter().flat_map(encode_revision).collect(), false),
+            (_, false) => (Vec::new(), true),
+        }
+    } else {
+        (Vec::new(), false)
+    };
+    content.extend(revisions.iter().flat_map(encode_revision));
+    Ok(if append {
+        RecordOp::Append(key, content)
+    } else {
+        RecordOp::Write(key, content)
+    })
+}
+
+/// Writes snapshots of the document at the start of the chunks which unsaved revisions begin.
+/// Revisions which cannot be replayed leave the rest without snapshots, so that the revisions
+/// before them can still be saved.
+async fn revision_snapshot_ops<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+    history: &RevisionHistory,
+) -> Result<Vec<RecordOp>, DocumentFileError> {
+    let mut ops = Vec::new();
+    let first_chunk = history.saved / REVISIONS_PER_CHUNK;
+    if history.latest_number() / REVISIONS_PER_CHUNK == first_chunk {
+        return Ok(ops);
+    }
+    let start = first_chunk * REVISIONS_PER_CHUNK;
+    let mut document = match read_document_at(storage, document_ref, history, start).await {
+        Ok(document) => document,
+        Err(RevisionError::DocumentFile(err)) => return Err(err),
+        Err(err) => {
+            log::error!(
+                "Cannot take snapshots of the history of {}: {:?}",
+                document_ref,
+                err
+            );
+            return Ok(ops);
+        }
+    };
+    let revisions = read_revisions(storage, document_ref, history, start, usize::MAX).await?;
+    for revision in revisions {
+        let number = revision.number;
+        if let Err(err) = document.process(revision.transaction) {
+            log::error!(
+                "Cannot take snapshots of the history of {} after revision {}: {:?}",
+                document_ref,
+                number,
+                err
+            );
+            break;
+        }
+        if number % REVISIONS_PER_CHUNK == 0 {
+            ops.push(RecordOp::Write(
+                RecordKey::new(
+                    document_ref,
+                    RecordKind::RevisionSnapshot(number / REVISIONS_PER_CHUNK),
+                ),
+                document.snapshot().content().to_vec(),
+            ));
+        }
+    }
+    Ok(ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use system::uuid::Uuid;
+    use system::{DocumentMutation, PropKind, PropReadable, PropValue};
+
+    fn rename(document: &mut Document, history: &mut RevisionHistory, name: &str) {
+        let transaction = Transaction::new(vec![DocumentMutation::UpsertProp(
+            document.document_id(),
+            PropKind::Name,
+            Some(PropValue::String(name.into())),
+        )]);
+        document.process(transaction.clone()).expect("should work");
+        history.record(transaction, None);
+    }
+
+    fn name_of(document: &Document) -> Option<&str> {
+        document.get_string_prop(&document.document_id(), &PropKind::Name)
+    }
+
+    async fn save(
+        storage: &MemoryStorage,
+        document_ref: &DocumentRef,
+        history: &mut RevisionHistory,
+    ) {
+        let ops = revision_history_ops(storage, document_ref, history)
+            .await
+            .expect("should prepare");
+        storage.write_batch(&ops).await.expect("should write");
+        history.mark_saved();
+    }
+
+    #[tokio::test]
+    async fn it_should_read_revisions_from_chunks_and_snapshots() {
+        let storage = MemoryStorage::new();
+        let document_ref = DocumentRef::main(Uuid::new_v4());
+        let mut document = Document::new();
+        let mut history = RevisionHistory::new(&document);
+        for i in 1..=250 {
+            rename(&mut document, &mut history, &i.to_string());
+            if i == 120 || i == 250 {
+                save(&storage, &document_ref, &mut history).await;
+            }
+        }
+        assert!(history.unsaved().is_empty());
+        for chunk in 0..=2 {
+            let key = RecordKey::new(&document_ref, RecordKind::RevisionSnapshot(chunk));
+            assert!(storage.metadata(&key).await.is_ok(), "chunk {}", chunk);
+        }
+
+        let history = read_revision_history(&storage, &document_ref, &document)
+            .await
+            .expect("should read");
+        assert_eq!(history.latest_number(), 250);
+        let revisions = read_revisions(&storage, &document_ref, &history, 95, 10)
+            .await
+            .expect("should read");
+        assert_eq!(
+            revisions.iter().map(|r| r.number).collect::<Vec<_>>(),
+            (96..=105).collect::<Vec<_>>()
+        );
+        for number in &[0, 100, 150, 250] {
+            let document = read_document_at(&storage, &document_ref, &history, *number)
+                .await
+                .expect("should rebuild");
+            let expected = number.to_string();
+            assert_eq!(
+                name_of(&document),
+                Some(expected.as_str()).filter(|_| *number > 0)
+            );
+        }
+        assert!(matches!(
+            read_document_at(&storage, &document_ref, &history, 251).await,
+            Err(RevisionError::NotFound(251))
+        ));
+    }
+}
@@ -1,47 +1,82 @@
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::{channel, Sender};
 
 use system::{
-    CommandResult, ConnectionId, FatalError, FileId, IdentifiableCommand, IdentifiableEvent,
-    LivePointerEvent, RollbackReason, SessionCommand, SessionError, SessionEvent, SessionId,
+    diff_documents, split_init_event, CommandResult, ConnectionId, Document, FatalError, FileId,
+    IdentifiableCommand, IdentifiableEvent, InitCompression, InitTransferOptions, LivePointerEvent,
+    RollbackReason, SessionCommand, SessionError, SessionEvent, SessionId, Transaction,
 };
 
 use super::connection::{ConnectionCommand, ConnectionEvent};
 use crate::admin::{AdminCommand, FileDescription};
+use crate::checkpoint::{
+    add_checkpoint, read_checkpoint, read_checkpoints, Checkpoint, CheckpointId,
+};
 use crate::connection_tx_storage::{ConnectionTx, ConnectionTxStorage};
-use crate::document_file::{read_document_file, write_document_file};
+use crate::document_file::{
+    delete_branch, get_document_file_meta, list_branches, list_document_files, write_document_file,
+    BranchName, DocumentFileError, DocumentRef,
+};
+use crate::file_command::{FileCommand, FileCommandError};
+use crate::recovery::{recover_document_files, RecoveryReport};
+use crate::revision_history::{read_document_at, read_revisions, RevisionHistory, RevisionNumber};
 use crate::server_state::ServerState;
 use crate::session::{
-    PendingTransactionCommitError, PendingTransactionCommitResult, SessionBehavior,
+    AutosavePolicy, PendingTransactionCommitError, PendingTransactionCommitResult, Session,
+    SessionBehavior,
+};
+use crate::storage::Storage;
+use crate::transaction_log::{
+    append_transaction_log, compact_document, open_document, recover_document,
 };
 
 pub type ServerTx = Sender<ServerCommand>;
 
+/// How often sessions are checked for autosave
+const AUTOSAVE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Debug)]
 pub enum ServerCommand {
     ConnectionCommand(ConnectionCommand),
     AdminCommand(AdminCommand),
+    FileCommand(FileCommand),
+    /// Saves the sessions which their `AutosavePolicy` says to.
+    Autosave,
 }
 
-struct Server {
+struct Server<S: Storage> {
+    storage: S,
     server_state: ServerState,
     connections: ConnectionTxStorage,
+    init_transfer: InitTransferOptions,
+    recovery_reports: Vec<RecoveryReport>,
+    autosave: AutosavePolicy,
 }
 
-impl Server {
-    fn new() -> Self {
+impl<S: Storage> Server<S> {
+    fn new(storage: S) -> Self {
         Self {
+            storage,
             server_state: ServerState::new(),
             connections: ConnectionTxStorage::new(),
+            init_transfer: init_transfer_from_env(),
+            recovery_reports: Vec::new(),
+            autosave: AutosavePolicy::from_env(),
         }
     }
 
     async fn handle_connection_command(&mut self, command: &ConnectionCommand) {
         match command {
-            ConnectionCommand::Connect { tx, file_id } => {
+            ConnectionCommand::Connect {
+                tx,
+                document_ref,
+                init_compression,
+            } => {
                 let mut tx = tx.clone();
 
                 if self
-                    .join_or_create_auto_commit_session(file_id, tx.clone())
+                    .join_or_create_auto_commit_session(document_ref, tx.clone(), *init_compression)
                     .await
                     .is_err()
                 {
@@ -92,9 +127,10 @@ impl Server {
     async fn handle_admin_command(&mut self, command: AdminCommand) {
         match command {
             AdminCommand::GetSessionState { file_id, tx } => {
+                let document_ref = DocumentRef::main(file_id);
                 if let Some(session) = self
                     .server_state
-                    .get_session_id_of_file(&file_id)
+                    .get_session_id_of_file(&document_ref)
                     .and_then(|session_id| self.server_state.get_session(session_id))
                 {
                     tx.send(Ok(FileDescription::Online {
@@ -104,16 +140,24 @@ impl Server {
                     }))
                     .expect("must success")
                 } else {
-                    let result = match read_document_file(&file_id).await {
-                        Ok(document) => Ok(FileDescription::Offline(format!("{:#?}", document))),
-                        Err(_) => Err(format!("No file with id {}", file_id)),
+                    let result = match open_document(&self.storage, &document_ref).await {
+                        Ok((document, _)) => {
+                            Ok(FileDescription::Offline(format!("{:#?}", document)))
+                        }
+                        Err(err) if err.is_not_found() => {
+                            Err(format!("No file with id {}", file_id))
+                        }
+                        Err(err) => Err(format!("Cannot read file with id {}: {:?}", file_id, err)),
                     };
                     tx.send(result).expect("must success")
                 }
             }
             AdminCommand::OpenManualCommitSession { file_id, tx } => {
                 match self
-                    .create_session(&file_id, SessionBehavior::ManualCommitByAdmin)
+                    .create_session(
+                        &DocumentRef::main(file_id),
+                        SessionBehavior::ManualCommitByAdmin,
+                    )
                     .await
                 {
                     Ok(session_id) => {
@@ -125,7 +169,11 @@ impl Server {
                 }
             }
             AdminCommand::CloseManualCommitSession { file_id, tx } => {
-                if let Some(session_id) = self.server_state.session_id(&file_id).cloned() {
+                if let Some(session_id) = self
+                    .server_state
+                    .session_id(&DocumentRef::main(file_id))
+                    .cloned()
+                {
                     self.terminate_session(&session_id).await;
                     tx.send(Ok(())).expect("must succeed");
                 }
@@ -135,7 +183,11 @@ impl Server {
                 tx: transmit,
             } => {
                 let is_valid_command: bool;
-                if let Some(session_id) = self.server_state.session_id(&file_id).cloned() {
+                if let Some(session_id) = self
+                    .server_state
+                    .session_id(&DocumentRef::main(file_id))
+                    .cloned()
+                {
                     if !self.server_state.has_session(&session_id) {
                         transmit.send(Err(())).expect("must succeed");
                         return;
@@ -160,15 +212,19 @@ impl Server {
                                 Some(&from),
                             )
                             .await;
+                            self.log_latest_revision(&session_id).await;
                             is_valid_command = true;
                         }
                         Err(err) => match err {
                             PendingTransactionCommitError::InvalidRequest => {
                                 is_valid_command = false;
                             }
-                            PendingTransactionCommitError::Rollback { from, tx_id } => {
-                                let session_event =
-                                    SessionEvent::TransactionNack(tx_id, RollbackReason::Something);
+                            PendingTransactionCommitError::Rollback { from, tx_id, error } => {
+                                log::warn!("Rolling back transaction {}: {:?}", tx_id, error);
+                                let session_event = SessionEvent::TransactionNack(
+                                    tx_id,
+                                    RollbackReason::InvalidMutation(error),
+                                );
                                 self.connections
                                     .send(
                                         &from,
@@ -194,9 +250,207 @@ impl Server {
                     transmit.send(Err(())).expect("must succeed");
                 }
             }
+            AdminCommand::GetRecoveryReports { tx } => {
+                tx.send(self.recovery_reports.clone())
+                    .expect("must succeed");
+            }
         };
     }
 
+    async fn handle_file_command(&mut self, command: FileCommand) {
+        match command {
+            FileCommand::CreateDocument { tx } => {
+                let file_id = system::uuid::Uuid::new_v4();
+                let result = write_document_file(
+                    &self.storage,
+                    &DocumentRef::main(file_id),
+                    &Document::new(),
+                )
+                .await
+                .map(|()| file_id)
+                .map_err(FileCommandError::DocumentFile);
+                tx.send(result).expect("must succeed");
+            }
+            FileCommand::ListDocuments { tx } => {
+                let result = list_document_files(&self.storage)
+                    .await
+                    .map_err(FileCommandError::DocumentFile);
+                tx.send(result).expect("must succeed");
+            }
+            FileCommand::GetDocumentMeta { document_ref, tx } => {
+                let result = get_document_file_meta(&self.storage, &document_ref)
+                    .await
+                    .map_err(FileCommandError::DocumentFile);
+                tx.send(result).expect("must succeed");
+            }
+            FileCommand::GetDocument { file_id, tx } => {
+                let result = self.latest_document(&DocumentRef::main(file_id)).await;
+                tx.send(result).expect("must succeed");
+            }
+            FileCommand::ReplaceDocument {
+                file_id,
+                document,
+                tx,
+            } => {
+                let document_ref = DocumentRef::main(file_id);
+                let result = if self
+                    .server_state
+                    .get_session_id_of_file(&document_ref)
+                    .is_some()
+                {
+                    Err(FileCommandError::SessionInProgress)
+                } else {
+                    replace_document_file(&self.storage, &document_ref, &document).await
+                };
+                tx.send(result).expect("must succeed");
+            }
+            FileCommand::ApplyTransaction {
+                file_id,
+                transaction,
+                tx,
+            } => {
+                let document_ref = DocumentRef::main(file_id);
+                let result = if self
+                    .server_state
+                    .get_session_id_of_file(&document_ref)
+                    .is_some()
+                {
+                    Err(FileCommandError::SessionInProgress)
+                } else {
+                    apply_to_document_file(&self.storage, &document_ref, transaction)
+                        .await
+                        .map(|_| ())
+                };
+                tx.send(result).expect("must succeed");
+            }
+            FileCommand::ForkBranch {
+                file_id,
+                from,
+                branch,
+                tx,
+            } => {
+                let result = self.fork_branch(file_id, from, branch).await;
+                tx.send(result).expect("must succeed");
+            }
+            FileCommand::ListBranches { file_id, tx } => {
+                let result = match get_document_file_meta(
+                    &self.storage,
+                    &DocumentRef::main(file_id),
+                )
+                .await
+                {
+                    Ok(_) => list_branches(&self.storage, &file_id).await,
+                    Err(error) => Err(error),
+                }
+                .map_err(FileCommandError::DocumentFile);
+                tx.send(result).expect("must succeed");
+            }
+            FileCommand::DeleteBranch {
+                file_id,
+                branch,
+                tx,
+            } => {
+                let result = match DocumentRef::branch(file_id, Some(branch)) {
+                    Ok(document_ref) => {
+                        if self
+                            .server_state
+                            .get_session_id_of_file(&document_ref)
+                            .is_some()
+                        {
+                            Err(FileCommandError::SessionInProgress)
+                        } else {
+                            delete_branch(&self.storage, &document_ref)
+                                .await
+                                .map_err(FileCommandError::DocumentFile)
+                        }
+                    }
+                    Err(error) => Err(FileCommandError::DocumentFile(error)),
+                };
+                tx.send(result).expect("must succeed");
+            }
+            FileCommand::ListRevisions {
+                file_id,
+                after,
+                limit,
+                tx,
+            } => {
+                let document_ref = DocumentRef::main(file_id);
+                let result = match self.latest_history(&document_ref).await {
+                    Ok(history) => {
+                        read_revisions(&self.storage, &document_ref, &history, after, limit)
+                            .await
+                            .map_err(FileCommandError::DocumentFile)
+                    }
+                    Err(error) => Err(error),
+                };
+                tx.send(result).expect("must succeed");
+            }
+            FileCommand::GetRevisionDocument {
+                file_id,
+                revision,
+                tx,
+            } => {
+                let result = self
+                    .revision_document(&DocumentRef::main(file_id), revision)
+                    .await;
+                tx.send(result).expect("must succeed");
+            }
+            FileCommand::RestoreRevision {
+                file_id,
+                revision,
+                tx,
+            } => {
+                let result = self
+                    .restore_revision(&DocumentRef::main(file_id), revision)
+                    .await;
+                tx.send(result).expect("must succeed");
+            }
+            FileCommand::CreateCheckpoint {
+                file_id,
+                label,
+                author,
+                tx,
+            } => {
+                let document_ref = DocumentRef::main(file_id);
+                let result = match self.latest_document(&document_ref).await {
+                    Ok(document) => {
+                        let checkpoint = Checkpoint::new(label, author, &document);
+                        add_checkpoint(&self.storage, &document_ref, &checkpoint)
+                            .await
+                            .map(|()| checkpoint)
+                            .map_err(FileCommandError::DocumentFile)
+                    }
+                    Err(error) => Err(error),
+                };
+                tx.send(result).expect("must succeed");
+            }
+            FileCommand::ListCheckpoints { file_id, tx } => {
+                let result = self.checkpoints(&DocumentRef::main(file_id)).await;
+                tx.send(result).expect("must succeed");
+            }
+            FileCommand::GetCheckpoint {
+                file_id,
+                checkpoint_id,
+                tx,
+            } => {
+                let result = self
+                    .checkpoint(&DocumentRef::main(file_id), &checkpoint_id)
+                    .await;
+                tx.send(result).expect("must succeed");
+            }
+            FileCommand::RestoreCheckpoint {
+                file_id,
+                checkpoint_id,
+                tx,
+            } => {
+                let result = self
+                    .restore_checkpoint(&DocumentRef::main(file_id), &checkpoint_id)
+                    .await;
+                tx.send(result).expect("must succeed");
+            }
+        }
+    }
+
     async fn handle_session_command(
         &mut self,
         from: &ConnectionId,
@@ -232,6 +486,7 @@ impl Server {
 
                 match result {
                     Ok(Some(tx)) => {
+                        self.log_latest_revision(&session_id).await;
                         let session_event = SessionEvent::TransactionAck(tx.id.clone());
                         self.broadcast_session_event(
                             &session_id,
@@ -242,37 +497,214 @@ impl Server {
                         Ok(Some(session_event))
                     }
                     Ok(None) => Ok(None),
-                    Err(_) => Ok(Some(SessionEvent::TransactionNack(
-                        tx.id.clone(),
-                        RollbackReason::Something,
-                    ))),
+                    Err(error) => {
+                        log::warn!("Rejecting transaction {}: {:?}", tx.id, error);
+                        Ok(Some(SessionEvent::TransactionNack(
+                            tx.id,
+                            RollbackReason::InvalidMutation(error),
+                        )))
+                    }
                 }
             }
         }
     }
 }
 
-impl Server {
+impl<S: Storage> Server<S> {
+    /// Returns the document of the branch, including changes of the session in progress.
+    async fn latest_document(
+        &self,
+        document_ref: &DocumentRef,
+    ) -> Result<Document, FileCommandError> {
+        if let Some(session) = self
+            .server_state
+            .get_session_id_of_file(document_ref)
+            .and_then(|session_id| self.server_state.get_session(session_id))
+        {
+            Ok(session.document().clone())
+        } else {
+            open_document(&self.storage, document_ref)
+                .await
+                .map(|(document, _)| document)
+                .map_err(FileCommandError::DocumentFile)
+        }
+    }
+
+    async fn latest_history(
+        &self,
+        document_ref: &DocumentRef,
+    ) -> Result<RevisionHistory, FileCommandError> {
+        if let Some(session) = self
+            .server_state
+            .get_session_id_of_file(document_ref)
+            .and_then(|session_id| self.server_state.get_session(session_id))
+        {
+            Ok(session.history().clone())
+        } else {
+            open_document(&self.storage, document_ref)
+                .await
+                .map(|(_, history)| history)
+                .map_err(FileCommandError::DocumentFile)
+        }
+    }
+
+    async fn revision_document(
+        &self,
+        document_ref: &DocumentRef,
+        revision: RevisionNumber,
+    ) -> Result<Document, FileCommandError> {
+        let history = self.latest_history(document_ref).await?;
+        read_document_at(&self.storage, document_ref, &history, revision)
+            .await
+            .map_err(FileCommandError::Revision)
+    }
+
+    async fn restore_revision(
+        &mut self,
+        document_ref: &DocumentRef,
+        revision: RevisionNumber,
+    ) -> Result<RevisionNumber, FileCommandError> {
+        let restored = self.revision_document(document_ref, revision).await?;
+        let number = self.restore_document(document_ref, &restored).await?;
+        log::info!(
+            "Restored revision {} of {} as revision {}",
+            revision,
+            document_ref,
+            number
+        );
+        Ok(number)
+    }
+
+    async fn checkpoints(
+        &self,
+        document_ref: &DocumentRef,
+    ) -> Result<Vec<Checkpoint>, FileCommandError> {
+        get_document_file_meta(&self.storage, document_ref)
+            .await
+            .map_err(FileCommandError::DocumentFile)?;
+        read_checkpoints(&self.storage, document_ref)
+            .await
+            .map_err(FileCommandError::DocumentFile)
+    }
+
+    async fn checkpoint(
+        &self,
+        document_ref: &DocumentRef,
+        checkpoint_id: &CheckpointId,
+    ) -> Result<Checkpoint, FileCommandError> {
+        get_document_file_meta(&self.storage, document_ref)
+            .await
+            .map_err(FileCommandError::DocumentFile)?;
+        read_checkpoint(&self.storage, document_ref, checkpoint_id)
+            .await
+            .map_err(|err| {
+                if err.is_not_found() {
+                    FileCommandError::CheckpointNotFound
+                } else {
+                    FileCommandError::DocumentFile(err)
+                }
+            })
+    }
+
+    async fn restore_checkpoint(
+        &mut self,
+        document_ref: &DocumentRef,
+        checkpoint_id: &CheckpointId,
+    ) -> Result<RevisionNumber, FileCommandError> {
+        let checkpoint = self.checkpoint(document_ref, checkpoint_id).await?;
+        let restored = Document::try_from(&checkpoint.snapshot)
+            .map_err(|error| FileCommandError::DocumentFile(DocumentFileError::Snapshot(error)))?;
+        let number = self.restore_document(document_ref, &restored).await?;
+        log::info!(
+            "Restored checkpoint {:?} of {} as revision {}",
+            checkpoint.label,
+            document_ref,
+            number
+        );
+        Ok(number)
+    }
+
+    /// Commits the difference between the latest document and `restored`, so that the document
+    /// goes back without losing its history. `restored` must be a version of the latest document.
+    async fn restore_document(
+        &mut self,
+        document_ref: &DocumentRef,
+        restored: &Document,
+    ) -> Result<RevisionNumber, FileCommandError> {
+        let latest = self.latest_document(document_ref).await?;
+        if latest.document_id() != restored.document_id() {
+            return Err(FileCommandError::DocumentMismatch);
+        }
+        let transaction = diff_documents(&latest, restored).transaction;
+
+        let session_id = self
+            .server_state
+            .get_session_id_of_file(document_ref)
+            .cloned();
+        let number = if let Some(session_id) = session_id {
+            let (tx, number) = self
+                .server_state
+                .apply_server_transaction(&session_id, transaction)
+                .map_err(FileCommandError::InvalidMutation)?;
+            self.broadcast_session_event(&session_id, SessionEvent::OthersTransaction(tx), None)
+                .await;
+            self.log_latest_revision(&session_id).await;
+            number
+        } else {
+            apply_to_document_file(&self.storage, document_ref, transaction).await?
+        };
+        Ok(number)
+    }
+
+    async fn fork_branch(
+        &mut self,
+        file_id: FileId,
+        from: Option<BranchName>,
+        branch: BranchName,
+    ) -> Result<(), FileCommandError> {
+        let from = DocumentRef::branch(file_id, from).map_err(FileCommandError::DocumentFile)?;
+        let to =
+            DocumentRef::branch(file_id, Some(branch)).map_err(FileCommandError::DocumentFile)?;
+        if to.branch.is_none() || get_document_file_meta(&self.storage, &to).await.is_ok() {
+            return Err(FileCommandError::BranchAlreadyExists);
+        }
+        let document = self.latest_document(&from).await?;
+        write_document_file(&self.storage, &to, &document)
+            .await
+            .map_err(FileCommandError::DocumentFile)?;
+        log::info!("Forked branch {} from {}", to, from);
+        Ok(())
+    }
+
     async fn create_session(
         &mut self,
-        file_id: &FileId,
+        document_ref: &DocumentRef,
         behavior: SessionBehavior,
     ) -> Result<SessionId, ()> {
-        let document = read_document_file(&file_id).await.map_err(|_| ())?;
+        let (document, history) = recover_document(&self.storage, document_ref)
+            .await
+            .map_err(|_| ())?;
         let session_id = self
             .server_state
-            .create_session(&file_id, document, behavior)
+            .create_session(document_ref, document, history, behavior)
             .map_err(|_| ())?;
+        if let Some(session) = self.server_state.get_session_mut(&session_id) {
+            session.autosave = self.autosave;
+        }
         Ok(session_id)
     }
 
+    /// Sends `Init` in chunks with `init_compression`, or as is if it is `None`.
     async fn join_session(
         &mut self,
-        file_id: &FileId,
+        document_ref: &DocumentRef,
         tx: ConnectionTx,
+        init_compression: Option<InitCompression>,
     ) -> Result<(SessionId, ConnectionId), ()> {
-        let (session_id, connection_id) =
-            self.server_state.join_session(file_id).map_err(|_| ())?;
+        let (session_id, connection_id) = self
+            .server_state
+            .join_session(document_ref)
+            .map_err(|_| ())?;
 
         let (session_snapshot, document_snapshot) = self
             .server_state
@@ -283,18 +715,31 @@ impl Server {
         self.connections
             .send(&connection_id, ConnectionEvent::Connected { connection_id })
             .await;
-        self.connections
-            .send(
-                &connection_id,
-                ConnectionEvent::IdentifiableEvent(IdentifiableEvent::BySystem {
-                    session_event: SessionEvent::Init {
-                        session_id: session_id.clone(),
-                        session_snapshot: session_snapshot.clone(),
-                        document_snapshot,
-                    },
-                }),
-            )
-            .await;
+        let init = SessionEvent::Init {
+            session_id,
+            session_snapshot: session_snapshot.clone(),
+            document_snapshot,
+        };
+        let init_events = match init_compression {
+            Some(compression) => split_init_event(
+                init,
+                &InitTransferOptions {
+                    compression,
+                    ..self.init_transfer
+                },
+            ),
+            None => vec![init],
+        };
+        for session_event in init_events {
+            self.connections
+                .send(
+                    &connection_id,
+                    ConnectionEvent::IdentifiableEvent(IdentifiableEvent::BySystem {
+                        session_event,
+                    }),
+                )
+                .await;
+        }
         self.broadcast_session_event(
             &session_id,
             SessionEvent::SessionStateChanged(session_snapshot),
@@ -306,24 +751,66 @@ impl Server {
 
     async fn join_or_create_auto_commit_session(
         &mut self,
-        file_id: &FileId,
+        document_ref: &DocumentRef,
         tx: ConnectionTx,
+        init_compression: Option<InitCompression>,
     ) -> Result<(SessionId, ConnectionId), ()> {
-        if self.server_state.get_session_id_of_file(file_id).is_none() {
-            self.create_session(file_id, SessionBehavior::AutoTerminateWhenEmpty)
-                .await
-                .expect("must succeed");
+        if self
+            .server_state
+            .get_session_id_of_file(document_ref)
+            .is_none()
+        {
+            self.create_session(document_ref, SessionBehavior::AutoTerminateWhenEmpty)
+                .await?;
         }
-        let (session_id, connection_id) = self.join_session(file_id, tx).await?;
+        let (session_id, connection_id) = self
+            .join_session(document_ref, tx, init_compression)
+            .await?;
         Ok((session_id, connection_id))
     }
 
     async fn terminate_session(&mut self, session_id: &SessionId) {
-        let session = self.server_state.terminate_session(session_id);
+        let mut session = self.server_state.terminate_session(session_id);
         for connection_id in &session.connections {
             self.disconnect_from_server(connection_id, false).await;
         }
-        write_document_file(&session.file_id, session.document()).await;
+        let document_ref = session.document_ref.clone();
+        let (document, history) = session.document_and_history_mut();
+        if let Err(err) = compact_document(&self.storage, &document_ref, document, history).await {
+            log::error!("Cannot save {}: {:?}", document_ref, err);
+        }
+    }
+
+    /// Appends the latest revision of the session to the transaction log, and saves the document
+    /// if the autosave policy says to.
+    async fn log_latest_revision(&mut self, session_id: &SessionId) {
+        let session = match self.server_state.get_session_mut(session_id) {
+            Some(session) => session,
+            None => return,
+        };
+        if let Some(revision) = session.history().unsaved().last() {
+            if let Err(err) =
+                append_transaction_log(&self.storage, &session.document_ref, revision).await
+            {
+                log::error!("Cannot log revision of {}: {:?}", session.document_ref, err);
+            }
+        }
+        session.mark_changed();
+        let now = Instant::now();
+        if session.should_autosave(now) {
+            save_session(&self.storage, session, now).await;
+        }
+    }
+
+    async fn autosave_sessions(&mut self) {
+        let now = Instant::now();
+        for session_id in self.server_state.session_ids() {
+            if let Some(session) = self.server_state.get_session_mut(&session_id) {
+                if session.should_autosave(now) && save_session(&self.storage, session, now).await {
+                    log::info!("Autosaved {}", session.document_ref);
+                }
+            }
+        }
     }
 
     async fn leave_session(&mut self, connection_id: &ConnectionId, broadcast: bool) {
@@ -365,7 +852,7 @@ impl Server {
     }
 }
 
-impl Server {
+impl<S: Storage> Server<S> {
     async fn broadcast_session_state(&mut self, session_id: &SessionId) {
         if let Some(session) = self.server_state.get_session(&session_id) {
             let session_snapshot = session.snapshot();
@@ -386,7 +873,7 @@ impl Server {
     ) {
         if let Ok(conns) = self.server_state.connection_ids_in_session(session_id) {
             for connection_id in conns {
-                if without.map_or(false, |c| c != connection_id) {
+                if without.is_none_or(|c| c != connection_id) {
                     let event = ConnectionEvent::IdentifiableEvent(IdentifiableEvent::BySystem {
                         session_event: session_event.clone(),
                     });
@@ -397,11 +884,95 @@ impl Server {
     }
 }
 
-pub fn spawn_server() -> ServerTx {
+/// Reads `INIT_TRANSFER_THRESHOLD` in bytes. The compression is chosen by each client when it
+/// connects. An unset or invalid variable keeps the default.
+fn init_transfer_from_env() -> InitTransferOptions {
+    let mut options = InitTransferOptions::default();
+    if let Some(threshold) = std::env::var("INIT_TRANSFER_THRESHOLD")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+    {
+        options.threshold = threshold;
+    }
+    options
+}
+
+/// Saves the document of the session, and returns whether it is saved.
+async fn save_session<S: Storage>(storage: &S, session: &mut Session, now: Instant) -> bool {
+    let document_ref = session.document_ref.clone();
+    let (document, history) = session.document_and_history_mut();
+    let result = compact_document(storage, &document_ref, document, history).await;
+    match result {
+        Ok(()) => {
+            session.mark_saved(now);
+            true
+        }
+        Err(err) => {
+            log::error!("Cannot save {}: {:?}", session.document_ref, err);
+            false
+        }
+    }
+}
+
+/// Commits the transaction to the file, which must not be in a session.
+async fn apply_to_document_file<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+    transaction: Transaction,
+) -> Result<RevisionNumber, FileCommandError> {
+    let (mut document, mut history) = open_document(storage, document_ref)
+        .await
+        .map_err(FileCommandError::DocumentFile)?;
+    document
+        .process(transaction.clone())
+        .map_err(FileCommandError::InvalidMutation)?;
+    let number = history.record(transaction, None);
+    compact_document(storage, document_ref, &document, &mut history)
+        .await
+        .map_err(FileCommandError::DocumentFile)?;
+    Ok(number)
+}
+
+/// Overwrites the file, which must not be in a session. The replacement is recorded as the
+/// difference, so it must be a version of the same document, unless the file does not exist yet.
+async fn replace_document_file<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+    document: &Document,
+) -> Result<(), FileCommandError> {
+    let mut history = match open_document(storage, document_ref).await {
+        Ok((current, mut history)) => {
+            if current.document_id() != document.document_id() {
+                return Err(FileCommandError::DocumentMismatch);
+            }
+            history.record(diff_documents(&current, document).transaction, None);
+            history
+        }
+        Err(err) if err.is_not_found() => RevisionHistory::new(document),
+        Err(err) => return Err(FileCommandError::DocumentFile(err)),
+    };
+    compact_document(storage, document_ref, document, &mut history)
+        .await
+        .map_err(FileCommandError::DocumentFile)
+}
+
+pub fn spawn_server<S: Storage>(storage: S) -> ServerTx {
     let (srv_tx, mut srv_rx) = channel::<ServerCommand>(256);
 
+    let mut autosave_tx = srv_tx.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(AUTOSAVE_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            if autosave_tx.send(ServerCommand::Autosave).await.is_err() {
+                break;
+            }
+        }
+    });
+
     tokio::spawn(async move {
-        let mut server = Box::new(Server::new());
+        let mut server = Box::new(Server::new(storage));
+        server.recovery_reports = recover_document_files(&server.storage).await;
 
         while let Some(command) = srv_rx.recv().await {
             match command {
@@ -411,6 +982,12 @@ pub fn spawn_server() -> ServerTx {
                 ServerCommand::AdminCommand(admin_command) => {
                     server.handle_admin_command(admin_command).await;
                 }
+                ServerCommand::FileCommand(file_command) => {
+                    server.handle_file_command(file_command).await;
+                }
+                ServerCommand::Autosave => {
+                    server.autosave_sessions().await;
+                }
             }
         }
     });
@@ -1,10 +1,13 @@
+use crate::document_file::DocumentRef;
+use crate::revision_history::{RevisionHistory, RevisionNumber};
 use crate::session::{
     PendingTransactionCommitError, PendingTransactionCommitResult, Session, SessionBehavior,
 };
 use std::collections::HashMap;
 use std::num::Wrapping;
 use system::{
-    ConnectionId, Document, DocumentSnapshot, FileId, SessionId, SessionSnapshot, Transaction,
+    ConnectionId, Document, DocumentSnapshot, MutationError, SessionId, SessionSnapshot,
+    Transaction,
 };
 
 pub struct ServerState {
@@ -13,7 +16,7 @@ pub struct ServerState {
 
     session_id_source: Wrapping<SessionId>,
     sessions: HashMap<SessionId, Session>,
-    file_sessions: HashMap<FileId, SessionId>,
+    file_sessions: HashMap<DocumentRef, SessionId>,
 }
 
 #[derive(Debug)]
@@ -36,33 +39,33 @@ impl ServerState {
         }
     }
 
-    pub fn session_id(&self, file_id: &FileId) -> Option<&SessionId> {
-        self.file_sessions.get(file_id)
+    pub fn session_id(&self, document_ref: &DocumentRef) -> Option<&SessionId> {
+        self.file_sessions.get(document_ref)
     }
 
     pub fn get_session_id_of_connection(&self, connection_id: &ConnectionId) -> Option<&SessionId> {
         self.connection_locations.get(connection_id)
     }
 
-    pub fn get_session_id_of_file(&self, file_id: &FileId) -> Option<&SessionId> {
-        self.file_sessions.get(file_id)
+    pub fn get_session_id_of_file(&self, document_ref: &DocumentRef) -> Option<&SessionId> {
+        self.file_sessions.get(document_ref)
     }
 
     pub fn create_session(
         &mut self,
-        file_id: &FileId,
+        document_ref: &DocumentRef,
         document: Document,
+        history: RevisionHistory,
         behavior: SessionBehavior,
     ) -> Result<SessionId, ServerError> {
         let session_id = self.new_session_id();
-        if self.file_sessions.contains_key(file_id) {
+        if self.file_sessions.contains_key(document_ref) {
             Err(ServerError::SessionAlreadyCreatedForFileId)
         } else {
-            self.file_sessions
-                .insert(file_id.clone(), session_id.clone());
+            self.file_sessions.insert(document_ref.clone(), session_id);
             self.sessions.insert(
                 session_id.clone(),
-                Session::new(file_id.clone(), document, behavior),
+                Session::new(document_ref.clone(), document, history, behavior),
             );
             Ok(session_id)
         }
@@ -70,9 +73,9 @@ impl ServerState {
 
     pub fn join_session(
         &mut self,
-        file_id: &FileId,
+        document_ref: &DocumentRef,
     ) -> Result<(SessionId, ConnectionId), ServerError> {
-        if let Some(session_id) = self.file_sessions.get(file_id).cloned() {
+        if let Some(session_id) = self.file_sessions.get(document_ref).cloned() {
             let connection_id = self.new_connection_id();
             if self
                 .sessions
@@ -108,6 +111,14 @@ impl ServerState {
         self.sessions.get(session_id)
     }
 
+    pub fn session_ids(&self) -> Vec<SessionId> {
+        self.sessions.keys().cloned().collect()
+    }
+
+    pub fn get_session_mut(&mut self, session_id: &SessionId) -> Option<&mut Session> {
+        self.sessions.get_mut(session_id)
+    }
+
     pub fn connection_ids_in_session(
         &self,
         session_id: &SessionId,
@@ -130,7 +141,7 @@ impl ServerState {
 
     pub fn terminate_session(&mut self, session_id: &SessionId) -> Session {
         let session = self.sessions.remove(&session_id).expect("must exist");
-        self.file_sessions.remove(&session.file_id);
+        self.file_sessions.remove(&session.document_ref);
         self.connection_locations
             .retain(|_, saved_session_id| session_id != saved_session_id);
         session
@@ -151,13 +162,24 @@ impl ServerState {
         session_id: &SessionId,
         from: &ConnectionId,
         tx: Transaction,
-    ) -> Result<Option<Transaction>, ()> {
+    ) -> Result<Option<Transaction>, MutationError> {
         self.sessions
             .get_mut(session_id)
             .expect("must exist")
             .handle_transaction(from, tx)
     }
 
+    pub fn apply_server_transaction(
+        &mut self,
+        session_id: &SessionId,
+        tx: Transaction,
+    ) -> Result<(Transaction, RevisionNumber), MutationError> {
+        self.sessions
+            .get_mut(session_id)
+            .expect("must exist")
+            .apply_server_transaction(tx)
+    }
+
     pub fn has_session(&mut self, session_id: &SessionId) -> bool {
         self.sessions.contains_key(session_id)
     }
@@ -181,9 +203,22 @@ mod tests {
     #[test]
     fn it_remove_session_when_all_connections_disconnect() {
         let mut state = ServerState::new();
+        let document_ref = DocumentRef::main(Uuid::new_v4());
         let document = Document::new();
-        let (_, connection_id) = state.create_session(&Uuid::new_v4(), document).expect("");
-        state.leave_session(&connection_id).expect("");
+        let history = RevisionHistory::new(&document);
+        state
+            .create_session(
+                &document_ref,
+                document,
+                history,
+                SessionBehavior::AutoTerminateWhenEmpty,
+            )
+            .expect("");
+        let (_, connection_id) = state.join_session(&document_ref).expect("");
+        let session_id = state.leave_session(&connection_id).expect("");
+        if state.get_session(&session_id).expect("").should_terminate() {
+            state.terminate_session(&session_id);
+        }
         assert!(state.sessions.is_empty())
     }
 }
@@ -1,7 +1,10 @@
+use crate::document_file::DocumentRef;
+use crate::revision_history::{RevisionHistory, RevisionNumber};
 use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 use system::{
-    ConnectionId, Document, DocumentReadable, DocumentSnapshot, FileId, ServerLeaderDocument,
-    SessionSnapshot, Transaction, TransactionId,
+    ConnectionId, Document, DocumentReadable, DocumentSnapshot, MutationError,
+    ServerLeaderDocument, SessionSnapshot, Transaction, TransactionId,
 };
 
 #[derive(Debug)]
@@ -22,16 +25,63 @@ pub enum PendingTransactionCommitError {
     Rollback {
         from: ConnectionId,
         tx_id: TransactionId,
+        error: MutationError,
     },
 }
 
 #[derive(Debug)]
 pub struct Session {
-    pub file_id: FileId,
+    pub document_ref: DocumentRef,
     pub connections: Vec<ConnectionId>,
     document: ServerLeaderDocument,
     pub behavior: SessionBehavior,
     pending_txs: VecDeque<PendingTransactionItem>,
+    history: RevisionHistory,
+    pub autosave: AutosavePolicy,
+    /// Committed transactions since the document was last saved
+    unsaved_transactions: usize,
+    last_saved_at: Instant,
+}
+
+/// When a session saves its document, whichever comes first. `None` disables the condition.
+#[derive(Debug, Clone, Copy)]
+pub struct AutosavePolicy {
+    pub interval: Option<Duration>,
+    pub transactions: Option<usize>,
+}
+
+impl Default for AutosavePolicy {
+    fn default() -> Self {
+        Self {
+            interval: Some(Duration::from_secs(60)),
+            transactions: Some(100),
+        }
+    }
+}
+
+impl AutosavePolicy {
+    /// Reads `AUTOSAVE_INTERVAL_SECS` and `AUTOSAVE_TRANSACTIONS`, where 0 disables the
+    /// condition. Unset variables keep the defaults.
+    pub fn from_env() -> Self {
+        Self::from_vars(|key| std::env::var(key).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let default = Self::default();
+        let read = |key: &str| var(key).and_then(|value| value.parse::<u64>().ok());
+        Self {
+            interval: match read("AUTOSAVE_INTERVAL_SECS") {
+                Some(0) => None,
+                Some(secs) => Some(Duration::from_secs(secs)),
+                None => default.interval,
+            },
+            transactions: match read("AUTOSAVE_TRANSACTIONS") {
+                Some(0) => None,
+                Some(count) => Some(count as usize),
+                None => default.transactions,
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -41,13 +91,22 @@ pub enum SessionBehavior {
 }
 
 impl Session {
-    pub fn new(file_id: FileId, document: Document, behavior: SessionBehavior) -> Self {
+    pub fn new(
+        document_ref: DocumentRef,
+        document: Document,
+        history: RevisionHistory,
+        behavior: SessionBehavior,
+    ) -> Self {
         Self {
-            file_id,
+            document_ref,
             connections: Vec::new(),
             document: ServerLeaderDocument::new(document),
             behavior,
             pending_txs: VecDeque::new(),
+            history,
+            autosave: AutosavePolicy::default(),
+            unsaved_transactions: 0,
+            last_saved_at: Instant::now(),
         }
     }
 
@@ -68,10 +127,12 @@ impl Session {
         &mut self,
         from: &ConnectionId,
         tx: Transaction,
-    ) -> Result<Option<Transaction>, ()> {
+    ) -> Result<Option<Transaction>, MutationError> {
         match self.behavior {
             SessionBehavior::AutoTerminateWhenEmpty => {
-                self.document.process_transaction(tx).map(|tx| Some(tx))
+                let tx = self.document.process_transaction(tx)?;
+                self.history.record(tx.clone(), Some(*from));
+                Ok(Some(tx))
             }
             SessionBehavior::ManualCommitByAdmin => {
                 self.pending_txs.push_back(PendingTransactionItem {
@@ -93,10 +154,15 @@ impl Session {
             SessionBehavior::ManualCommitByAdmin => {
                 if let Some(PendingTransactionItem { from, tx }) = self.pending_txs.pop_front() {
                     let tx_id = tx.id.clone();
-                    self.document
-                        .process_transaction(tx)
-                        .map(|tx| Some(PendingTransactionCommitResult { from, tx }))
-                        .map_err(|_| PendingTransactionCommitError::Rollback { from, tx_id })
+                    match self.document.process_transaction(tx) {
+                        Ok(tx) => {
+                            self.history.record(tx.clone(), Some(from));
+                            Ok(Some(PendingTransactionCommitResult { from, tx }))
+                        }
+                        Err(error) => {
+                            Err(PendingTransactionCommitError::Rollback { from, tx_id, error })
+                        }
+                    }
                 } else {
                     Err(PendingTransactionCommitError::InvalidRequest)
                 }
@@ -104,6 +170,25 @@ impl Session {
         }
     }
 
+    /// Commits a transaction made by the server regardless of the behavior.
+    pub fn apply_server_transaction(
+        &mut self,
+        tx: Transaction,
+    ) -> Result<(Transaction, RevisionNumber), MutationError> {
+        let tx = self.document.process_transaction(tx)?;
+        let number = self.history.record(tx.clone(), None);
+        Ok((tx, number))
+    }
+
+    pub fn history(&self) -> &RevisionHistory {
+        &self.history
+    }
+
+    /// Lets the document be saved along with the history, which keeps track of what is saved.
+    pub fn document_and_history_mut(&mut self) -> (&Document, &mut RevisionHistory) {
+        (self.document.document(), &mut self.history)
+    }
+
     pub fn document_snapshot(&self) -> DocumentSnapshot {
         self.document.snapshot()
     }
@@ -112,9 +197,104 @@ impl Session {
         self.document.document()
     }
 
+    pub fn mark_changed(&mut self) {
+        self.unsaved_transactions += 1;
+    }
+
+    pub fn mark_saved(&mut self, now: Instant) {
+        self.unsaved_transactions = 0;
+        self.last_saved_at = now;
+    }
+
+    /// Whether the document changed since it was last saved, and the policy says to save it.
+    pub fn should_autosave(&self, now: Instant) -> bool {
+        if self.unsaved_transactions == 0 {
+            return false;
+        }
+        let by_transactions = self
+            .autosave
+            .transactions
+            .is_some_and(|count| self.unsaved_transactions >= count);
+        let by_interval = self
+            .autosave
+            .interval
+            .is_some_and(|interval| now.duration_since(self.last_saved_at) >= interval);
+        by_transactions || by_interval
+    }
+
     pub fn has_pending_transactions(&self) -> bool {
         !self.pending_txs.is_empty()
     }
 }
 
 // TODO
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use system::uuid::Uuid;
+
+    fn session(autosave: AutosavePolicy) -> Session {
+        let document = Document::new();
+        let history = RevisionHistory::new(&document);
+        let mut session = Session::new(
+            DocumentRef::main(Uuid::new_v4()),
+            document,
+            history,
+            SessionBehavior::AutoTerminateWhenEmpty,
+        );
+        session.autosave = autosave;
+        session
+    }
+
+    #[test]
+    fn it_should_read_autosave_policy_from_vars() {
+        let policy = AutosavePolicy::from_vars(|key| match key {
+            "AUTOSAVE_INTERVAL_SECS" => Some("0".into()),
+            "AUTOSAVE_TRANSACTIONS" => Some("5".into()),
+            _ => None,
+        });
+        assert_eq!(policy.interval, None);
+        assert_eq!(policy.transactions, Some(5));
+
+        let policy = AutosavePolicy::from_vars(|_| Some("invalid".into()));
+        assert_eq!(policy.interval, AutosavePolicy::default().interval);
+        assert_eq!(policy.transactions, AutosavePolicy::default().transactions);
+    }
+
+    #[test]
+    fn it_should_autosave_by_interval_or_transaction_count() {
+        let mut session = session(AutosavePolicy {
+            interval: Some(Duration::from_secs(10)),
+            transactions: Some(3),
+        });
+        let saved_at = Instant::now();
+        session.mark_saved(saved_at);
+        assert!(!session.should_autosave(saved_at + Duration::from_secs(3600)));
+
+        session.mark_changed();
+        session.mark_changed();
+        assert!(!session.should_autosave(saved_at + Duration::from_secs(1)));
+        assert!(session.should_autosave(saved_at + Duration::from_secs(10)));
+
+        session.mark_changed();
+        assert!(session.should_autosave(saved_at + Duration::from_secs(1)));
+
+        session.mark_saved(saved_at + Duration::from_secs(2));
+        assert!(!session.should_autosave(saved_at + Duration::from_secs(20)));
+    }
+
+    #[test]
+    fn it_should_not_autosave_when_disabled() {
+        let mut session = session(AutosavePolicy {
+            interval: None,
+            transactions: None,
+        });
+        let saved_at = Instant::now();
+        session.mark_saved(saved_at);
+        for _ in 0..1000 {
+            session.mark_changed();
+        }
+        assert!(!session.should_autosave(saved_at + Duration::from_secs(3600)));
+    }
+}
@@ -0,0 +1,139 @@
+use super::{RecordKey, RecordKind, RecordMeta, Storage, StorageError};
+use crate::document_file::DocumentRef;
+use async_trait::async_trait;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+/// Suffix of a file being written. A file with it is left over only if the server stopped while
+/// writing it.
+const TEMP_FILE_SUFFIX: &str = ".tmp";
+
+/// Infixes of records of which a document has many. Their files are named
+/// `{document_ref}{infix}{index}`, e.g. `{file_id}.rcs.checkpoint.{checkpoint_id}`.
+const CHECKPOINT_INFIX: &str = ".rcs.checkpoint.";
+const REVISIONS_INFIX: &str = ".rcs.history.";
+const REVISION_SNAPSHOT_INFIX: &str = ".rcs.snapshot.";
+
+/// Stores each record as a file in a directory, e.g. `{file_id}.rcs` for the snapshot of the main
+/// branch and `{file_id}@{branch}.rcs.log` for the transaction log of a branch.
+///
+/// Batches are not atomic. Their changes are ordered so that what a stop in the middle leaves can
+/// be recovered from.
+#[derive(Debug, Clone)]
+pub struct FsStorage {
+    root: PathBuf,
+}
+
+impl FsStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &RecordKey) -> PathBuf {
+        self.root
+            .join(format!("{}{}", key.document_ref, suffix(key.kind)))
+    }
+}
+
+fn suffix(kind: RecordKind) -> String {
+    match kind {
+        RecordKind::Snapshot => ".rcs".into(),
+        RecordKind::Backup => ".rcs.bak".into(),
+        RecordKind::History => ".rcs.history".into(),
+        RecordKind::Revisions(chunk) => format!("{}{}", REVISIONS_INFIX, chunk),
+        RecordKind::RevisionSnapshot(chunk) => format!("{}{}", REVISION_SNAPSHOT_INFIX, chunk),
+        RecordKind::Checkpoint(id) => format!("{}{}", CHECKPOINT_INFIX, id),
+        RecordKind::Log => ".rcs.log".into(),
+    }
+}
+
+fn parse_file_name(file_name: &str) -> Option<RecordKey> {
+    let (stem, kind) = if let Some((stem, id)) = file_name.split_once(CHECKPOINT_INFIX) {
+        (stem, RecordKind::Checkpoint(id.parse().ok()?))
+    } else if let Some((stem, chunk)) = file_name.split_once(REVISIONS_INFIX) {
+        (stem, RecordKind::Revisions(chunk.parse().ok()?))
+    } else if let Some((stem, chunk)) = file_name.split_once(REVISION_SNAPSHOT_INFIX) {
+        (stem, RecordKind::RevisionSnapshot(chunk.parse().ok()?))
+    } else {
+        RecordKind::SINGLE
+            .iter()
+            .find_map(|kind| Some((file_name.strip_suffix(&suffix(*kind))?, *kind)))?
+    };
+    let document_ref = DocumentRef::from_str(stem).ok()?;
+    Some(RecordKey { document_ref, kind })
+}
+
+#[async_trait]
+impl Storage for FsStorage {
+    async fn read(&self, key: &RecordKey) -> Result<Vec<u8>, StorageError> {
+        Ok(fs::read(self.path(key)).await?)
+    }
+
+    /// Writes to a temporary file and renames it.
+    async fn write(&self, key: &RecordKey, content: &[u8]) -> Result<(), StorageError> {
+        let path = self.path(key);
+        let mut temp_path = path.clone().into_os_string();
+        temp_path.push(TEMP_FILE_SUFFIX);
+        let mut file = fs::File::create(&temp_path).await?;
+        file.write_all(content).await?;
+        file.sync_all().await?;
+        fs::rename(&temp_path, &path).await?;
+        Ok(())
+    }
+
+    async fn append(&self, key: &RecordKey, content: &[u8]) -> Result<(), StorageError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(key))
+            .await?;
+        file.write_all(content).await?;
+        file.sync_data().await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &RecordKey) -> Result<(), StorageError> {
+        match fs::remove_file(self.path(key))
+            .await
+            .map_err(StorageError::from)
+        {
+            Err(StorageError::NotFound) => Ok(()),
+            result => result,
+        }
+    }
+
+    async fn metadata(&self, key: &RecordKey) -> Result<RecordMeta, StorageError> {
+        let metadata = fs::metadata(self.path(key)).await?;
+        Ok(RecordMeta {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+
+    async fn list(&self) -> Result<Vec<RecordKey>, StorageError> {
+        let mut result = Vec::new();
+        let mut entries = fs::read_dir(&self.root).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if let Some(key) = entry.file_name().to_str().and_then(parse_file_name) {
+                result.push(key);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Removes temporary files, as the files they would replace are intact.
+    async fn recover_incomplete_writes(&self) -> Result<Vec<String>, StorageError> {
+        let mut result = Vec::new();
+        let mut entries = fs::read_dir(&self.root).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if file_name.ends_with(TEMP_FILE_SUFFIX) {
+                fs::remove_file(entry.path()).await?;
+                result.push(format!("Removed an incomplete write {}", file_name));
+            }
+        }
+        Ok(result)
+    }
+}
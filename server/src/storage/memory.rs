@@ -0,0 +1,95 @@
+use super::{RecordKey, RecordMeta, RecordOp, Storage, StorageError};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Keeps records in memory, e.g. for tests. Nothing survives the process.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    records: Mutex<HashMap<RecordKey, (Vec<u8>, SystemTime)>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn read(&self, key: &RecordKey) -> Result<Vec<u8>, StorageError> {
+        let records = self.records.lock().expect("must not be poisoned");
+        records
+            .get(key)
+            .map(|(content, _)| content.clone())
+            .ok_or(StorageError::NotFound)
+    }
+
+    async fn write(&self, key: &RecordKey, content: &[u8]) -> Result<(), StorageError> {
+        let mut records = self.records.lock().expect("must not be poisoned");
+        records.insert(key.clone(), (content.to_vec(), SystemTime::now()));
+        Ok(())
+    }
+
+    async fn append(&self, key: &RecordKey, content: &[u8]) -> Result<(), StorageError> {
+        let mut records = self.records.lock().expect("must not be poisoned");
+        let record = records
+            .entry(key.clone())
+            .or_insert_with(|| (Vec::new(), SystemTime::now()));
+        record.0.extend_from_slice(content);
+        record.1 = SystemTime::now();
+        Ok(())
+    }
+
+    async fn delete(&self, key: &RecordKey) -> Result<(), StorageError> {
+        let mut records = self.records.lock().expect("must not be poisoned");
+        records.remove(key);
+        Ok(())
+    }
+
+    /// Applies the changes under one lock, so that nobody sees a part of them.
+    async fn write_batch(&self, ops: &[RecordOp]) -> Result<(), StorageError> {
+        let mut records = self.records.lock().expect("must not be poisoned");
+        let now = SystemTime::now();
+        for op in ops {
+            match op {
+                RecordOp::Write(key, content) => {
+                    records.insert(key.clone(), (content.clone(), now));
+                }
+                RecordOp::Append(key, content) => {
+                    let record = records
+                        .entry(key.clone())
+                        .or_insert_with(|| (Vec::new(), now));
+                    record.0.extend_from_slice(content);
+                    record.1 = now;
+                }
+                RecordOp::Delete(key) => {
+                    records.remove(key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn metadata(&self, key: &RecordKey) -> Result<RecordMeta, StorageError> {
+        let records = self.records.lock().expect("must not be poisoned");
+        records
+            .get(key)
+            .map(|(content, modified)| RecordMeta {
+                len: content.len() as u64,
+                modified: Some(*modified),
+            })
+            .ok_or(StorageError::NotFound)
+    }
+
+    async fn list(&self) -> Result<Vec<RecordKey>, StorageError> {
+        let records = self.records.lock().expect("must not be poisoned");
+        Ok(records.keys().cloned().collect())
+    }
+
+    /// Writes are never interrupted in memory.
+    async fn recover_incomplete_writes(&self) -> Result<Vec<String>, StorageError> {
+        Ok(Vec::new())
+    }
+}
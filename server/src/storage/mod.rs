@@ -0,0 +1,221 @@
+use crate::checkpoint::CheckpointId;
+use crate::document_file::DocumentRef;
+use async_trait::async_trait;
+use std::time::SystemTime;
+
+mod fs;
+mod memory;
+mod sqlite;
+
+pub use self::fs::FsStorage;
+pub use self::memory::MemoryStorage;
+pub use self::sqlite::SqliteStorage;
+
+/// Kind of data kept for a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RecordKind {
+    Snapshot,
+    /// Last intact snapshot before the current one
+    Backup,
+    /// Latest saved revision of the history
+    History,
+    /// Revisions of a chunk of the history, appended as they are saved
+    Revisions(u64),
+    /// Document at the start of a chunk of the history, i.e. the base of the history for chunk 0
+    RevisionSnapshot(u64),
+    /// One checkpoint, so that adding a checkpoint leaves the others as they are
+    Checkpoint(CheckpointId),
+    /// Transaction log since the last compaction
+    Log,
+}
+
+impl RecordKind {
+    /// Kinds of which a document has one record at most
+    pub const SINGLE: [RecordKind; 4] = [
+        RecordKind::Snapshot,
+        RecordKind::Backup,
+        RecordKind::History,
+        RecordKind::Log,
+    ];
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RecordKey {
+    pub document_ref: DocumentRef,
+    pub kind: RecordKind,
+}
+
+impl RecordKey {
+    pub fn new(document_ref: &DocumentRef, kind: RecordKind) -> Self {
+        Self {
+            document_ref: document_ref.clone(),
+            kind,
+        }
+    }
+}
+
+/// Change to a record in a batch.
+#[derive(Debug, Clone)]
+pub enum RecordOp {
+    Write(RecordKey, Vec<u8>),
+    Append(RecordKey, Vec<u8>),
+    Delete(RecordKey),
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordMeta {
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    NotFound,
+    Io(std::io::Error),
+    /// Error of the underlying store other than I/O
+    Backend(String),
+}
+
+impl From<std::io::Error> for StorageError {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::NotFound => StorageError::NotFound,
+            _ => StorageError::Io(err),
+        }
+    }
+}
+
+/// Where documents and the data kept for them are stored, as records of bytes.
+#[async_trait]
+pub trait Storage: Send + Sync + 'static {
+    async fn read(&self, key: &RecordKey) -> Result<Vec<u8>, StorageError>;
+
+    /// Replaces the record. The record is either the old one or the new one even if the server
+    /// stops in the middle.
+    async fn write(&self, key: &RecordKey, content: &[u8]) -> Result<(), StorageError>;
+
+    /// Appends to the record, creating it if it doesn't exist, and returns once it is durable.
+    async fn append(&self, key: &RecordKey, content: &[u8]) -> Result<(), StorageError>;
+
+    /// Deleting a record which doesn't exist is not an error.
+    async fn delete(&self, key: &RecordKey) -> Result<(), StorageError>;
+
+    /// Applies the changes in order, all or none of them if the storage can. By default they are
+    /// applied one by one, so a stop of the server in the middle leaves only the first ones.
+    async fn write_batch(&self, ops: &[RecordOp]) -> Result<(), StorageError> {
+        for op in ops {
+            match op {
+                RecordOp::Write(key, content) => self.write(key, content).await?,
+                RecordOp::Append(key, content) => self.append(key, content).await?,
+                RecordOp::Delete(key) => self.delete(key).await?,
+            }
+        }
+        Ok(())
+    }
+
+    async fn metadata(&self, key: &RecordKey) -> Result<RecordMeta, StorageError>;
+
+    async fn list(&self) -> Result<Vec<RecordKey>, StorageError>;
+
+    /// Cleans up what writes interrupted by a stop of the server left, and describes each.
+    async fn recover_incomplete_writes(&self) -> Result<Vec<String>, StorageError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use system::uuid::Uuid;
+
+    async fn it_should_keep_records<S: Storage>(storage: S) {
+        let document_ref = DocumentRef::main(Uuid::new_v4());
+        let snapshot = RecordKey::new(&document_ref, RecordKind::Snapshot);
+        let log = RecordKey::new(&document_ref, RecordKind::Log);
+        let checkpoint = RecordKey::new(&document_ref, RecordKind::Checkpoint(Uuid::new_v4()));
+
+        assert!(matches!(
+            storage.read(&snapshot).await,
+            Err(StorageError::NotFound)
+        ));
+        storage
+            .write(&snapshot, b"old")
+            .await
+            .expect("should write");
+        storage
+            .write(&snapshot, b"new")
+            .await
+            .expect("should write");
+        storage.append(&log, b"a").await.expect("should append");
+        storage.append(&log, b"b").await.expect("should append");
+        storage
+            .write(&checkpoint, b"checkpoint")
+            .await
+            .expect("should write");
+        assert_eq!(storage.read(&snapshot).await.expect("should read"), b"new");
+        assert_eq!(storage.read(&log).await.expect("should read"), b"ab");
+        assert_eq!(storage.metadata(&log).await.expect("should exist").len, 2);
+
+        let mut keys = storage.list().await.expect("should list");
+        keys.sort_by_key(|key| key.kind);
+        assert_eq!(keys, vec![snapshot.clone(), checkpoint, log.clone()]);
+
+        storage
+            .write_batch(&[
+                RecordOp::Append(log.clone(), b"c".to_vec()),
+                RecordOp::Write(snapshot.clone(), b"batch".to_vec()),
+            ])
+            .await
+            .expect("should write batch");
+        assert_eq!(
+            storage.read(&snapshot).await.expect("should read"),
+            b"batch"
+        );
+        assert_eq!(storage.read(&log).await.expect("should read"), b"abc");
+
+        storage.delete(&log).await.expect("should delete");
+        storage
+            .delete(&log)
+            .await
+            .expect("should ignore missing record");
+        assert!(matches!(
+            storage.metadata(&log).await,
+            Err(StorageError::NotFound)
+        ));
+        assert!(storage
+            .recover_incomplete_writes()
+            .await
+            .expect("should recover")
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn memory_storage_should_keep_records() {
+        it_should_keep_records(MemoryStorage::new()).await;
+    }
+
+    #[tokio::test]
+    async fn fs_storage_should_keep_records() {
+        let root = std::env::temp_dir().join(format!("storage-test-{}", Uuid::new_v4()));
+        std::fs::create_dir(&root).expect("should create directory");
+        it_should_keep_records(FsStorage::new(&root)).await;
+        std::fs::remove_dir_all(&root).expect("should remove directory");
+    }
+
+    #[tokio::test]
+    async fn sqlite_storage_should_keep_records() {
+        it_should_keep_records(SqliteStorage::open_in_memory().expect("should open")).await;
+
+        let root = std::env::temp_dir().join(format!("storage-test-{}", Uuid::new_v4()));
+        std::fs::create_dir(&root).expect("should create directory");
+        let path = root.join("documents.sqlite");
+        let key = RecordKey::new(&DocumentRef::main(Uuid::new_v4()), RecordKind::Log);
+        let storage = SqliteStorage::open(&path).expect("should open");
+        storage.append(&key, b"a").await.expect("should append");
+        storage.append(&key, b"b").await.expect("should append");
+        drop(storage);
+        let storage = SqliteStorage::open(&path).expect("should open again");
+        assert_eq!(storage.read(&key).await.expect("should read"), b"ab");
+        assert_eq!(storage.metadata(&key).await.expect("should exist").len, 2);
+        drop(storage);
+        std::fs::remove_dir_all(&root).expect("should remove directory");
+    }
+}
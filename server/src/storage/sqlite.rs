@@ -0,0 +1,273 @@
+use super::{RecordKey, RecordKind, RecordMeta, RecordOp, Storage, StorageError};
+use crate::document_file::DocumentRef;
+use crate::revision_history::now_millis;
+use actix_web::error::BlockingError;
+use actix_web::web;
+use async_trait::async_trait;
+use rusqlite::{params, Connection, TransactionBehavior};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Stores every record in a table of one SQLite database file. Each call, including a batch, runs
+/// in a transaction, so a record is never half-written. A record is stored in parts, one row for
+/// each write or append, so that appending doesn't rewrite what is there.
+///
+/// Calls run on the thread pool for blocking calls, so that they don't hold up the async worker.
+/// They take turns on the connection.
+#[derive(Debug)]
+pub struct SqliteStorage {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    /// Opens the database file, creating it and its table if they don't exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    /// Opens a database which lives only as long as the storage, e.g. for tests.
+    pub fn open_in_memory() -> Result<Self, StorageError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(connection: Connection) -> Result<Self, StorageError> {
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = FULL;
+             CREATE TABLE IF NOT EXISTS record_parts (
+                 document_ref TEXT NOT NULL,
+                 kind TEXT NOT NULL,
+                 seq INTEGER NOT NULL,
+                 content BLOB NOT NULL,
+                 modified INTEGER NOT NULL,
+                 PRIMARY KEY (document_ref, kind, seq)
+             );",
+        )?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Runs `f` with the connection on the thread pool for blocking calls.
+    async fn run<T, F>(&self, f: F) -> Result<T, StorageError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, StorageError> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        web::block(move || f(&mut connection.lock().expect("must not be poisoned")))
+            .await
+            .map_err(|err| match err {
+                BlockingError::Error(err) => err,
+                BlockingError::Canceled => StorageError::Backend("Blocking call canceled".into()),
+            })
+    }
+}
+
+fn in_transaction<T>(
+    connection: &mut Connection,
+    f: impl FnOnce(&Connection) -> Result<T, StorageError>,
+) -> Result<T, StorageError> {
+    let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let result = f(&transaction)?;
+    transaction.commit()?;
+    Ok(result)
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(err: rusqlite::Error) -> Self {
+        match err {
+            rusqlite::Error::QueryReturnedNoRows => StorageError::NotFound,
+            _ => StorageError::Backend(err.to_string()),
+        }
+    }
+}
+
+/// Prefixes of kinds of records of which a document has many. Each is followed by the index of
+/// the record, e.g. the checkpoint id.
+const CHECKPOINT_PREFIX: &str = "checkpoint:";
+const REVISIONS_PREFIX: &str = "revisions:";
+const REVISION_SNAPSHOT_PREFIX: &str = "revision_snapshot:";
+
+fn kind_name(kind: RecordKind) -> String {
+    match kind {
+        RecordKind::Snapshot => "snapshot".into(),
+        RecordKind::Backup => "backup".into(),
+        RecordKind::History => "history".into(),
+        RecordKind::Revisions(chunk) => format!("{}{}", REVISIONS_PREFIX, chunk),
+        RecordKind::RevisionSnapshot(chunk) => format!("{}{}", REVISION_SNAPSHOT_PREFIX, chunk),
+        RecordKind::Checkpoint(id) => format!("{}{}", CHECKPOINT_PREFIX, id),
+        RecordKind::Log => "log".into(),
+    }
+}
+
+fn parse_kind_name(name: &str) -> Option<RecordKind> {
+    if let Some(id) = name.strip_prefix(CHECKPOINT_PREFIX) {
+        Some(RecordKind::Checkpoint(id.parse().ok()?))
+    } else if let Some(chunk) = name.strip_prefix(REVISIONS_PREFIX) {
+        Some(RecordKind::Revisions(chunk.parse().ok()?))
+    } else if let Some(chunk) = name.strip_prefix(REVISION_SNAPSHOT_PREFIX) {
+        Some(RecordKind::RevisionSnapshot(chunk.parse().ok()?))
+    } else {
+        RecordKind::SINGLE
+            .iter()
+            .copied()
+            .find(|kind| kind_name(*kind) == name)
+    }
+}
+
+/// Replaces the parts of the record with one.
+fn write_record(
+    connection: &Connection,
+    key: &RecordKey,
+    content: &[u8],
+) -> Result<(), StorageError> {
+    delete_record(connection, key)?;
+    append_record(connection, key, content)
+}
+
+/// Adds a part after the last one.
+fn append_record(
+    connection: &Connection,
+    key: &RecordKey,
+    content: &[u8],
+) -> Result<(), StorageError> {
+    connection.execute(
+        "INSERT INTO record_parts (document_ref, kind, seq, content, modified)
+         SELECT ?1, ?2, COALESCE(MAX(seq) + 1, 0), ?3, ?4
+         FROM record_parts WHERE document_ref = ?1 AND kind = ?2",
+        params![
+            key.document_ref.to_string(),
+            kind_name(key.kind),
+            content,
+            now_millis() as i64
+        ],
+    )?;
+    Ok(())
+}
+
+fn delete_record(connection: &Connection, key: &RecordKey) -> Result<(), StorageError> {
+    connection.execute(
+        "DELETE FROM record_parts WHERE document_ref = ?1 AND kind = ?2",
+        params![key.document_ref.to_string(), kind_name(key.kind)],
+    )?;
+    Ok(())
+}
+
+/// Joins the parts of the record.
+fn read_record(connection: &Connection, key: &RecordKey) -> Result<Vec<u8>, StorageError> {
+    let mut statement = connection.prepare(
+        "SELECT content FROM record_parts WHERE document_ref = ?1 AND kind = ?2
+         ORDER BY seq",
+    )?;
+    let parts = statement.query_map(
+        params![key.document_ref.to_string(), kind_name(key.kind)],
+        |row| row.get::<_, Vec<u8>>(0),
+    )?;
+    let mut content = None::<Vec<u8>>;
+    for part in parts {
+        content.get_or_insert_with(Vec::new).extend(part?);
+    }
+    content.ok_or(StorageError::NotFound)
+}
+
+fn record_metadata(connection: &Connection, key: &RecordKey) -> Result<RecordMeta, StorageError> {
+    let (len, modified) = connection.query_row(
+        "SELECT COALESCE(SUM(length(content)), 0), MAX(modified) FROM record_parts
+         WHERE document_ref = ?1 AND kind = ?2",
+        params![key.document_ref.to_string(), kind_name(key.kind)],
+        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<i64>>(1)?)),
+    )?;
+    let modified = modified.ok_or(StorageError::NotFound)?;
+    Ok(RecordMeta {
+        len: len as u64,
+        modified: Some(UNIX_EPOCH + Duration::from_millis(modified as u64)),
+    })
+}
+
+fn list_records(connection: &Connection) -> Result<Vec<RecordKey>, StorageError> {
+    let mut statement =
+        connection.prepare("SELECT DISTINCT document_ref, kind FROM record_parts")?;
+    let rows = statement.query_map(params![], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+    let mut result = Vec::new();
+    for row in rows {
+        let (document_ref, kind) = row?;
+        match (DocumentRef::from_str(&document_ref), parse_kind_name(&kind)) {
+            (Ok(document_ref), Some(kind)) => result.push(RecordKey { document_ref, kind }),
+            _ => log::warn!("Unknown record {} {} in the database", document_ref, kind),
+        }
+    }
+    Ok(result)
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn read(&self, key: &RecordKey) -> Result<Vec<u8>, StorageError> {
+        let key = key.clone();
+        self.run(move |connection| read_record(connection, &key))
+            .await
+    }
+
+    async fn write(&self, key: &RecordKey, content: &[u8]) -> Result<(), StorageError> {
+        let (key, content) = (key.clone(), content.to_vec());
+        self.run(move |connection| {
+            in_transaction(connection, |connection| {
+                write_record(connection, &key, &content)
+            })
+        })
+        .await
+    }
+
+    async fn append(&self, key: &RecordKey, content: &[u8]) -> Result<(), StorageError> {
+        let (key, content) = (key.clone(), content.to_vec());
+        self.run(move |connection| {
+            in_transaction(connection, |connection| {
+                append_record(connection, &key, &content)
+            })
+        })
+        .await
+    }
+
+    async fn delete(&self, key: &RecordKey) -> Result<(), StorageError> {
+        let key = key.clone();
+        self.run(move |connection| delete_record(connection, &key))
+            .await
+    }
+
+    /// Applies the changes in one transaction.
+    async fn write_batch(&self, ops: &[RecordOp]) -> Result<(), StorageError> {
+        let ops = ops.to_vec();
+        self.run(move |connection| {
+            in_transaction(connection, |connection| {
+                for op in &ops {
+                    match op {
+                        RecordOp::Write(key, content) => write_record(connection, key, content)?,
+                        RecordOp::Append(key, content) => append_record(connection, key, content)?,
+                        RecordOp::Delete(key) => delete_record(connection, key)?,
+                    }
+                }
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    async fn metadata(&self, key: &RecordKey) -> Result<RecordMeta, StorageError> {
+        let key = key.clone();
+        self.run(move |connection| record_metadata(connection, &key))
+            .await
+    }
+
+    async fn list(&self) -> Result<Vec<RecordKey>, StorageError> {
+        self.run(|connection| list_records(connection)).await
+    }
+
+    /// SQLite rolls back interrupted transactions by itself when the database is opened.
+    async fn recover_incomplete_writes(&self) -> Result<Vec<String>, StorageError> {
+        Ok(Vec::new())
+    }
+}
@@ -0,0 +1,351 @@
+use crate::document_file::{document_file_ops, read_document_file, DocumentFileError, DocumentRef};
+use crate::revision_history::{
+    decode_revisions, encode_revision, read_document_at, read_revision_history,
+    revision_history_ops, Revision, RevisionError, RevisionHistory,
+};
+use crate::storage::{RecordKey, RecordKind, RecordOp, Storage, StorageError};
+use system::Document;
+
+// Revisions committed since the last compaction are appended to the log of the document as they
+// are acked, so that they survive a crash. They are stored like in the revision history.
+
+pub async fn append_transaction_log<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+    revision: &Revision,
+) -> Result<(), DocumentFileError> {
+    storage
+        .append(
+            &RecordKey::new(document_ref, RecordKind::Log),
+            &encode_revision(revision),
+        )
+        .await
+        .map_err(DocumentFileError::Storage)
+}
+
+/// Returns the logged revisions. A record half-written by a crash ends the log.
+pub async fn read_transaction_log<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+) -> Result<Vec<Revision>, DocumentFileError> {
+    let content = match storage
+        .read(&RecordKey::new(document_ref, RecordKind::Log))
+        .await
+    {
+        Ok(content) => content,
+        Err(StorageError::NotFound) => return Ok(Vec::new()),
+        Err(err) => return Err(DocumentFileError::Storage(err)),
+    };
+    let decoded = decode_revisions(&content);
+    if decoded.torn || decoded.error.is_some() {
+        log::warn!(
+            "Ignoring a broken record at the end of the transaction log of {}",
+            document_ref
+        );
+    }
+    Ok(decoded.revisions)
+}
+
+/// Saves the document, appends the revisions of the history which are not saved yet, and
+/// empties the log, in one batch. Where batches are not atomic, the history is written first, so
+/// that `open_document` can rebuild the document from it whenever the log is left over.
+pub async fn compact_document<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+    document: &Document,
+    history: &mut RevisionHistory,
+) -> Result<(), DocumentFileError> {
+    let mut ops = revision_history_ops(storage, document_ref, history).await?;
+    ops.extend(document_file_ops(storage, document_ref, document).await);
+    ops.push(RecordOp::Delete(RecordKey::new(
+        document_ref,
+        RecordKind::Log,
+    )));
+    storage
+        .write_batch(&ops)
+        .await
+        .map_err(DocumentFileError::Storage)?;
+    history.mark_saved();
+    Ok(())
+}
+
+/// Reads the document with its history, and replays the logged revisions which the history
+/// doesn't have yet. Nothing is written, so that reading a file has no side effects. The log is
+/// compacted by `recover_document` instead.
+pub async fn open_document<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+) -> Result<(Document, RevisionHistory), DocumentFileError> {
+    replay_document(storage, document_ref)
+        .await
+        .map(|(document, history, _)| (document, history))
+}
+
+/// Opens the document and compacts its log if it has any revision, e.g. when a session starts or
+/// after a stop of the server.
+pub async fn recover_document<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+) -> Result<(Document, RevisionHistory), DocumentFileError> {
+    let (document, mut history, replayed) = replay_document(storage, document_ref).await?;
+    if replayed {
+        compact_document(storage, document_ref, &document, &mut history).await?;
+    }
+    Ok((document, history))
+}
+
+/// Also returns whether the log has revisions which were replayed, i.e. whether it can be
+/// compacted. A log which cannot be replayed is left as is.
+async fn replay_document<S: Storage>(
+    storage: &S,
+    document_ref: &DocumentRef,
+) -> Result<(Document, RevisionHistory, bool), DocumentFileError> {
+    let document = read_document_file(storage, document_ref).await?;
+    let history = read_revision_history(storage, document_ref, &document).await?;
+    let logged = read_transaction_log(storage, document_ref).await?;
+    if logged.is_empty() {
+        return Ok((document, history, false));
+    }
+
+    let mut replayed = history.clone();
+    let latest = replayed.latest_number();
+    for revision in logged.into_iter().filter(|r| r.number > latest) {
+        replayed.push(revision);
+    }
+    match read_document_at(storage, document_ref, &replayed, replayed.latest_number()).await {
+        Ok(replayed_document) => {
+            log::info!(
+                "Replayed the transaction log of {} up to revision {}",
+                document_ref,
+                replayed.latest_number()
+            );
+            Ok((replayed_document, replayed, true))
+        }
+        Err(RevisionError::DocumentFile(err)) => Err(err),
+        Err(err) => {
+            log::error!(
+                "Cannot replay the transaction log of {}: {:?}",
+                document_ref,
+                err
+            );
+            Ok((document, history, false))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStorage, RecordMeta};
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use system::uuid::Uuid;
+    use system::{DocumentMutation, PropKind, PropReadable, PropValue, Transaction};
+
+    fn rename(document: &mut Document, history: &mut RevisionHistory, name: &str) -> Revision {
+        let transaction = Transaction::new(vec![DocumentMutation::UpsertProp(
+            document.document_id(),
+            PropKind::Name,
+            Some(PropValue::String(name.into())),
+        )]);
+        document.process(transaction.clone()).expect("should work");
+        history.record(transaction, None);
+        history.unsaved().last().cloned().expect("just recorded")
+    }
+
+    fn name_of(document: &Document) -> Option<&str> {
+        document.get_string_prop(&document.document_id(), &PropKind::Name)
+    }
+
+    /// Fails to write snapshots while `fail_snapshot` is set, like a server stopping right before
+    /// writing one.
+    #[derive(Default)]
+    struct FailingStorage {
+        inner: MemoryStorage,
+        fail_snapshot: AtomicBool,
+    }
+
+    #[async_trait]
+    impl Storage for FailingStorage {
+        async fn read(&self, key: &RecordKey) -> Result<Vec<u8>, StorageError> {
+            self.inner.read(key).await
+        }
+
+        async fn write(&self, key: &RecordKey, content: &[u8]) -> Result<(), StorageError> {
+            if key.kind == RecordKind::Snapshot && self.fail_snapshot.load(Ordering::SeqCst) {
+                return Err(StorageError::Backend("stopped".into()));
+            }
+            self.inner.write(key, content).await
+        }
+
+        async fn append(&self, key: &RecordKey, content: &[u8]) -> Result<(), StorageError> {
+            self.inner.append(key, content).await
+        }
+
+        async fn delete(&self, key: &RecordKey) -> Result<(), StorageError> {
+            self.inner.delete(key).await
+        }
+
+        async fn metadata(&self, key: &RecordKey) -> Result<RecordMeta, StorageError> {
+            self.inner.metadata(key).await
+        }
+
+        async fn list(&self) -> Result<Vec<RecordKey>, StorageError> {
+            self.inner.list().await
+        }
+
+        async fn recover_incomplete_writes(&self) -> Result<Vec<String>, StorageError> {
+            self.inner.recover_incomplete_writes().await
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_ignore_torn_tail_record() {
+        let storage = MemoryStorage::new();
+        let document_ref = DocumentRef::main(Uuid::new_v4());
+        let mut document = Document::new();
+        let mut history = RevisionHistory::new(&document);
+        for name in &["a", "b"] {
+            let revision = rename(&mut document, &mut history, name);
+            append_transaction_log(&storage, &document_ref, &revision)
+                .await
+                .expect("should append");
+        }
+        let mut torn = 100u32.to_le_bytes().to_vec();
+        torn.extend_from_slice(&[1, 2, 3]);
+        storage
+            .append(&RecordKey::new(&document_ref, RecordKind::Log), &torn)
+            .await
+            .expect("should append");
+
+        let logged = read_transaction_log(&storage, &document_ref)
+            .await
+            .expect("should read");
+        assert_eq!(
+            logged.iter().map(|r| r.number).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_should_replay_newer_logged_revisions_and_compact() {
+        let storage = MemoryStorage::new();
+        let document_ref = DocumentRef::main(Uuid::new_v4());
+        let mut document = Document::new();
+        let mut history = RevisionHistory::new(&document);
+        let first = rename(&mut document, &mut history, "a");
+        compact_document(&storage, &document_ref, &document, &mut history)
+            .await
+            .expect("should compact");
+        let second = rename(&mut document, &mut history, "b");
+        for revision in &[first, second] {
+            append_transaction_log(&storage, &document_ref, revision)
+                .await
+                .expect("should append");
+        }
+
+        let log = RecordKey::new(&document_ref, RecordKind::Log);
+        let (opened, opened_history) = open_document(&storage, &document_ref)
+            .await
+            .expect("should open");
+        assert_eq!(name_of(&opened), Some("b"));
+        assert_eq!(opened_history.latest_number(), 2);
+        assert!(storage.metadata(&log).await.is_ok());
+        let saved = read_document_file(&storage, &document_ref)
+            .await
+            .expect("should read");
+        assert_eq!(name_of(&saved), Some("a"));
+
+        let (recovered, _) = recover_document(&storage, &document_ref)
+            .await
+            .expect("should recover");
+        assert_eq!(name_of(&recovered), Some("b"));
+        assert!(matches!(
+            storage.metadata(&log).await,
+            Err(StorageError::NotFound)
+        ));
+        let saved = read_document_file(&storage, &document_ref)
+            .await
+            .expect("should read");
+        assert_eq!(name_of(&saved), Some("b"));
+    }
+
+    #[tokio::test]
+    async fn it_should_append_only_unsaved_revisions_to_history() {
+        let storage = MemoryStorage::new();
+        let document_ref = DocumentRef::main(Uuid::new_v4());
+        let history_key = RecordKey::new(&document_ref, RecordKind::Revisions(0));
+        let mut document = Document::new();
+        let mut history = RevisionHistory::new(&document);
+        rename(&mut document, &mut history, "a");
+        compact_document(&storage, &document_ref, &document, &mut history)
+            .await
+            .expect("should compact");
+        let saved = storage.read(&history_key).await.expect("should read");
+
+        rename(&mut document, &mut history, "b");
+        compact_document(&storage, &document_ref, &document, &mut history)
+            .await
+            .expect("should compact");
+        let appended = storage.read(&history_key).await.expect("should read");
+        assert!(appended.len() > saved.len());
+        assert_eq!(&appended[..saved.len()], saved.as_slice());
+
+        storage
+            .append(&history_key, &[1, 2])
+            .await
+            .expect("should append");
+        let (mut opened, mut opened_history) = open_document(&storage, &document_ref)
+            .await
+            .expect("should open");
+        assert_eq!(opened_history.latest_number(), 2);
+        let revision = rename(&mut opened, &mut opened_history, "c");
+        compact_document(&storage, &document_ref, &opened, &mut opened_history)
+            .await
+            .expect("should compact");
+        let mut expected = appended;
+        expected.extend(encode_revision(&revision));
+        assert_eq!(
+            storage.read(&history_key).await.expect("should read"),
+            expected,
+            "a torn revision should be dropped when the chunk is written again"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_should_write_history_before_snapshot() {
+        let storage = FailingStorage::default();
+        let document_ref = DocumentRef::main(Uuid::new_v4());
+        let mut document = Document::new();
+        let mut history = RevisionHistory::new(&document);
+        compact_document(&storage, &document_ref, &document, &mut history)
+            .await
+            .expect("should compact");
+        let revision = rename(&mut document, &mut history, "a");
+        append_transaction_log(&storage, &document_ref, &revision)
+            .await
+            .expect("should append");
+
+        storage.fail_snapshot.store(true, Ordering::SeqCst);
+        assert!(
+            compact_document(&storage, &document_ref, &document, &mut history)
+                .await
+                .is_err()
+        );
+        let saved = read_document_file(&storage, &document_ref)
+            .await
+            .expect("should read");
+        assert_eq!(name_of(&saved), None);
+        let saved_history = read_revision_history(&storage, &document_ref, &saved)
+            .await
+            .expect("should read");
+        assert_eq!(saved_history.latest_number(), 1);
+
+        storage.fail_snapshot.store(false, Ordering::SeqCst);
+        let (opened, opened_history) = open_document(&storage, &document_ref)
+            .await
+            .expect("should open");
+        assert_eq!(name_of(&opened), Some("a"));
+        assert_eq!(opened_history.latest_number(), 1);
+    }
+}
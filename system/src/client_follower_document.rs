@@ -3,7 +3,7 @@ use crate::document_command_transaction::convert_command_to_tx;
 use crate::materialize::Materialize;
 use crate::traits::DocumentReadable;
 use crate::transactional_document::TransactionalDocument;
-use crate::{DocumentCommand, DocumentSnapshot, PropReadable};
+use crate::{DocumentCommand, DocumentSnapshot, PropReadable, SnapshotError};
 use std::collections::HashSet;
 
 #[derive(Debug)]
@@ -25,41 +25,47 @@ pub struct TransactionResult {
 }
 
 impl ClientFollowerDocument {
-    pub fn new(snapshot: DocumentSnapshot) -> Self {
-        let tx_document = TransactionalDocument::from_snapshot(snapshot);
+    pub fn new(snapshot: DocumentSnapshot) -> Result<Self, SnapshotError> {
+        let tx_document = TransactionalDocument::from_snapshot(snapshot)?;
         log::debug!(
             "ClientFollowerDocument created: {}",
             tx_document.document_id()
         );
-        Self {
+        Ok(Self {
             tx_document,
             undo_stack: Vec::new(),
             redo_stack: Vec::new(),
-        }
+        })
     }
 
     pub fn handle_command(&mut self, command: DocumentCommand) -> Result<TransactionResult, ()> {
         log::debug!("Handle document command: {:?}", command);
         let tx = convert_command_to_tx(&self.tx_document, command)?;
+        Ok(self.handle_local_transaction(tx))
+    }
 
+    /// Begins a transaction made by this client, such as an import, so that it can be undone like
+    /// commands.
+    pub fn handle_local_transaction(&mut self, tx: Transaction) -> TransactionResult {
         self.undo_stack.push(tx.inverted(&self.tx_document));
         self.redo_stack.clear();
 
         let invalidated_object_ids = self.invalidated_object_ids(&tx);
         self.tx_document.begin(tx.clone());
-        Ok(TransactionResult {
+        TransactionResult {
             invalidated_object_ids,
             transaction: tx,
-        })
+        }
     }
 
     pub fn handle_transaction(&mut self, tx: Transaction) -> Result<TransactionResult, ()> {
         log::info!("Handle others transaction: {:?}", tx);
         let invalidated_object_ids = self.invalidated_object_ids(&tx);
         self.tx_document.begin(tx.clone());
-        self.tx_document
-            .finish(&tx.id, true)
-            .expect("tx from server must be valid");
+        if let Err(err) = self.tx_document.finish(&tx.id, true) {
+            log::error!("Transaction from server cannot be applied: {:?}", err);
+            return Err(());
+        }
         Ok(TransactionResult {
             invalidated_object_ids,
             transaction: tx,
@@ -70,10 +76,16 @@ impl ClientFollowerDocument {
         log::info!("Ack: {:?}", tx_id);
         if let Some(tx) = self.tx_document.get_tx(tx_id) {
             let invalidated_object_ids = self.invalidated_object_ids(&tx);
-            Ok(TransactionResult {
-                invalidated_object_ids,
-                transaction: self.tx_document.finish(tx_id, true).expect("must finish"),
-            })
+            match self.tx_document.finish(tx_id, true) {
+                Ok(transaction) => Ok(TransactionResult {
+                    invalidated_object_ids,
+                    transaction,
+                }),
+                Err(err) => {
+                    log::error!("Acked transaction cannot be applied: {:?}", err);
+                    Err(())
+                }
+            }
         } else {
             Err(())
         }
@@ -100,6 +112,8 @@ impl ClientFollowerDocument {
 
     pub fn undo(&mut self) -> Result<TransactionResult, ()> {
         if let Some(tx) = self.undo_stack.pop() {
+            // The original transaction may be still pending, so it needs a new id.
+            let tx = Transaction::new(tx.items);
             let inverted = tx.inverted(self.readable());
             log::info!("creating redo transaction {:?}", inverted);
             self.redo_stack.push(inverted);
@@ -118,6 +132,8 @@ impl ClientFollowerDocument {
 
     pub fn redo(&mut self) -> Result<TransactionResult, ()> {
         if let Some(tx) = self.redo_stack.pop() {
+            // The original transaction may be still pending, so it needs a new id.
+            let tx = Transaction::new(tx.items);
             let inverted = tx.inverted(self.readable());
             log::info!("creating undo transaction {:?}", inverted);
             self.undo_stack.push(inverted);
@@ -210,7 +226,8 @@ impl Transaction {
 mod tests {
     use super::*;
     use crate::document_command_transaction::convert_command_to_tx;
-    use crate::Document;
+    use crate::{Color, Document, ServerLeaderDocument};
+    use euclid::default::Point2D;
 
     #[test]
     fn it_should_preserve_global_transform_when_changing_parent() {
@@ -220,28 +237,46 @@ mod tests {
         let frame_id = uuid::Uuid::new_v4();
         let oval_id = uuid::Uuid::new_v4();
 
-        document.process(Transaction::new(vec![
-            // frame
-            DocumentMutation::CreateObject(frame_id, ObjectKind::Frame),
-            DocumentMutation::UpsertProp(frame_id, PropKind::PosX, Some(PropValue::Float(10.0))),
-            DocumentMutation::UpsertProp(frame_id, PropKind::PosY, Some(PropValue::Float(20.0))),
-            DocumentMutation::UpsertProp(
-                frame_id,
-                PropKind::Parent,
-                Some(PropValue::Reference(document_id)),
-            ),
-            // oval
-            DocumentMutation::CreateObject(oval_id, ObjectKind::Oval),
-            DocumentMutation::UpsertProp(oval_id, PropKind::PosX, Some(PropValue::Float(100.0))),
-            DocumentMutation::UpsertProp(oval_id, PropKind::PosY, Some(PropValue::Float(100.0))),
-            DocumentMutation::UpsertProp(
-                oval_id,
-                PropKind::Parent,
-                Some(PropValue::Reference(document_id)),
-            ),
-        ]));
+        document
+            .process(Transaction::new(vec![
+                // frame
+                DocumentMutation::CreateObject(frame_id, ObjectKind::Frame),
+                DocumentMutation::UpsertProp(
+                    frame_id,
+                    PropKind::PosX,
+                    Some(PropValue::Float(10.0)),
+                ),
+                DocumentMutation::UpsertProp(
+                    frame_id,
+                    PropKind::PosY,
+                    Some(PropValue::Float(20.0)),
+                ),
+                DocumentMutation::UpsertProp(
+                    frame_id,
+                    PropKind::Parent,
+                    Some(PropValue::Reference(document_id)),
+                ),
+                // oval
+                DocumentMutation::CreateObject(oval_id, ObjectKind::Oval),
+                DocumentMutation::UpsertProp(
+                    oval_id,
+                    PropKind::PosX,
+                    Some(PropValue::Float(100.0)),
+                ),
+                DocumentMutation::UpsertProp(
+                    oval_id,
+                    PropKind::PosY,
+                    Some(PropValue::Float(100.0)),
+                ),
+                DocumentMutation::UpsertProp(
+                    oval_id,
+                    PropKind::Parent,
+                    Some(PropValue::Reference(document_id)),
+                ),
+            ]))
+            .expect("should work");
         let snapshot = DocumentSnapshot::from(&document);
-        let doc = ClientFollowerDocument::new(snapshot);
+        let doc = ClientFollowerDocument::new(snapshot).expect("should work");
 
         let tx = convert_command_to_tx(
             &doc.tx_document,
@@ -280,4 +315,46 @@ mod tests {
             .expect("must exist");
         assert_eq!(pos_y_after, 80.0);
     }
+
+    #[test]
+    fn it_should_undo_deletion_of_object_created_by_pending_transaction() {
+        let mut server = ServerLeaderDocument::new(Document::new());
+        let mut client = ClientFollowerDocument::new(server.snapshot()).expect("should work");
+
+        let create_result = client
+            .handle_command(DocumentCommand::CreateOval {
+                pos: Point2D::new(10.0, 20.0),
+                r_h: 30.0,
+                r_v: 40.0,
+                fill_color: Color::default(),
+            })
+            .expect("should work");
+        let oval_id = match &create_result.transaction.items[0] {
+            DocumentMutation::CreateObject(object_id, _) => *object_id,
+            _ => panic!("unexpected transaction"),
+        };
+
+        let delete_result = client
+            .handle_command(DocumentCommand::DeleteObject { id: oval_id })
+            .expect("should work");
+        assert_eq!(client.readable().is_deleted(&oval_id), Some(true));
+
+        let undo_result = client.undo().expect("should work");
+        assert_eq!(client.readable().is_deleted(&oval_id), Some(false));
+        assert_eq!(client.readable().get_all_props_of_object(&oval_id).len(), 7);
+
+        for tx_result in [create_result, delete_result, undo_result] {
+            let tx_id = tx_result.transaction.id;
+            server
+                .process_transaction(tx_result.transaction)
+                .expect("should work");
+            client.handle_ack(&tx_id).expect("should work");
+        }
+
+        assert_eq!(
+            format!("{:?}", server.materialize_oval(&oval_id)),
+            format!("{:?}", client.materialize_oval(&oval_id))
+        );
+        assert!(client.materialize_oval(&oval_id).is_ok());
+    }
 }
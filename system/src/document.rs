@@ -1,5 +1,7 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 
+use crate::snapshot_format::{decode_document, encode_document, split_header, SnapshotError};
 use crate::traits::{DocumentReadable, PropReadable};
 
 use crate::message::*;
@@ -9,7 +11,6 @@ use uuid::Uuid;
 // TODO: tree - cyclic reference detection
 // TODO: LayeredStorage - 레이어링을 해야 하기 때문에 partial property 를 지원해야 한다.
 //       이 때 mutation_id 까지 같이 고려할 것
-// TODO: serialize to file
 
 // atomicity 를 유지해야 하는 단위로 데이터를 저장하는 key value store. 그 밖에 대해서는 모른다. (undo 라던가)
 
@@ -34,7 +35,10 @@ pub struct Document {
 
 impl Document {
     pub fn new() -> Self {
-        let document_id = uuid::Uuid::new_v4();
+        Self::with_id(uuid::Uuid::new_v4())
+    }
+
+    pub(crate) fn with_id(document_id: ObjectId) -> Self {
         let mut objects = HashMap::new();
         objects.insert(document_id.clone(), ObjectKind::Document);
         Document {
@@ -52,11 +56,61 @@ impl Document {
     }
 
     /// Actually apply each mutations in a transaction to this storage.
-    /// Assume that validation succeeded before calling this method, so this must succeed.
-    pub fn process(&mut self, tx: Transaction) {
+    /// Either every mutation is applied or nothing is applied.
+    pub fn process(&mut self, tx: Transaction) -> Result<(), MutationError> {
+        self.validate(&tx)?;
         for m in &tx.items {
             self.mutate(m);
         }
+        Ok(())
+    }
+
+    /// Check that each mutation can be applied in order, without touching this storage.
+    fn validate(&self, tx: &Transaction) -> Result<(), MutationError> {
+        // Existence of objects and props touched by preceding mutations in the transaction
+        let mut objects = HashMap::<ObjectId, bool>::new();
+        let mut props = HashMap::<(ObjectId, PropKind), bool>::new();
+
+        for (index, mutation) in tx.items.iter().enumerate() {
+            let error = |reason| MutationError {
+                index,
+                mutation: mutation.clone(),
+                reason,
+            };
+            match mutation {
+                DocumentMutation::CreateObject(object_id, _) => {
+                    let exists = objects
+                        .get(object_id)
+                        .cloned()
+                        .unwrap_or_else(|| self.objects.contains_key(object_id));
+                    if exists {
+                        return Err(error(MutationErrorReason::ObjectAlreadyExists));
+                    }
+                    objects.insert(*object_id, true);
+                }
+                DocumentMutation::DeleteObject(object_id) => {
+                    let exists = objects
+                        .get(object_id)
+                        .cloned()
+                        .unwrap_or_else(|| self.objects.contains_key(object_id));
+                    if !exists {
+                        return Err(error(MutationErrorReason::ObjectNotFound));
+                    }
+                    objects.insert(*object_id, false);
+                }
+                DocumentMutation::UpsertProp(object_id, prop_kind, prop_value_opt) => {
+                    let idx_key = (*object_id, *prop_kind);
+                    let exists = props.get(&idx_key).cloned().unwrap_or_else(|| {
+                        self.idx_by_object_id_and_prop_kind.contains_key(&idx_key)
+                    });
+                    if prop_value_opt.is_none() && !exists {
+                        return Err(error(MutationErrorReason::PropNotFound));
+                    }
+                    props.insert(idx_key, prop_value_opt.is_some());
+                }
+            }
+        }
+        Ok(())
     }
 
     fn mutate(&mut self, mutation: &DocumentMutation) {
@@ -65,11 +119,9 @@ impl Document {
                 self.objects.insert(object_id.clone(), object_kind.clone());
             }
             DocumentMutation::UpsertProp(object_id, prop_kind, prop_value_opt) => {
+                let idx_key = &(*object_id, *prop_kind);
                 if let Some(prop_value) = prop_value_opt {
-                    if let Some(record_id) = self
-                        .idx_by_object_id_and_prop_kind
-                        .get(&(object_id.clone(), prop_kind.clone()))
-                    {
+                    if let Some(record_id) = self.idx_by_object_id_and_prop_kind.get(idx_key) {
                         // Update prop
                         let record = self.props.get_mut(record_id).expect("must exist");
                         *record = Record {
@@ -90,14 +142,10 @@ impl Document {
                         );
                         self.create_index_item(&record_id, object_id, prop_kind);
                     }
-                } else {
+                } else if let Some(record_id) =
+                    self.idx_by_object_id_and_prop_kind.get(idx_key).cloned()
+                {
                     // Delete prop
-                    let idx_key = &(object_id.clone(), prop_kind.clone());
-                    let record_id = self
-                        .idx_by_object_id_and_prop_kind
-                        .get(idx_key)
-                        .expect("must exist")
-                        .clone();
                     self.props.remove(&record_id);
                     self.delete_index_item(&record_id, object_id, prop_kind);
                 }
@@ -198,6 +246,22 @@ impl DocumentReadable for Document {
     }
 }
 
+/// Describes which mutation of a transaction cannot be applied and why.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MutationError {
+    /// Position of the mutation in `Transaction::items`
+    pub index: usize,
+    pub mutation: DocumentMutation,
+    pub reason: MutationErrorReason,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MutationErrorReason {
+    ObjectAlreadyExists,
+    ObjectNotFound,
+    PropNotFound,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct DocumentSnapshot {
     content: Vec<u8>,
@@ -211,6 +275,11 @@ impl DocumentSnapshot {
     pub fn from_vec(content: Vec<u8>) -> Self {
         Self { content }
     }
+
+    /// Checks the header and the checksum without decoding the document.
+    pub fn verify(&self) -> Result<(), SnapshotError> {
+        split_header(&self.content).map(|_| ())
+    }
 }
 
 impl std::fmt::Debug for DocumentSnapshot {
@@ -224,13 +293,64 @@ impl std::fmt::Debug for DocumentSnapshot {
 impl From<&Document> for DocumentSnapshot {
     fn from(d: &Document) -> Self {
         DocumentSnapshot {
-            content: bincode::serialize(d).expect("compatible"), // TODO
+            content: encode_document(d),
         }
     }
 }
 
-impl From<&DocumentSnapshot> for Document {
-    fn from(snapshot: &DocumentSnapshot) -> Self {
-        bincode::deserialize(&snapshot.content).expect("compatible")
+impl TryFrom<&DocumentSnapshot> for Document {
+    type Error = SnapshotError;
+
+    fn try_from(snapshot: &DocumentSnapshot) -> Result<Self, Self::Error> {
+        decode_document(&snapshot.content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_reject_transaction_deleting_prop_twice() {
+        let mut document = Document::new();
+        let document_id = document.document_id();
+        document
+            .process(Transaction::new(vec![DocumentMutation::UpsertProp(
+                document_id,
+                PropKind::Name,
+                Some(PropValue::String("name".into())),
+            )]))
+            .expect("should work");
+
+        let result = document.process(Transaction::new(vec![
+            DocumentMutation::UpsertProp(document_id, PropKind::Name, None),
+            DocumentMutation::UpsertProp(document_id, PropKind::Name, None),
+        ]));
+
+        let error = result.expect_err("should fail");
+        assert_eq!(error.index, 1);
+        assert_eq!(error.reason, MutationErrorReason::PropNotFound);
+        // Nothing should be applied.
+        assert_eq!(
+            document.get_string_prop(&document_id, &PropKind::Name),
+            Some("name")
+        );
+    }
+
+    #[test]
+    fn it_should_reject_deleting_unknown_object() {
+        let mut document = Document::new();
+        let object_id = uuid::Uuid::new_v4();
+
+        let error = document
+            .process(Transaction::new(vec![
+                DocumentMutation::CreateObject(object_id, ObjectKind::Oval),
+                DocumentMutation::DeleteObject(object_id),
+                DocumentMutation::DeleteObject(object_id),
+            ]))
+            .expect_err("should fail");
+        assert_eq!(error.index, 2);
+        assert_eq!(error.reason, MutationErrorReason::ObjectNotFound);
+        assert_eq!(document.get_object_kind(&object_id), None);
     }
 }
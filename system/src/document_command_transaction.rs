@@ -164,7 +164,7 @@ pub fn convert_command_to_tx<R: PropReadable + DocumentReadable>(
     }
 }
 
-fn create_last_index_of_parent<R: PropReadable + DocumentReadable>(
+pub(crate) fn create_last_index_of_parent<R: PropReadable + DocumentReadable>(
     readable: &R,
     parent_id: &ObjectId,
 ) -> Base95 {
@@ -0,0 +1,288 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::document::{Document, DocumentSnapshot};
+use crate::message::*;
+use crate::snapshot_format::SnapshotError;
+use crate::traits::PropReadable;
+
+/// Changes which turn one version of a document into another.
+#[derive(Debug, Clone)]
+pub struct DocumentDiff {
+    /// Deletions first, then creations, then prop changes, so that it can be processed in order
+    pub transaction: Transaction,
+    pub summary: Vec<ChangeSummary>,
+}
+
+/// Human-readable change of an object. `name` is taken from the version where the object exists.
+#[derive(Debug, Clone)]
+pub enum ChangeSummary {
+    Created {
+        id: ObjectId,
+        kind: ObjectKind,
+        name: Option<String>,
+    },
+    Deleted {
+        id: ObjectId,
+        kind: ObjectKind,
+        name: Option<String>,
+    },
+    Updated {
+        id: ObjectId,
+        kind: ObjectKind,
+        name: Option<String>,
+        /// Prop, value before and value after
+        props: Vec<(PropKind, Option<PropValue>, Option<PropValue>)>,
+    },
+}
+
+impl fmt::Display for ChangeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (verb, id, kind, name) = match self {
+            ChangeSummary::Created { id, kind, name } => ("Created", id, kind, name),
+            ChangeSummary::Deleted { id, kind, name } => ("Deleted", id, kind, name),
+            ChangeSummary::Updated { id, kind, name, .. } => ("Updated", id, kind, name),
+        };
+        write!(f, "{} {:?}", verb, kind)?;
+        if let Some(name) = name {
+            write!(f, " {:?}", name)?;
+        }
+        write!(f, " ({})", id)?;
+        if let ChangeSummary::Updated { props, .. } = self {
+            let props = props
+                .iter()
+                .map(|(prop_kind, before, after)| {
+                    format!(
+                        "{:?} {} -> {}",
+                        prop_kind,
+                        display_prop_value(before),
+                        display_prop_value(after)
+                    )
+                })
+                .collect::<Vec<_>>();
+            write!(f, ": {}", props.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn display_prop_value(prop_value: &Option<PropValue>) -> String {
+    match prop_value {
+        None => "(none)".into(),
+        Some(PropValue::String(s)) => format!("{:?}", s),
+        Some(PropValue::Float(v)) => v.to_string(),
+        Some(PropValue::Reference(id)) => id.to_string(),
+        Some(PropValue::Color(color)) => color.to_hex(),
+    }
+}
+
+/// Objects which are not deleted, with their kinds.
+pub(crate) fn live_objects<R: PropReadable>(readable: &R) -> BTreeMap<ObjectId, ObjectKind> {
+    readable
+        .containing_objects()
+        .filter(|object_id| !readable.is_deleted(object_id).unwrap_or(true))
+        .filter_map(|object_id| {
+            readable
+                .get_object_kind(object_id)
+                .map(|kind| (*object_id, kind.clone()))
+        })
+        .collect()
+}
+
+pub(crate) fn props_of<R: PropReadable>(
+    readable: &R,
+    object_id: &ObjectId,
+) -> BTreeMap<PropKind, PropValue> {
+    readable
+        .get_all_props_of_object(object_id)
+        .into_iter()
+        .filter_map(|(prop_kind, prop_value)| prop_value.map(|v| (prop_kind, v)))
+        .collect()
+}
+
+fn name_of<R: PropReadable>(readable: &R, object_id: &ObjectId) -> Option<String> {
+    readable
+        .get_string_prop(object_id, &PropKind::Name)
+        .map(|name| name.into())
+}
+
+/// Returns the smallest transaction which turns `from` into `to`, which are versions of the same
+/// document. Deleted objects lose their props first, like `DocumentCommand::DeleteObject` does.
+/// An object whose kind differs is deleted and created again.
+pub fn diff_documents<F: PropReadable, T: PropReadable>(from: &F, to: &T) -> DocumentDiff {
+    let from_objects = live_objects(from);
+    let to_objects = live_objects(to);
+
+    let mut deletions = Vec::new();
+    let mut creations = Vec::new();
+    let mut upserts = Vec::new();
+    let mut summary = Vec::new();
+
+    for (object_id, kind) in &from_objects {
+        if to_objects.get(object_id) == Some(kind) {
+            continue;
+        }
+        for prop_kind in props_of(from, object_id).keys() {
+            deletions.push(DocumentMutation::UpsertProp(
+                *object_id,
+                *prop_kind,
+                None,
+            ));
+        }
+        deletions.push(DocumentMutation::DeleteObject(*object_id));
+        summary.push(ChangeSummary::Deleted {
+            id: *object_id,
+            kind: kind.clone(),
+            name: name_of(from, object_id),
+        });
+    }
+
+    for (object_id, kind) in &to_objects {
+        let to_props = props_of(to, object_id);
+        if from_objects.get(object_id) != Some(kind) {
+            creations.push(DocumentMutation::CreateObject(
+                *object_id,
+                kind.clone(),
+            ));
+            for (prop_kind, prop_value) in to_props {
+                upserts.push(DocumentMutation::UpsertProp(
+                    *object_id,
+                    prop_kind,
+                    Some(prop_value),
+                ));
+            }
+            summary.push(ChangeSummary::Created {
+                id: *object_id,
+                kind: kind.clone(),
+                name: name_of(to, object_id),
+            });
+            continue;
+        }
+
+        let from_props = props_of(from, object_id);
+        let prop_kinds = from_props
+            .keys()
+            .chain(to_props.keys())
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        let mut changed_props = Vec::new();
+        for prop_kind in prop_kinds {
+            let before = from_props.get(&prop_kind).cloned();
+            let after = to_props.get(&prop_kind).cloned();
+            if before != after {
+                upserts.push(DocumentMutation::UpsertProp(
+                    *object_id,
+                    prop_kind,
+                    after.clone(),
+                ));
+                changed_props.push((prop_kind, before, after));
+            }
+        }
+        if !changed_props.is_empty() {
+            summary.push(ChangeSummary::Updated {
+                id: *object_id,
+                kind: kind.clone(),
+                name: name_of(to, object_id),
+                props: changed_props,
+            });
+        }
+    }
+
+    let mut items = deletions;
+    items.extend(creations);
+    items.extend(upserts);
+    DocumentDiff {
+        transaction: Transaction::new(items),
+        summary,
+    }
+}
+
+pub fn diff_snapshots(
+    from: &DocumentSnapshot,
+    to: &DocumentSnapshot,
+) -> Result<DocumentDiff, SnapshotError> {
+    let from = Document::try_from(from)?;
+    let to = Document::try_from(to)?;
+    Ok(diff_documents(&from, &to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DocumentReadable;
+
+    fn upsert(object_id: ObjectId, prop_kind: PropKind, prop_value: PropValue) -> DocumentMutation {
+        DocumentMutation::UpsertProp(object_id, prop_kind, Some(prop_value))
+    }
+
+    #[test]
+    fn it_should_turn_one_version_into_another() {
+        let mut from = Document::new();
+        let document_id = from.document_id();
+        let kept_id = uuid::Uuid::new_v4();
+        let deleted_id = uuid::Uuid::new_v4();
+        from.process(Transaction::new(vec![
+            DocumentMutation::CreateObject(kept_id, ObjectKind::Oval),
+            upsert(kept_id, PropKind::Parent, PropValue::Reference(document_id)),
+            upsert(kept_id, PropKind::Name, PropValue::String("Dot".into())),
+            upsert(kept_id, PropKind::PosX, PropValue::Float(1.0)),
+            upsert(kept_id, PropKind::PosY, PropValue::Float(2.0)),
+            DocumentMutation::CreateObject(deleted_id, ObjectKind::Frame),
+            upsert(
+                deleted_id,
+                PropKind::Parent,
+                PropValue::Reference(document_id),
+            ),
+        ]))
+        .expect("should work");
+
+        let mut to = from.clone();
+        let created_id = uuid::Uuid::new_v4();
+        to.process(Transaction::new(vec![
+            DocumentMutation::UpsertProp(deleted_id, PropKind::Parent, None),
+            DocumentMutation::DeleteObject(deleted_id),
+            DocumentMutation::CreateObject(created_id, ObjectKind::Frame),
+            upsert(
+                created_id,
+                PropKind::Parent,
+                PropValue::Reference(document_id),
+            ),
+            upsert(kept_id, PropKind::PosX, PropValue::Float(3.0)),
+            DocumentMutation::UpsertProp(kept_id, PropKind::PosY, None),
+        ]))
+        .expect("should work");
+
+        let diff = diff_documents(&from, &to);
+        assert_eq!(diff.transaction.items.len(), 6);
+        assert_eq!(diff.summary.len(), 3);
+        assert!(diff.summary.iter().any(|s| s.to_string()
+            == format!(
+                "Updated Oval \"Dot\" ({}): PosX 1 -> 3, PosY 2 -> (none)",
+                kept_id
+            )));
+
+        let mut patched = from.clone();
+        patched.process(diff.transaction).expect("should be valid");
+        assert!(diff_documents(&patched, &to).transaction.items.is_empty());
+        assert!(diff_documents(&to, &to).summary.is_empty());
+    }
+
+    #[test]
+    fn it_should_diff_snapshots() {
+        let from = Document::new();
+        let mut to = from.clone();
+        to.process(Transaction::new(vec![upsert(
+            from.document_id(),
+            PropKind::Name,
+            PropValue::String("Renamed".into()),
+        )]))
+        .expect("should work");
+
+        let diff = diff_snapshots(&from.snapshot(), &to.snapshot()).expect("should diff");
+        assert!(matches!(
+            diff.transaction.items.as_slice(),
+            [DocumentMutation::UpsertProp(_, PropKind::Name, Some(_))]
+        ));
+    }
+}
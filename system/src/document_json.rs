@@ -0,0 +1,221 @@
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Serialize};
+
+use crate::document::{Document, MutationError};
+use crate::message::*;
+use crate::traits::{DocumentReadable, PropReadable};
+
+/// Human readable representation of a whole document.
+///
+/// Objects and props are sorted by their keys, so the same document is always written the same way.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentJson {
+    pub document_id: ObjectId,
+    pub objects: BTreeMap<ObjectId, ObjectJson>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectJson {
+    pub kind: ObjectKind,
+    pub props: BTreeMap<PropKind, PropValue>,
+}
+
+#[derive(Debug)]
+pub enum DocumentJsonError {
+    /// `document_id` doesn't point to an object of `ObjectKind::Document`
+    InvalidDocumentObject,
+    InvalidMutation(MutationError),
+}
+
+impl DocumentJson {
+    /// Props of objects which don't exist are not part of the document, so they are not included.
+    pub fn from_readable<R: PropReadable + DocumentReadable>(readable: &R) -> Self {
+        let object_ids = readable
+            .containing_objects()
+            .filter(|object_id| !readable.is_deleted(object_id).unwrap_or(false))
+            .cloned()
+            .collect::<HashSet<_>>();
+
+        let objects = object_ids
+            .iter()
+            .filter_map(|object_id| {
+                readable.get_object_kind(object_id).map(|kind| {
+                    let props = readable
+                        .get_all_props_of_object(object_id)
+                        .into_iter()
+                        .filter_map(|(prop_kind, prop_value_opt)| {
+                            prop_value_opt.map(|prop_value| (prop_kind, prop_value))
+                        })
+                        .collect();
+                    (
+                        *object_id,
+                        ObjectJson {
+                            kind: kind.clone(),
+                            props,
+                        },
+                    )
+                })
+            })
+            .collect();
+
+        Self {
+            document_id: readable.document_id(),
+            objects,
+        }
+    }
+}
+
+impl From<&Document> for DocumentJson {
+    fn from(document: &Document) -> Self {
+        Self::from_readable(document)
+    }
+}
+
+impl TryFrom<&DocumentJson> for Document {
+    type Error = DocumentJsonError;
+
+    fn try_from(json: &DocumentJson) -> Result<Self, Self::Error> {
+        match json.objects.get(&json.document_id) {
+            Some(ObjectJson {
+                kind: ObjectKind::Document,
+                ..
+            }) => {}
+            _ => return Err(DocumentJsonError::InvalidDocumentObject),
+        }
+
+        let mut mutations = Vec::new();
+        for (object_id, object) in &json.objects {
+            if object_id != &json.document_id {
+                mutations.push(DocumentMutation::CreateObject(
+                    *object_id,
+                    object.kind.clone(),
+                ));
+            }
+            for (prop_kind, prop_value) in &object.props {
+                mutations.push(DocumentMutation::UpsertProp(
+                    *object_id,
+                    *prop_kind,
+                    Some(prop_value.clone()),
+                ));
+            }
+        }
+
+        let mut document = Document::with_id(json.document_id);
+        document
+            .process(Transaction::new(mutations))
+            .map_err(DocumentJsonError::InvalidMutation)?;
+        Ok(document)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serde_json;
+
+    fn sample_document() -> (Document, ObjectId, ObjectId) {
+        let mut document = Document::new();
+        let document_id = document.document_id();
+        let frame_id = uuid::Uuid::new_v4();
+        let oval_id = uuid::Uuid::new_v4();
+        document
+            .process(Transaction::new(vec![
+                DocumentMutation::UpsertProp(
+                    document_id,
+                    PropKind::Name,
+                    Some(PropValue::String("sample".into())),
+                ),
+                DocumentMutation::CreateObject(frame_id, ObjectKind::Frame),
+                DocumentMutation::UpsertProp(
+                    frame_id,
+                    PropKind::Parent,
+                    Some(PropValue::Reference(document_id)),
+                ),
+                DocumentMutation::UpsertProp(
+                    frame_id,
+                    PropKind::Width,
+                    Some(PropValue::Float(0.1)),
+                ),
+                DocumentMutation::CreateObject(oval_id, ObjectKind::Oval),
+                DocumentMutation::UpsertProp(
+                    oval_id,
+                    PropKind::Parent,
+                    Some(PropValue::Reference(frame_id)),
+                ),
+                DocumentMutation::UpsertProp(
+                    oval_id,
+                    PropKind::FillColor,
+                    Some(PropValue::Color(Color { r: 1, g: 2, b: 3 })),
+                ),
+            ]))
+            .expect("should work");
+        (document, frame_id, oval_id)
+    }
+
+    #[test]
+    fn it_should_round_trip_through_json() {
+        let (document, frame_id, oval_id) = sample_document();
+
+        let json = serde_json::to_string_pretty(&DocumentJson::from(&document)).expect("");
+        let parsed = serde_json::from_str::<DocumentJson>(&json).expect("should parse");
+        let restored = Document::try_from(&parsed).expect("should convert");
+
+        assert_eq!(restored.document_id(), document.document_id());
+        assert_eq!(
+            restored.get_string_prop(&document.document_id(), &PropKind::Name),
+            Some("sample")
+        );
+        assert_eq!(
+            restored.get_object_kind(&frame_id),
+            Some(&ObjectKind::Frame)
+        );
+        assert_eq!(
+            restored.get_float_prop(&frame_id, &PropKind::Width),
+            Some(&0.1)
+        );
+        assert_eq!(
+            restored.get_id_prop(&oval_id, &PropKind::Parent),
+            Some(&frame_id)
+        );
+        assert_eq!(
+            format!(
+                "{:?}",
+                restored.get_color_prop(&oval_id, &PropKind::FillColor)
+            ),
+            format!("{:?}", Some(Color { r: 1, g: 2, b: 3 }))
+        );
+
+        // Exporting again should produce exactly the same text.
+        let json_again = serde_json::to_string_pretty(&DocumentJson::from(&restored)).expect("");
+        assert_eq!(json, json_again);
+    }
+
+    #[test]
+    fn it_should_key_objects_and_props_by_name() {
+        let (document, frame_id, _) = sample_document();
+
+        let value = serde_json::to_value(DocumentJson::from(&document)).expect("");
+        assert_eq!(
+            value["objects"][frame_id.to_string()]["kind"],
+            serde_json::json!("Frame")
+        );
+        assert_eq!(
+            value["objects"][frame_id.to_string()]["props"]["Parent"],
+            serde_json::json!({ "Reference": document.document_id().to_string() })
+        );
+    }
+
+    #[test]
+    fn it_should_reject_json_without_document_object() {
+        let (document, frame_id, _) = sample_document();
+        let mut json = DocumentJson::from(&document);
+        json.document_id = frame_id;
+
+        assert!(matches!(
+            Document::try_from(&json),
+            Err(DocumentJsonError::InvalidDocumentObject)
+        ));
+    }
+}
@@ -0,0 +1,576 @@
+use std::collections::{HashMap, HashSet};
+
+use base95::Base95;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::document::Document;
+use crate::materialize::Materialize;
+use crate::message::*;
+use crate::scene::{build_scene, SceneNode};
+use crate::traits::{DocumentReadable, PropReadable};
+use crate::MutationError;
+
+// Excalidraw places every element in scene coordinates, and frames can't be nested. Elements refer
+// to their frames by `frameId` instead.
+
+const SCENE_TYPE: &str = "excalidraw";
+const SCENE_VERSION: u32 = 2;
+const TRANSPARENT: &str = "transparent";
+const FRAME_STROKE_COLOR: &str = "#bbbbbb";
+
+/// Scene file of Excalidraw, usually saved with the extension `.excalidraw`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExcalidrawScene {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub version: u32,
+    #[serde(default)]
+    pub source: String,
+    pub elements: Vec<ExcalidrawElement>,
+    #[serde(default)]
+    pub app_state: Value,
+    #[serde(default)]
+    pub files: Value,
+}
+
+/// Fields of an element used for the conversion. Other fields are kept in `extra` as they are.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExcalidrawElement {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    #[serde(default)]
+    pub angle: f32,
+    #[serde(default = "default_stroke_color")]
+    pub stroke_color: String,
+    #[serde(default = "default_background_color")]
+    pub background_color: String,
+    #[serde(default = "default_fill_style")]
+    pub fill_style: String,
+    #[serde(default)]
+    pub frame_id: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub is_deleted: bool,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+fn default_stroke_color() -> String {
+    "#000000".into()
+}
+
+fn default_background_color() -> String {
+    TRANSPARENT.into()
+}
+
+fn default_fill_style() -> String {
+    "solid".into()
+}
+
+/// Element of the scene which is skipped or imported differently. Elements are identified by their
+/// `id`s in the scene.
+#[derive(Debug, Clone, Serialize)]
+pub enum ExcalidrawImportWarning {
+    /// No kind of object matches the element, such as `text` or `arrow`
+    UnsupportedElement {
+        id: String,
+        kind: String,
+    },
+    UnsupportedRotation {
+        id: String,
+    },
+    /// Ovals are always filled with a solid color, so the stroke color is used if the background
+    /// is transparent, and patterns are filled solid
+    UnsupportedFill {
+        id: String,
+    },
+    /// `frameId` doesn't refer to a frame imported from the scene, so the element is put in the
+    /// document
+    UnknownFrame {
+        id: String,
+        frame_id: String,
+    },
+    /// Another element before it has the same `id`, so the element is skipped
+    DuplicateId {
+        id: String,
+    },
+}
+
+#[derive(Debug)]
+pub struct ExcalidrawImport {
+    pub document: Document,
+    pub warnings: Vec<ExcalidrawImportWarning>,
+}
+
+impl ExcalidrawScene {
+    /// Ovals become ellipses and frames become frames, in z-order. Frames in frames become frames
+    /// of their own, since Excalidraw can't nest them.
+    pub fn from_materialize<R, M>(materialize: &M) -> Self
+    where
+        R: PropReadable + DocumentReadable,
+        M: Materialize<R>,
+    {
+        let document_id = materialize.readable().document_id();
+        let nodes = build_scene(materialize, &document_id).unwrap_or_default();
+        let mut elements = Vec::new();
+        for node in &nodes {
+            push_elements(materialize.readable(), node, None, &mut elements);
+        }
+
+        Self {
+            kind: SCENE_TYPE.into(),
+            version: SCENE_VERSION,
+            source: env!("CARGO_PKG_NAME").into(),
+            elements,
+            app_state: json!({ "viewBackgroundColor": "#ffffff" }),
+            files: json!({}),
+        }
+    }
+
+    /// Creates a version of the document `document_id` from the scene, so that it can replace the
+    /// document of a file. Deleted elements are ignored.
+    pub fn to_document(&self, document_id: ObjectId) -> Result<ExcalidrawImport, MutationError> {
+        let mut document = Document::with_id(document_id);
+        let mut warnings = Vec::new();
+
+        let mut ids = HashSet::new();
+        let mut elements = Vec::new();
+        for element in self.elements.iter().filter(|element| !element.is_deleted) {
+            if ids.insert(element.id.as_str()) {
+                elements.push(element);
+            } else {
+                warnings.push(ExcalidrawImportWarning::DuplicateId {
+                    id: element.id.clone(),
+                });
+            }
+        }
+        let frames = elements
+            .iter()
+            .filter(|element| is_frame(element) && element.angle == 0.0)
+            .map(|element| (element.id.as_str(), (uuid::Uuid::new_v4(), *element)))
+            .collect::<HashMap<_, _>>();
+
+        let mut mutations = Vec::new();
+        let mut next_indices = HashMap::new();
+        for element in elements {
+            if element.angle != 0.0 {
+                warnings.push(ExcalidrawImportWarning::UnsupportedRotation {
+                    id: element.id.clone(),
+                });
+                continue;
+            }
+
+            let (parent_id, origin_x, origin_y) =
+                match element.frame_id.as_ref().filter(|_| !is_frame(element)) {
+                    Some(frame_id) => match frames.get(frame_id.as_str()) {
+                        Some((object_id, frame)) => (*object_id, frame.x, frame.y),
+                        None => {
+                            warnings.push(ExcalidrawImportWarning::UnknownFrame {
+                                id: element.id.clone(),
+                                frame_id: frame_id.clone(),
+                            });
+                            (document_id, 0.0, 0.0)
+                        }
+                    },
+                    None => (document_id, 0.0, 0.0),
+                };
+            let (x, y) = (element.x - origin_x, element.y - origin_y);
+
+            let (id, kind, props) = match element.kind.as_str() {
+                "ellipse" => {
+                    let (r_h, r_v) = (element.width / 2.0, element.height / 2.0);
+                    let (fill_color, is_exact) = element_fill_color(element);
+                    if !is_exact {
+                        warnings.push(ExcalidrawImportWarning::UnsupportedFill {
+                            id: element.id.clone(),
+                        });
+                    }
+                    (
+                        uuid::Uuid::new_v4(),
+                        ObjectKind::Oval,
+                        vec![
+                            (PropKind::PosX, PropValue::Float(x + r_h)),
+                            (PropKind::PosY, PropValue::Float(y + r_v)),
+                            (PropKind::RadiusH, PropValue::Float(r_h)),
+                            (PropKind::RadiusV, PropValue::Float(r_v)),
+                            (PropKind::FillColor, PropValue::Color(fill_color)),
+                        ],
+                    )
+                }
+                "frame" | "magicframe" => {
+                    let mut props = vec![
+                        (PropKind::PosX, PropValue::Float(x)),
+                        (PropKind::PosY, PropValue::Float(y)),
+                        (PropKind::Width, PropValue::Float(element.width)),
+                        (PropKind::Height, PropValue::Float(element.height)),
+                    ];
+                    if let Some(name) = &element.name {
+                        props.push((PropKind::Name, PropValue::String(name.clone())));
+                    }
+                    (frames[element.id.as_str()].0, ObjectKind::Frame, props)
+                }
+                _ => {
+                    warnings.push(ExcalidrawImportWarning::UnsupportedElement {
+                        id: element.id.clone(),
+                        kind: element.kind.clone(),
+                    });
+                    continue;
+                }
+            };
+
+            let next_index = next_indices.entry(parent_id).or_insert_with(Base95::mid);
+            let index = std::mem::replace(next_index, Base95::mid());
+            *next_index = Base95::avg_with_one(&index);
+
+            mutations.push(DocumentMutation::CreateObject(id, kind));
+            mutations.push(DocumentMutation::UpsertProp(
+                id,
+                PropKind::Parent,
+                Some(PropValue::Reference(parent_id)),
+            ));
+            mutations.push(DocumentMutation::UpsertProp(
+                id,
+                PropKind::Index,
+                Some(PropValue::String(index.to_string())),
+            ));
+            for (prop_kind, prop_value) in props {
+                mutations.push(DocumentMutation::UpsertProp(
+                    id,
+                    prop_kind,
+                    Some(prop_value),
+                ));
+            }
+        }
+
+        document.process(Transaction::new(mutations))?;
+        Ok(ExcalidrawImport { document, warnings })
+    }
+}
+
+impl From<&Document> for ExcalidrawScene {
+    fn from(document: &Document) -> Self {
+        Self::from_materialize(document)
+    }
+}
+
+fn is_frame(element: &ExcalidrawElement) -> bool {
+    element.kind == "frame" || element.kind == "magicframe"
+}
+
+/// Returns the color to fill an oval, and whether the element is filled with it exactly.
+fn element_fill_color(element: &ExcalidrawElement) -> (Color, bool) {
+    match Color::from_hex(&element.background_color) {
+        Some(color) => (color, element.fill_style == "solid"),
+        None => (
+            Color::from_hex(&element.stroke_color).unwrap_or_default(),
+            false,
+        ),
+    }
+}
+
+fn push_elements<R: PropReadable>(
+    readable: &R,
+    node: &SceneNode,
+    frame_id: Option<&ObjectId>,
+    elements: &mut Vec<ExcalidrawElement>,
+) {
+    match node {
+        SceneNode::Oval {
+            id,
+            transform,
+            r_h,
+            r_v,
+            fill_color,
+        } => elements.push(new_element(
+            id,
+            "ellipse",
+            (
+                transform.m31 - r_h,
+                transform.m32 - r_v,
+                r_h * 2.0,
+                r_v * 2.0,
+            ),
+            fill_color.to_hex(),
+            fill_color.to_hex(),
+            frame_id,
+            None,
+        )),
+        SceneNode::Frame {
+            id,
+            transform,
+            w,
+            h,
+            children,
+        } => {
+            elements.push(new_element(
+                id,
+                "frame",
+                (transform.m31, transform.m32, *w, *h),
+                FRAME_STROKE_COLOR.into(),
+                TRANSPARENT.into(),
+                frame_id,
+                readable.get_string_prop(id, &PropKind::Name),
+            ));
+            for child in children {
+                push_elements(readable, child, Some(id), elements);
+            }
+        }
+    }
+}
+
+fn new_element(
+    id: &ObjectId,
+    kind: &str,
+    (x, y, width, height): (f32, f32, f32, f32),
+    stroke_color: String,
+    background_color: String,
+    frame_id: Option<&ObjectId>,
+    name: Option<&str>,
+) -> ExcalidrawElement {
+    // Excalidraw fills in missing fields when it loads a scene, but older versions don't.
+    let extra = json!({
+        "strokeWidth": 1,
+        "strokeStyle": "solid",
+        "roughness": 0,
+        "opacity": 100,
+        "groupIds": [],
+        "roundness": null,
+        "seed": 1,
+        "version": 1,
+        "versionNonce": 0,
+        "boundElements": null,
+        "updated": 0,
+        "link": null,
+        "locked": false,
+    });
+    ExcalidrawElement {
+        id: id.to_string(),
+        kind: kind.into(),
+        x,
+        y,
+        width,
+        height,
+        angle: 0.0,
+        stroke_color,
+        background_color,
+        fill_style: default_fill_style(),
+        // Frames are never nested in Excalidraw.
+        frame_id: frame_id
+            .filter(|_| kind != "frame")
+            .map(|frame_id| frame_id.to_string()),
+        name: name.map(|name| name.into()),
+        is_deleted: false,
+        extra: match extra {
+            Value::Object(extra) => extra,
+            _ => unreachable!(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serde_json;
+
+    fn sample_document() -> (Document, ObjectId, ObjectId) {
+        let mut document = Document::new();
+        let document_id = document.document_id();
+        let frame_id = uuid::Uuid::new_v4();
+        let oval_id = uuid::Uuid::new_v4();
+        document
+            .process(Transaction::new(vec![
+                DocumentMutation::CreateObject(frame_id, ObjectKind::Frame),
+                DocumentMutation::UpsertProp(
+                    frame_id,
+                    PropKind::Parent,
+                    Some(PropValue::Reference(document_id)),
+                ),
+                DocumentMutation::UpsertProp(
+                    frame_id,
+                    PropKind::Name,
+                    Some(PropValue::String("page".into())),
+                ),
+                DocumentMutation::UpsertProp(
+                    frame_id,
+                    PropKind::PosX,
+                    Some(PropValue::Float(100.0)),
+                ),
+                DocumentMutation::CreateObject(oval_id, ObjectKind::Oval),
+                DocumentMutation::UpsertProp(
+                    oval_id,
+                    PropKind::Parent,
+                    Some(PropValue::Reference(frame_id)),
+                ),
+                DocumentMutation::UpsertProp(oval_id, PropKind::PosX, Some(PropValue::Float(5.0))),
+                DocumentMutation::UpsertProp(
+                    oval_id,
+                    PropKind::RadiusH,
+                    Some(PropValue::Float(4.0)),
+                ),
+                DocumentMutation::UpsertProp(
+                    oval_id,
+                    PropKind::FillColor,
+                    Some(PropValue::Color(Color {
+                        r: 224,
+                        g: 49,
+                        b: 49,
+                    })),
+                ),
+            ]))
+            .expect("should work");
+        (document, frame_id, oval_id)
+    }
+
+    #[test]
+    fn it_should_export_objects_in_scene_coordinates() {
+        let (document, frame_id, oval_id) = sample_document();
+
+        let scene = ExcalidrawScene::from(&document);
+        assert_eq!(scene.kind, "excalidraw");
+        assert_eq!(scene.elements.len(), 2);
+
+        let frame = &scene.elements[0];
+        assert_eq!(frame.kind, "frame");
+        assert_eq!(frame.id, frame_id.to_string());
+        assert_eq!(frame.name.as_deref(), Some("page"));
+        assert_eq!(frame.x, 100.0);
+
+        let oval = &scene.elements[1];
+        assert_eq!(oval.kind, "ellipse");
+        assert_eq!(oval.id, oval_id.to_string());
+        assert_eq!(oval.frame_id, Some(frame_id.to_string()));
+        assert_eq!((oval.x, oval.width), (101.0, 8.0));
+        assert_eq!(oval.background_color, "#e03131");
+
+        let value = serde_json::to_value(&scene).expect("");
+        assert_eq!(value["elements"][1]["backgroundColor"], "#e03131");
+        assert_eq!(value["elements"][1]["frameId"], frame_id.to_string());
+        assert_eq!(value["elements"][1]["roughness"], 0);
+    }
+
+    #[test]
+    fn it_should_round_trip_through_scene() {
+        let (document, _, _) = sample_document();
+        let json = serde_json::to_string(&ExcalidrawScene::from(&document)).expect("");
+        let scene = serde_json::from_str::<ExcalidrawScene>(&json).expect("should parse");
+
+        let ExcalidrawImport {
+            document: imported,
+            warnings,
+        } = scene
+            .to_document(document.document_id())
+            .expect("should import");
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(imported.document_id(), document.document_id());
+        let document = imported;
+
+        let frames = document.get_children_indices(&document.document_id());
+        assert_eq!(frames.len(), 1);
+        let frame_id = frames[0].0;
+        assert_eq!(
+            document.get_string_prop(&frame_id, &PropKind::Name),
+            Some("page")
+        );
+        assert_eq!(
+            document.get_float_prop(&frame_id, &PropKind::PosX),
+            Some(&100.0)
+        );
+
+        let ovals = document.get_children_indices(&frame_id);
+        assert_eq!(ovals.len(), 1);
+        let oval_id = ovals[0].0;
+        assert_eq!(
+            document.get_float_prop(&oval_id, &PropKind::PosX),
+            Some(&5.0)
+        );
+        assert_eq!(
+            document.get_float_prop(&oval_id, &PropKind::RadiusH),
+            Some(&4.0)
+        );
+    }
+
+    #[test]
+    fn it_should_report_unsupported_elements() {
+        let scene = serde_json::from_value::<ExcalidrawScene>(json!({
+            "type": "excalidraw",
+            "version": 2,
+            "elements": [
+                { "id": "a", "type": "text", "x": 0, "y": 0, "width": 10, "height": 10,
+                  "text": "hello" },
+                { "id": "b", "type": "ellipse", "x": 0, "y": 0, "width": 10, "height": 10,
+                  "angle": 0.5 },
+                { "id": "c", "type": "ellipse", "x": 0, "y": 0, "width": 10, "height": 20,
+                  "strokeColor": "#1971c2", "backgroundColor": "transparent", "frameId": "x" },
+                { "id": "d", "type": "ellipse", "x": 0, "y": 0, "width": 10, "height": 10,
+                  "isDeleted": true },
+            ],
+        }))
+        .expect("should parse");
+
+        let ExcalidrawImport { document, warnings } = scene
+            .to_document(uuid::Uuid::new_v4())
+            .expect("should import");
+        assert!(matches!(
+            warnings.as_slice(),
+            [
+                ExcalidrawImportWarning::UnsupportedElement { id: a, kind },
+                ExcalidrawImportWarning::UnsupportedRotation { id: b },
+                ExcalidrawImportWarning::UnknownFrame { id: c, .. },
+                ExcalidrawImportWarning::UnsupportedFill { .. },
+            ] if a == "a" && kind == "text" && b == "b" && c == "c"
+        ));
+
+        let children = document.get_children_indices(&document.document_id());
+        assert_eq!(children.len(), 1);
+        let oval_id = children[0].0;
+        assert_eq!(
+            document.get_float_prop(&oval_id, &PropKind::RadiusV),
+            Some(&10.0)
+        );
+        assert_eq!(
+            document
+                .get_color_prop(&oval_id, &PropKind::FillColor)
+                .map(|c| c.to_hex()),
+            Some("#1971c2".into())
+        );
+    }
+
+    #[test]
+    fn it_should_skip_elements_with_duplicate_ids() {
+        let scene = serde_json::from_value::<ExcalidrawScene>(json!({
+            "type": "excalidraw",
+            "version": 2,
+            "elements": [
+                { "id": "f", "type": "frame", "x": 0, "y": 0, "width": 100, "height": 100 },
+                { "id": "f", "type": "frame", "x": 200, "y": 0, "width": 100, "height": 100 },
+                { "id": "o", "type": "ellipse", "x": 10, "y": 10, "width": 10, "height": 10,
+                  "backgroundColor": "#ffc9c9", "fillStyle": "solid", "frameId": "f" },
+            ],
+        }))
+        .expect("should parse");
+
+        let ExcalidrawImport { document, warnings } = scene
+            .to_document(uuid::Uuid::new_v4())
+            .expect("should import");
+        assert!(matches!(
+            warnings.as_slice(),
+            [ExcalidrawImportWarning::DuplicateId { id }] if id == "f"
+        ));
+        let frames = document.get_children_indices(&document.document_id());
+        assert_eq!(frames.len(), 1);
+        assert_eq!(
+            document.get_float_prop(&frames[0].0, &PropKind::PosX),
+            Some(&0.0)
+        );
+        assert_eq!(document.get_children_indices(&frames[0].0).len(), 1);
+    }
+}
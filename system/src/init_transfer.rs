@@ -0,0 +1,248 @@
+use serde::{Deserialize, Serialize};
+
+use crate::message::SessionEvent;
+
+// A large `SessionEvent::Init` is sent as `SessionEvent::InitChunk`s instead, to clients which ask
+// for chunks when they connect. Concatenated bytes of the chunks are the bincode dump of the `Init`
+// event, compressed as the chunks say. Other clients receive the event as is, whatever its size.
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum InitCompression {
+    None,
+    Deflate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InitChunk {
+    pub index: u32,
+    pub count: u32,
+    pub compression: InitCompression,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct InitTransferOptions {
+    /// Compression of the chunks, which the client chooses
+    pub compression: InitCompression,
+    /// `Init` events up to this many bytes are sent as is, whatever the compression
+    pub threshold: usize,
+    /// Upper bound of bytes in a chunk, so each websocket frame stays small
+    pub chunk_size: usize,
+}
+
+impl Default for InitTransferOptions {
+    fn default() -> Self {
+        Self {
+            compression: InitCompression::None,
+            threshold: 64 * 1024,
+            chunk_size: 256 * 1024,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InitTransferError {
+    /// Chunks arrived out of order, or belong to another transfer
+    UnexpectedChunk {
+        expected: u32,
+        index: u32,
+    },
+    Decompress,
+    Decode(bincode::Error),
+    /// Reassembled event is not `SessionEvent::Init`
+    NotInit,
+}
+
+const DEFLATE_LEVEL: u8 = 6;
+
+/// Returns events to send in place of the `Init` event to a client which can reassemble chunks.
+/// It is sent as is if it is not larger than the threshold, or if it is neither compressed nor
+/// larger than a chunk.
+pub fn split_init_event(init: SessionEvent, options: &InitTransferOptions) -> Vec<SessionEvent> {
+    debug_assert!(matches!(init, SessionEvent::Init { .. }));
+    let content = bincode::serialize(&init).expect("SessionEvent must be serializable");
+    if content.len() <= options.threshold
+        || (options.compression == InitCompression::None && content.len() <= options.chunk_size)
+    {
+        return vec![init];
+    }
+
+    let content = match options.compression {
+        InitCompression::None => content,
+        InitCompression::Deflate => miniz_oxide::deflate::compress_to_vec(&content, DEFLATE_LEVEL),
+    };
+    let chunks = content
+        .chunks(options.chunk_size.max(1))
+        .collect::<Vec<_>>();
+    let count = chunks.len() as u32;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, bytes)| {
+            SessionEvent::InitChunk(InitChunk {
+                index: index as u32,
+                count,
+                compression: options.compression,
+                bytes: bytes.to_vec(),
+            })
+        })
+        .collect()
+}
+
+/// Reassembles `SessionEvent::InitChunk`s into the `Init` event.
+#[derive(Debug, Default)]
+pub struct InitAssembler {
+    received: u32,
+    content: Vec<u8>,
+}
+
+impl InitAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the `Init` event once the last chunk is pushed. Chunks must be pushed in order.
+    pub fn push(&mut self, chunk: InitChunk) -> Result<Option<SessionEvent>, InitTransferError> {
+        if chunk.index != self.received || chunk.index >= chunk.count {
+            return Err(InitTransferError::UnexpectedChunk {
+                expected: self.received,
+                index: chunk.index,
+            });
+        }
+        self.received += 1;
+        self.content.extend_from_slice(&chunk.bytes);
+        if self.received < chunk.count {
+            return Ok(None);
+        }
+
+        let content = std::mem::take(&mut self.content);
+        self.received = 0;
+        let content = match chunk.compression {
+            InitCompression::None => content,
+            InitCompression::Deflate => miniz_oxide::inflate::decompress_to_vec(&content)
+                .map_err(|_| InitTransferError::Decompress)?,
+        };
+        match bincode::deserialize(&content).map_err(InitTransferError::Decode)? {
+            init @ SessionEvent::Init { .. } => Ok(Some(init)),
+            _ => Err(InitTransferError::NotInit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Document, DocumentMutation, ObjectKind, SessionSnapshot, Transaction};
+
+    fn init_event(oval_count: usize) -> SessionEvent {
+        let mut document = Document::new();
+        let mutations = (0..oval_count)
+            .map(|_| DocumentMutation::CreateObject(uuid::Uuid::new_v4(), ObjectKind::Oval))
+            .collect();
+        document
+            .process(Transaction::new(mutations))
+            .expect("should work");
+        SessionEvent::Init {
+            session_id: 1,
+            session_snapshot: SessionSnapshot {
+                connections: vec![1, 2],
+            },
+            document_snapshot: (&document).into(),
+        }
+    }
+
+    fn reassemble(events: Vec<SessionEvent>) -> SessionEvent {
+        let mut assembler = InitAssembler::new();
+        let mut init = None;
+        for event in events {
+            assert!(init.is_none());
+            match event {
+                SessionEvent::InitChunk(chunk) => {
+                    init = assembler.push(chunk).expect("should reassemble")
+                }
+                _ => panic!("should be a chunk"),
+            }
+        }
+        init.expect("should be complete")
+    }
+
+    fn document_snapshot(event: &SessionEvent) -> Vec<u8> {
+        match event {
+            SessionEvent::Init {
+                document_snapshot, ..
+            } => document_snapshot.content().to_vec(),
+            _ => panic!("should be Init"),
+        }
+    }
+
+    #[test]
+    fn it_should_send_small_uncompressed_init_as_is() {
+        let options = InitTransferOptions {
+            compression: InitCompression::None,
+            threshold: 0,
+            chunk_size: 1024 * 1024,
+        };
+        let events = split_init_event(init_event(1), &options);
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0], SessionEvent::Init { .. }));
+    }
+
+    #[test]
+    fn it_should_send_init_below_threshold_as_is() {
+        let options = InitTransferOptions {
+            compression: InitCompression::Deflate,
+            threshold: 64 * 1024,
+            chunk_size: 256,
+        };
+        let events = split_init_event(init_event(1), &options);
+        assert!(matches!(events.as_slice(), [SessionEvent::Init { .. }]));
+
+        let events = split_init_event(init_event(1), &InitTransferOptions::default());
+        assert!(matches!(events.as_slice(), [SessionEvent::Init { .. }]));
+    }
+
+    #[test]
+    fn it_should_reassemble_chunks() {
+        for compression in [InitCompression::None, InitCompression::Deflate].iter() {
+            let init = init_event(100);
+            let options = InitTransferOptions {
+                compression: *compression,
+                threshold: 0,
+                chunk_size: 256,
+            };
+            let events = split_init_event(init.clone(), &options);
+            assert!(events.len() > 1);
+            assert_eq!(
+                document_snapshot(&reassemble(events)),
+                document_snapshot(&init)
+            );
+        }
+    }
+
+    #[test]
+    fn it_should_reject_chunks_out_of_order() {
+        let options = InitTransferOptions {
+            compression: InitCompression::Deflate,
+            threshold: 0,
+            chunk_size: 64,
+        };
+        let mut chunks = split_init_event(init_event(100), &options)
+            .into_iter()
+            .map(|event| match event {
+                SessionEvent::InitChunk(chunk) => chunk,
+                _ => panic!("should be a chunk"),
+            });
+        let first = chunks.next().expect("first chunk");
+        let second = chunks.next().expect("second chunk");
+
+        let mut assembler = InitAssembler::new();
+        assert!(matches!(
+            assembler.push(second),
+            Err(InitTransferError::UnexpectedChunk {
+                expected: 0,
+                index: 1
+            })
+        ));
+        assert!(assembler.push(first).expect("should accept").is_none());
+    }
+}
@@ -2,9 +2,20 @@ mod client_follower_document;
 mod document;
 pub mod document_command;
 mod document_command_transaction;
+mod document_diff;
+mod document_json;
+mod excalidraw;
+mod init_transfer;
 pub mod materialize;
+mod merge;
 mod message;
+mod pdf_export;
+mod query;
+mod scene;
 mod server_leader_document;
+mod snapshot_format;
+mod svg_export;
+mod svg_import;
 mod traits;
 mod transaction_manager;
 mod transactional_document;
@@ -12,9 +23,20 @@ mod transactional_document;
 pub use client_follower_document::*;
 pub use document::*;
 pub use document_command::*;
+pub use document_diff::*;
+pub use document_json::*;
+pub use excalidraw::*;
+pub use init_transfer::*;
 pub use materialize::*;
+pub use merge::*;
 pub use message::*;
+pub use pdf_export::*;
+pub use query::*;
+pub use scene::*;
 pub use server_leader_document::*;
+pub use snapshot_format::{SnapshotError, CURRENT_SCHEMA_VERSION};
+pub use svg_export::*;
+pub use svg_import::*;
 pub use traits::*;
 
 pub extern crate bincode;
@@ -1,6 +1,11 @@
-use serde::Serialize;
+use std::collections::HashMap;
 
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::document::Document;
 use crate::message::*;
+use crate::scene::transformed_box;
 use crate::traits::{DocumentReadable, PropReadable};
 
 #[derive(Debug, Clone, Serialize)]
@@ -19,26 +24,142 @@ pub enum ObjectMaterial {
 
 #[derive(Debug, Clone, Serialize)]
 pub struct OvalMaterial {
-    id: ObjectId,
-    name: String,
-    pos_x: f32,
-    pos_y: f32,
-    r_h: f32,
-    r_v: f32,
-    fill_color: Color,
-    index: String,
+    pub id: ObjectId,
+    pub name: String,
+    pub pos_x: f32,
+    pub pos_y: f32,
+    pub r_h: f32,
+    pub r_v: f32,
+    pub fill_color: Color,
+    pub index: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub global: Option<GlobalMaterial>,
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct FrameMaterial {
-    id: ObjectId,
-    name: String,
-    pos_x: f32,
-    pos_y: f32,
-    w: f32,
-    h: f32,
-    index: String,
-    children: Vec<ObjectId>,
+    pub id: ObjectId,
+    pub name: String,
+    pub pos_x: f32,
+    pub pos_y: f32,
+    pub w: f32,
+    pub h: f32,
+    pub index: String,
+    pub children: Vec<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub global: Option<GlobalMaterial>,
+}
+
+/// Placement of an object in the coordinates of the document.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GlobalMaterial {
+    /// `[a, b, c, d, e, f]` of the global transform, in the order of CSS and SVG `matrix()`
+    pub transform: [f32; 6],
+    /// Axis-aligned bounding box after the global transform
+    pub bounds: BoundsMaterial,
+    /// Number of ancestors, so children of the document have depth 1
+    pub depth: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BoundsMaterial {
+    pub min_x: f32,
+    pub min_y: f32,
+    pub max_x: f32,
+    pub max_y: f32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MaterializeOptions {
+    /// Includes `GlobalMaterial` in materials of ovals and frames
+    #[serde(default)]
+    pub global: bool,
+}
+
+/// Material of an object with materials of its descendants, in z-order.
+#[derive(Debug, Clone, Serialize)]
+pub struct MaterialNode {
+    pub material: ObjectMaterial,
+    pub children: Vec<MaterialNode>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MaterializeTreeOptions {
+    /// Levels of descendants to include below the root. Unlimited if not given.
+    #[serde(default)]
+    pub max_depth: Option<u32>,
+    /// Kinds of objects to include. Descendants of an excluded object are attached to its nearest
+    /// included ancestor. The root is always included.
+    #[serde(default)]
+    pub kinds: Option<Vec<ObjectKind>>,
+    #[serde(flatten)]
+    pub object: MaterializeOptions,
+}
+
+/// The object doesn't exist, or lacks a prop needed to materialize it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MaterializeError {
+    ObjectNotFound,
+}
+
+/// Change of an object since it was materialized last time.
+#[derive(Debug, Clone, Serialize)]
+pub enum MaterialChange {
+    Created(ObjectMaterial),
+    /// Only the fields whose values changed, keyed by their names in `ObjectMaterial`
+    Updated {
+        id: ObjectId,
+        fields: Map<String, Value>,
+    },
+    Deleted(ObjectId),
+}
+
+/// Last materialized fields of each object, to find what changed since then.
+#[derive(Debug, Default)]
+pub struct MaterialCache {
+    fields: HashMap<ObjectId, Map<String, Value>>,
+}
+
+impl MaterialCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Caches the document and every object in it, so that later changes of them are reported as
+    /// updates rather than creations.
+    pub fn from_materialize<R, M>(materialize: &M) -> Self
+    where
+        R: PropReadable + DocumentReadable,
+        M: Materialize<R>,
+    {
+        let mut cache = Self::new();
+        let mut object_ids = vec![materialize.readable().document_id()];
+        while let Some(object_id) = object_ids.pop() {
+            if let Ok(material) = materialize.materialize_object(&object_id) {
+                cache.fields.insert(object_id, material_fields(&material));
+            }
+            object_ids.extend(
+                materialize
+                    .readable()
+                    .get_children_indices(&object_id)
+                    .into_iter()
+                    .map(|(child_id, _)| child_id),
+            );
+        }
+        cache
+    }
+}
+
+fn material_fields(material: &ObjectMaterial) -> Map<String, Value> {
+    let inner = match material {
+        ObjectMaterial::Document(m) => serde_json::to_value(m),
+        ObjectMaterial::Oval(m) => serde_json::to_value(m),
+        ObjectMaterial::Frame(m) => serde_json::to_value(m),
+    };
+    match inner.expect("must succeed") {
+        Value::Object(fields) => fields,
+        _ => unreachable!("materials are structs"),
+    }
 }
 
 pub trait Materialize<R: PropReadable + DocumentReadable> {
@@ -100,6 +221,7 @@ pub trait Materialize<R: PropReadable + DocumentReadable> {
                     .get_string_prop(object_id, &PropKind::Index)
                     .unwrap_or("?")
                     .into(),
+                global: None,
             })
             .ok_or(())
     }
@@ -140,6 +262,7 @@ pub trait Materialize<R: PropReadable + DocumentReadable> {
                     .iter()
                     .map(|(object_id, _)| object_id.clone())
                     .collect(),
+                global: None,
             })
             .ok_or(())
     }
@@ -158,4 +281,378 @@ pub trait Materialize<R: PropReadable + DocumentReadable> {
                     .map(|m| ObjectMaterial::Frame(m)),
             })
     }
+
+    fn materialize_object_with_options(
+        &self,
+        object_id: &ObjectId,
+        options: &MaterializeOptions,
+    ) -> Result<ObjectMaterial, MaterializeError> {
+        let mut material = self
+            .materialize_object(object_id)
+            .map_err(|()| MaterializeError::ObjectNotFound)?;
+        if options.global {
+            match &mut material {
+                ObjectMaterial::Document(_) => {}
+                ObjectMaterial::Oval(m) => {
+                    m.global =
+                        Some(self.materialize_global(object_id, -m.r_h, -m.r_v, m.r_h, m.r_v))
+                }
+                ObjectMaterial::Frame(m) => {
+                    m.global = Some(self.materialize_global(object_id, 0.0, 0.0, m.w, m.h))
+                }
+            }
+        }
+        Ok(material)
+    }
+
+    /// Computes `GlobalMaterial` of an object whose local bounding box is given.
+    fn materialize_global(
+        &self,
+        object_id: &ObjectId,
+        min_x: f32,
+        min_y: f32,
+        max_x: f32,
+        max_y: f32,
+    ) -> GlobalMaterial {
+        let readable = self.readable();
+        let transform = readable.get_global_transform(object_id);
+        let bounds = transformed_box(&transform, min_x, min_y, max_x, max_y);
+
+        let mut depth = 0;
+        let mut current_object_id = readable.get_id_prop(object_id, &PropKind::Parent);
+        while let Some(parent_id) = current_object_id {
+            depth += 1;
+            current_object_id = readable.get_id_prop(parent_id, &PropKind::Parent);
+        }
+
+        GlobalMaterial {
+            transform: [
+                transform.m11,
+                transform.m12,
+                transform.m21,
+                transform.m22,
+                transform.m31,
+                transform.m32,
+            ],
+            bounds: BoundsMaterial {
+                min_x: bounds.min.x,
+                min_y: bounds.min.y,
+                max_x: bounds.max.x,
+                max_y: bounds.max.y,
+            },
+            depth,
+        }
+    }
+
+    /// Materializes the object and its descendants in one go.
+    fn materialize_tree(
+        &self,
+        root_id: &ObjectId,
+        options: &MaterializeTreeOptions,
+    ) -> Result<MaterialNode, MaterializeError> {
+        Ok(MaterialNode {
+            material: self.materialize_object_with_options(root_id, &options.object)?,
+            children: self.materialize_subtrees(root_id, 1, options),
+        })
+    }
+
+    /// Materializes the included descendants of the object, whose children are at `depth` below the
+    /// root.
+    fn materialize_subtrees(
+        &self,
+        object_id: &ObjectId,
+        depth: u32,
+        options: &MaterializeTreeOptions,
+    ) -> Vec<MaterialNode> {
+        if options.max_depth.is_some_and(|max_depth| depth > max_depth) {
+            return Vec::new();
+        }
+        let mut nodes = Vec::new();
+        for (child_id, _) in self.readable().get_children_indices(object_id) {
+            let included = options.kinds.as_ref().is_none_or(|kinds| {
+                self.readable()
+                    .get_object_kind(&child_id)
+                    .is_some_and(|kind| kinds.contains(kind))
+            });
+            if !included {
+                nodes.extend(self.materialize_subtrees(&child_id, depth + 1, options));
+                continue;
+            }
+            if let Ok(material) = self.materialize_object_with_options(&child_id, &options.object) {
+                nodes.push(MaterialNode {
+                    material,
+                    children: self.materialize_subtrees(&child_id, depth + 1, options),
+                });
+            }
+        }
+        nodes
+    }
+
+    /// Materializes the objects again, and returns how they changed since they were cached. An
+    /// object which is not in the cache is reported as created, and an object which does not exist
+    /// anymore is reported as deleted. Unchanged objects are omitted.
+    fn materialize_changes<'a, I>(
+        &self,
+        cache: &mut MaterialCache,
+        object_ids: I,
+    ) -> Vec<MaterialChange>
+    where
+        I: IntoIterator<Item = &'a ObjectId>,
+    {
+        let mut changes = Vec::new();
+        for object_id in object_ids {
+            let material = match self.materialize_object(object_id) {
+                Ok(material) => material,
+                Err(_) => {
+                    if cache.fields.remove(object_id).is_some() {
+                        changes.push(MaterialChange::Deleted(*object_id));
+                    }
+                    continue;
+                }
+            };
+
+            let fields = material_fields(&material);
+            match cache.fields.insert(*object_id, fields.clone()) {
+                None => changes.push(MaterialChange::Created(material)),
+                Some(old_fields) => {
+                    let changed_fields = fields
+                        .into_iter()
+                        .filter(|(key, value)| old_fields.get(key) != Some(value))
+                        .collect::<Map<_, _>>();
+                    if !changed_fields.is_empty() {
+                        changes.push(MaterialChange::Updated {
+                            id: *object_id,
+                            fields: changed_fields,
+                        });
+                    }
+                }
+            }
+        }
+        changes
+    }
+}
+
+impl Materialize<Document> for Document {
+    fn readable(&self) -> &Document {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn float(object_id: ObjectId, prop_kind: PropKind, value: f32) -> DocumentMutation {
+        DocumentMutation::UpsertProp(object_id, prop_kind, Some(PropValue::Float(value)))
+    }
+
+    #[test]
+    fn it_should_report_changed_fields_only() {
+        let mut document = Document::new();
+        let document_id = document.document_id();
+        let oval_id = uuid::Uuid::new_v4();
+        document
+            .process(Transaction::new(vec![
+                DocumentMutation::CreateObject(oval_id, ObjectKind::Oval),
+                DocumentMutation::UpsertProp(
+                    oval_id,
+                    PropKind::Parent,
+                    Some(PropValue::Reference(document_id)),
+                ),
+            ]))
+            .expect("should work");
+        let mut cache = MaterialCache::from_materialize(&document);
+
+        document
+            .process(Transaction::new(vec![
+                float(oval_id, PropKind::PosX, 3.0),
+                float(oval_id, PropKind::PosY, 4.0),
+            ]))
+            .expect("should work");
+        let changes = document.materialize_changes(&mut cache, &[oval_id, document_id]);
+        assert_eq!(changes.len(), 1);
+        match &changes[0] {
+            MaterialChange::Updated { id, fields } => {
+                assert_eq!(id, &oval_id);
+                assert_eq!(fields.len(), 2);
+                assert_eq!(fields["pos_x"], 3.0);
+                assert_eq!(fields["pos_y"], 4.0);
+            }
+            change => panic!("unexpected change: {:?}", change),
+        }
+
+        assert!(document
+            .materialize_changes(&mut cache, &[oval_id])
+            .is_empty());
+    }
+
+    #[test]
+    fn it_should_materialize_global_placement_of_nested_objects() {
+        let mut document = Document::new();
+        let document_id = document.document_id();
+        let frame_id = uuid::Uuid::new_v4();
+        let inner_frame_id = uuid::Uuid::new_v4();
+        let oval_id = uuid::Uuid::new_v4();
+        let mut mutations = Vec::new();
+        for (object_id, kind, parent_id, x, y) in [
+            (frame_id, ObjectKind::Frame, document_id, 100.0, 50.0),
+            (inner_frame_id, ObjectKind::Frame, frame_id, 10.0, 20.0),
+            (oval_id, ObjectKind::Oval, inner_frame_id, 1.0, 2.0),
+        ]
+        .iter()
+        {
+            mutations.extend(vec![
+                DocumentMutation::CreateObject(*object_id, kind.clone()),
+                DocumentMutation::UpsertProp(
+                    *object_id,
+                    PropKind::Parent,
+                    Some(PropValue::Reference(*parent_id)),
+                ),
+                float(*object_id, PropKind::PosX, *x),
+                float(*object_id, PropKind::PosY, *y),
+            ]);
+        }
+        mutations.push(float(oval_id, PropKind::RadiusH, 5.0));
+        document
+            .process(Transaction::new(mutations))
+            .expect("should work");
+
+        let options = MaterializeOptions { global: true };
+        match document.materialize_object_with_options(&oval_id, &options) {
+            Ok(ObjectMaterial::Oval(m)) => assert_eq!(
+                m.global,
+                Some(GlobalMaterial {
+                    transform: [1.0, 0.0, 0.0, 1.0, 111.0, 72.0],
+                    bounds: BoundsMaterial {
+                        min_x: 106.0,
+                        min_y: 62.0,
+                        max_x: 116.0,
+                        max_y: 82.0,
+                    },
+                    depth: 3,
+                })
+            ),
+            material => panic!("unexpected material: {:?}", material),
+        }
+        match document.materialize_object_with_options(&frame_id, &options) {
+            Ok(ObjectMaterial::Frame(m)) => {
+                assert_eq!(m.global.expect("should be included").depth, 1)
+            }
+            material => panic!("unexpected material: {:?}", material),
+        }
+        match document.materialize_object(&oval_id) {
+            Ok(ObjectMaterial::Oval(m)) => assert!(m.global.is_none()),
+            material => panic!("unexpected material: {:?}", material),
+        }
+    }
+
+    #[test]
+    fn it_should_materialize_tree_with_depth_limit_and_kind_filter() {
+        let mut document = Document::new();
+        let document_id = document.document_id();
+        let frame_id = uuid::Uuid::new_v4();
+        let first_oval_id = uuid::Uuid::new_v4();
+        let second_oval_id = uuid::Uuid::new_v4();
+        let mut mutations = Vec::new();
+        for (object_id, kind, parent_id, index) in [
+            (frame_id, ObjectKind::Frame, document_id, "a"),
+            (first_oval_id, ObjectKind::Oval, frame_id, "b"),
+            (second_oval_id, ObjectKind::Oval, frame_id, "a"),
+        ]
+        .iter()
+        {
+            mutations.extend(vec![
+                DocumentMutation::CreateObject(*object_id, kind.clone()),
+                DocumentMutation::UpsertProp(
+                    *object_id,
+                    PropKind::Parent,
+                    Some(PropValue::Reference(*parent_id)),
+                ),
+                DocumentMutation::UpsertProp(
+                    *object_id,
+                    PropKind::Index,
+                    Some(PropValue::String(index.to_string())),
+                ),
+            ]);
+        }
+        document
+            .process(Transaction::new(mutations))
+            .expect("should work");
+
+        let ids = |nodes: &[MaterialNode]| {
+            nodes
+                .iter()
+                .map(|node| match &node.material {
+                    ObjectMaterial::Document(m) => m.id,
+                    ObjectMaterial::Oval(m) => m.id,
+                    ObjectMaterial::Frame(m) => m.id,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let tree = document
+            .materialize_tree(&document_id, &MaterializeTreeOptions::default())
+            .expect("should materialize");
+        assert_eq!(ids(&tree.children), vec![frame_id]);
+        assert_eq!(
+            ids(&tree.children[0].children),
+            vec![second_oval_id, first_oval_id]
+        );
+
+        let options = MaterializeTreeOptions {
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        let tree = document
+            .materialize_tree(&document_id, &options)
+            .expect("should materialize");
+        assert_eq!(ids(&tree.children), vec![frame_id]);
+        assert!(tree.children[0].children.is_empty());
+
+        let options = MaterializeTreeOptions {
+            kinds: Some(vec![ObjectKind::Oval]),
+            ..Default::default()
+        };
+        let tree = document
+            .materialize_tree(&document_id, &options)
+            .expect("should materialize");
+        assert_eq!(ids(&tree.children), vec![second_oval_id, first_oval_id]);
+
+        assert!(document
+            .materialize_tree(&uuid::Uuid::new_v4(), &options)
+            .is_err());
+    }
+
+    #[test]
+    fn it_should_report_creations_and_deletions() {
+        let mut document = Document::new();
+        let mut cache = MaterialCache::from_materialize(&document);
+        let oval_id = uuid::Uuid::new_v4();
+
+        document
+            .process(Transaction::new(vec![DocumentMutation::CreateObject(
+                oval_id,
+                ObjectKind::Oval,
+            )]))
+            .expect("should work");
+        let changes = document.materialize_changes(&mut cache, &[oval_id]);
+        assert!(matches!(
+            changes.as_slice(),
+            [MaterialChange::Created(ObjectMaterial::Oval(m))] if m.id == oval_id
+        ));
+
+        document
+            .process(Transaction::new(vec![DocumentMutation::DeleteObject(
+                oval_id,
+            )]))
+            .expect("should work");
+        let changes = document.materialize_changes(&mut cache, &[oval_id]);
+        assert!(matches!(
+            changes.as_slice(),
+            [MaterialChange::Deleted(id)] if id == &oval_id
+        ));
+        assert!(document
+            .materialize_changes(&mut cache, &[oval_id])
+            .is_empty());
+    }
 }
@@ -0,0 +1,527 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
+use crate::document::Document;
+use crate::document_diff::{live_objects, props_of};
+use crate::message::*;
+use crate::traits::PropReadable;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum MergeSide {
+    Ours,
+    Theirs,
+}
+
+/// Change which could not be merged automatically, and how it was resolved.
+#[derive(Debug, Clone, Serialize)]
+pub enum MergeConflict {
+    /// Both sides changed the prop differently. Ours is taken.
+    PropChanged {
+        object_id: ObjectId,
+        prop_kind: PropKind,
+        base: Option<PropValue>,
+        ours: Option<PropValue>,
+        theirs: Option<PropValue>,
+    },
+    /// One side deleted the object, and the other side changed the prop. The object is kept with
+    /// the change, so that nothing is lost silently. If the other side added or moved a child into
+    /// the deleted object, the change is the `Parent` of the child, and its parent is kept.
+    EditOfDeletedObject {
+        object_id: ObjectId,
+        prop_kind: PropKind,
+        deleted_by: MergeSide,
+    },
+    /// Taking the parent from theirs would make a cycle with a parent changed by ours. The parent
+    /// from ours is taken.
+    ReparentCycle {
+        object_id: ObjectId,
+        ours: Option<ObjectId>,
+        theirs: Option<ObjectId>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct DocumentMerge {
+    pub document: Document,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// Merges changes of `ours` and `theirs` since `base`, their common ancestor.
+///
+/// Changes are merged per (object, prop). A change made by only one side is taken, and so is a
+/// change made identically by both sides. Everything else is reported as a conflict.
+pub fn merge_documents(base: &Document, ours: &Document, theirs: &Document) -> DocumentMerge {
+    let base_objects = live_objects(base);
+    let our_objects = live_objects(ours);
+    let their_objects = live_objects(theirs);
+    let object_ids = base_objects
+        .keys()
+        .chain(our_objects.keys())
+        .chain(their_objects.keys())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+
+    let mut conflicts = Vec::new();
+    let mut objects = MergedObjects::new();
+    let mut deleted_objects = BTreeMap::new();
+    for object_id in &object_ids {
+        let base_props = props_of(base, object_id);
+        // A side without the object is regarded as not changing its props, so that changes of the
+        // other side survive when the object is kept.
+        let our_props = if our_objects.contains_key(object_id) {
+            props_of(ours, object_id)
+        } else {
+            base_props.clone()
+        };
+        let their_props = if their_objects.contains_key(object_id) {
+            props_of(theirs, object_id)
+        } else {
+            base_props.clone()
+        };
+
+        let prop_kinds = base_props
+            .keys()
+            .chain(our_props.keys())
+            .chain(their_props.keys())
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        let mut props = BTreeMap::new();
+        let mut changed_props = BTreeMap::new();
+        for prop_kind in prop_kinds {
+            let b = base_props.get(&prop_kind);
+            let o = our_props.get(&prop_kind);
+            let t = their_props.get(&prop_kind);
+            let merged = if o == t || t == b {
+                o
+            } else if o == b {
+                t
+            } else {
+                conflicts.push(MergeConflict::PropChanged {
+                    object_id: *object_id,
+                    prop_kind,
+                    base: b.cloned(),
+                    ours: o.cloned(),
+                    theirs: t.cloned(),
+                });
+                o
+            };
+            if o != b {
+                changed_props.insert(prop_kind, MergeSide::Ours);
+            } else if t != b {
+                changed_props.insert(prop_kind, MergeSide::Theirs);
+            }
+            if let Some(prop_value) = merged {
+                props.insert(prop_kind, prop_value.clone());
+            }
+        }
+
+        let in_base = base_objects.contains_key(object_id);
+        let deleted_by = match (
+            our_objects.contains_key(object_id),
+            their_objects.contains_key(object_id),
+        ) {
+            (true, true) => None,
+            (false, false) => continue,
+            (false, true) if in_base => Some(MergeSide::Ours),
+            (true, false) if in_base => Some(MergeSide::Theirs),
+            _ => None,
+        };
+        let kind = our_objects
+            .get(object_id)
+            .or_else(|| their_objects.get(object_id))
+            .cloned()
+            .expect("must exist in either side");
+        if let Some(deleted_by) = deleted_by {
+            let edits = changed_props
+                .iter()
+                .filter(|(_, side)| **side != deleted_by)
+                .map(|(prop_kind, _)| *prop_kind)
+                .collect::<Vec<_>>();
+            if edits.is_empty() {
+                deleted_objects.insert(*object_id, (kind, props, deleted_by));
+                continue;
+            }
+            for prop_kind in edits {
+                conflicts.push(MergeConflict::EditOfDeletedObject {
+                    object_id: *object_id,
+                    prop_kind,
+                    deleted_by,
+                });
+            }
+        }
+
+        objects.insert(*object_id, (kind, props));
+    }
+
+    let document_id = ours.document_id();
+    keep_parents_of_children(
+        &mut objects,
+        deleted_objects,
+        document_id,
+        (ours, theirs),
+        &mut conflicts,
+    );
+    break_reparent_cycles(&mut objects, ours, &mut conflicts);
+
+    let mut items = Vec::new();
+    for (object_id, (kind, props)) in objects {
+        if object_id != document_id {
+            items.push(DocumentMutation::CreateObject(object_id, kind));
+        }
+        for (prop_kind, prop_value) in props {
+            items.push(DocumentMutation::UpsertProp(
+                object_id,
+                prop_kind,
+                Some(prop_value),
+            ));
+        }
+    }
+    let mut document = Document::with_id(document_id);
+    document
+        .process(Transaction::new(items))
+        .expect("objects must be created once");
+
+    DocumentMerge {
+        document,
+        conflicts,
+    }
+}
+
+type MergedObjects = BTreeMap<ObjectId, (ObjectKind, BTreeMap<PropKind, PropValue>)>;
+
+fn merged_parent(objects: &MergedObjects, object_id: &ObjectId) -> Option<ObjectId> {
+    objects
+        .get(object_id)
+        .and_then(|(_, props)| props.get(&PropKind::Parent))
+        .and_then(|prop_value| prop_value.as_reference())
+        .cloned()
+}
+
+/// Keeps objects deleted by one side while the other side adds or moves children into them, so
+/// that the children stay visible. A side which deletes an object but leaves its children
+/// referring to it keeps them as they are.
+fn keep_parents_of_children(
+    objects: &mut MergedObjects,
+    mut deleted_objects: BTreeMap<ObjectId, (ObjectKind, BTreeMap<PropKind, PropValue>, MergeSide)>,
+    document_id: ObjectId,
+    (ours, theirs): (&Document, &Document),
+    conflicts: &mut Vec<MergeConflict>,
+) {
+    let mut kept = BTreeMap::new();
+    loop {
+        let dangling = objects
+            .keys()
+            .filter_map(|object_id| {
+                merged_parent(objects, object_id)
+                    .filter(|parent_id| {
+                        *parent_id != document_id && !objects.contains_key(parent_id)
+                    })
+                    .map(|parent_id| (*object_id, parent_id))
+            })
+            .collect::<Vec<_>>();
+        let mut changed = false;
+        for (object_id, parent_id) in dangling {
+            let deleted_by = match kept.get(&parent_id) {
+                Some(deleted_by) => *deleted_by,
+                None => match deleted_objects.get(&parent_id) {
+                    Some((_, _, deleted_by)) => *deleted_by,
+                    None => continue,
+                },
+            };
+            let deleting_side = match deleted_by {
+                MergeSide::Ours => ours,
+                MergeSide::Theirs => theirs,
+            };
+            if deleting_side.get_id_prop(&object_id, &PropKind::Parent) == Some(&parent_id) {
+                continue;
+            }
+            if let Some((kind, props, deleted_by)) = deleted_objects.remove(&parent_id) {
+                objects.insert(parent_id, (kind, props));
+                kept.insert(parent_id, deleted_by);
+                changed = true;
+            }
+            conflicts.push(MergeConflict::EditOfDeletedObject {
+                object_id,
+                prop_kind: PropKind::Parent,
+                deleted_by,
+            });
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Returns objects in the cycle reached by following parents from the object, if any.
+fn find_cycle(objects: &MergedObjects, object_id: &ObjectId) -> Option<Vec<ObjectId>> {
+    let mut path = Vec::new();
+    let mut current_object_id = Some(*object_id);
+    while let Some(id) = current_object_id {
+        if let Some(position) = path.iter().position(|p| p == &id) {
+            return Some(path.split_off(position));
+        }
+        path.push(id);
+        current_object_id = merged_parent(objects, &id);
+    }
+    None
+}
+
+/// Reverts parents taken from theirs until there is no cycle. Ours has no cycle, so every cycle
+/// has an object whose parent differs from the one in ours.
+fn break_reparent_cycles(
+    objects: &mut MergedObjects,
+    ours: &Document,
+    conflicts: &mut Vec<MergeConflict>,
+) {
+    let object_ids = objects.keys().cloned().collect::<Vec<_>>();
+    for object_id in object_ids {
+        while let Some(cycle) = find_cycle(objects, &object_id) {
+            let (object_id, our_parent_id) = cycle
+                .into_iter()
+                .filter(|id| ours.get_object_kind(id).is_some())
+                .map(|id| (id, ours.get_id_prop(&id, &PropKind::Parent).cloned()))
+                .find(|(id, our_parent_id)| &merged_parent(objects, id) != our_parent_id)
+                .expect("ours must not have a cycle");
+            let their_parent_id = merged_parent(objects, &object_id);
+
+            let props = &mut objects.get_mut(&object_id).expect("must exist").1;
+            match our_parent_id {
+                Some(parent_id) => props.insert(PropKind::Parent, PropValue::Reference(parent_id)),
+                None => props.remove(&PropKind::Parent),
+            };
+            conflicts.push(MergeConflict::ReparentCycle {
+                object_id,
+                ours: our_parent_id,
+                theirs: their_parent_id,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PropReadable;
+
+    fn upsert(object_id: ObjectId, prop_kind: PropKind, prop_value: PropValue) -> DocumentMutation {
+        DocumentMutation::UpsertProp(object_id, prop_kind, Some(prop_value))
+    }
+
+    fn process(document: &Document, items: Vec<DocumentMutation>) -> Document {
+        let mut document = document.clone();
+        document
+            .process(Transaction::new(items))
+            .expect("should work");
+        document
+    }
+
+    /// Document with two frames at the top level, and an oval in the first frame.
+    fn base_document() -> (Document, ObjectId, ObjectId, ObjectId) {
+        let document = Document::new();
+        let document_id = document.document_id();
+        let (first_id, second_id, oval_id) = (
+            uuid::Uuid::new_v4(),
+            uuid::Uuid::new_v4(),
+            uuid::Uuid::new_v4(),
+        );
+        let document = process(
+            &document,
+            vec![
+                DocumentMutation::CreateObject(first_id, ObjectKind::Frame),
+                upsert(
+                    first_id,
+                    PropKind::Parent,
+                    PropValue::Reference(document_id),
+                ),
+                DocumentMutation::CreateObject(second_id, ObjectKind::Frame),
+                upsert(
+                    second_id,
+                    PropKind::Parent,
+                    PropValue::Reference(document_id),
+                ),
+                DocumentMutation::CreateObject(oval_id, ObjectKind::Oval),
+                upsert(oval_id, PropKind::Parent, PropValue::Reference(first_id)),
+                upsert(oval_id, PropKind::PosX, PropValue::Float(0.0)),
+            ],
+        );
+        (document, first_id, second_id, oval_id)
+    }
+
+    #[test]
+    fn it_should_merge_changes_of_both_sides() {
+        let (base, first_id, second_id, oval_id) = base_document();
+        let ours = process(
+            &base,
+            vec![upsert(oval_id, PropKind::PosX, PropValue::Float(5.0))],
+        );
+        let new_oval_id = uuid::Uuid::new_v4();
+        let theirs = process(
+            &base,
+            vec![
+                upsert(oval_id, PropKind::PosY, PropValue::Float(7.0)),
+                DocumentMutation::DeleteObject(second_id),
+                DocumentMutation::CreateObject(new_oval_id, ObjectKind::Oval),
+                upsert(
+                    new_oval_id,
+                    PropKind::Parent,
+                    PropValue::Reference(first_id),
+                ),
+            ],
+        );
+
+        let merge = merge_documents(&base, &ours, &theirs);
+        assert!(merge.conflicts.is_empty());
+        let document = merge.document;
+        assert_eq!(
+            document.get_float_prop(&oval_id, &PropKind::PosX),
+            Some(&5.0)
+        );
+        assert_eq!(
+            document.get_float_prop(&oval_id, &PropKind::PosY),
+            Some(&7.0)
+        );
+        assert!(document.get_object_kind(&second_id).is_none());
+        assert_eq!(
+            document.get_id_prop(&new_oval_id, &PropKind::Parent),
+            Some(&first_id)
+        );
+    }
+
+    #[test]
+    fn it_should_report_conflicting_changes() {
+        let (base, _, second_id, oval_id) = base_document();
+        let ours = process(
+            &base,
+            vec![
+                upsert(oval_id, PropKind::PosX, PropValue::Float(5.0)),
+                DocumentMutation::DeleteObject(second_id),
+            ],
+        );
+        let theirs = process(
+            &base,
+            vec![
+                upsert(oval_id, PropKind::PosX, PropValue::Float(9.0)),
+                upsert(second_id, PropKind::Name, PropValue::String("Kept".into())),
+            ],
+        );
+
+        let merge = merge_documents(&base, &ours, &theirs);
+        let document = &merge.document;
+        assert_eq!(
+            document.get_float_prop(&oval_id, &PropKind::PosX),
+            Some(&5.0)
+        );
+        assert_eq!(
+            document.get_string_prop(&second_id, &PropKind::Name),
+            Some("Kept")
+        );
+        assert_eq!(merge.conflicts.len(), 2);
+        assert!(merge.conflicts.iter().any(|c| matches!(
+            c,
+            MergeConflict::PropChanged { object_id, prop_kind: PropKind::PosX, .. }
+                if object_id == &oval_id
+        )));
+        assert!(merge.conflicts.iter().any(|c| matches!(
+            c,
+            MergeConflict::EditOfDeletedObject {
+                object_id,
+                prop_kind: PropKind::Name,
+                deleted_by: MergeSide::Ours,
+            } if object_id == &second_id
+        )));
+    }
+
+    #[test]
+    fn it_should_break_reparent_cycles() {
+        let (base, first_id, second_id, _) = base_document();
+        let document_id = base.document_id();
+        // Each side puts one frame into the other.
+        let ours = process(
+            &base,
+            vec![upsert(
+                first_id,
+                PropKind::Parent,
+                PropValue::Reference(second_id),
+            )],
+        );
+        let theirs = process(
+            &base,
+            vec![upsert(
+                second_id,
+                PropKind::Parent,
+                PropValue::Reference(first_id),
+            )],
+        );
+
+        let merge = merge_documents(&base, &ours, &theirs);
+        let document = &merge.document;
+        assert_eq!(
+            document.get_id_prop(&first_id, &PropKind::Parent),
+            Some(&second_id)
+        );
+        assert_eq!(
+            document.get_id_prop(&second_id, &PropKind::Parent),
+            Some(&document_id)
+        );
+        assert!(matches!(
+            merge.conflicts.as_slice(),
+            [MergeConflict::ReparentCycle { object_id, ours: Some(ours), theirs: Some(theirs) }]
+                if object_id == &second_id && ours == &document_id && theirs == &first_id
+        ));
+    }
+
+    #[test]
+    fn it_should_keep_deleted_parent_of_added_child() {
+        let (base, first_id, second_id, oval_id) = base_document();
+        let ours = process(
+            &base,
+            vec![
+                DocumentMutation::UpsertProp(second_id, PropKind::Parent, None),
+                DocumentMutation::DeleteObject(second_id),
+            ],
+        );
+        let new_oval_id = uuid::Uuid::new_v4();
+        let theirs = process(
+            &base,
+            vec![
+                DocumentMutation::CreateObject(new_oval_id, ObjectKind::Oval),
+                upsert(
+                    new_oval_id,
+                    PropKind::Parent,
+                    PropValue::Reference(second_id),
+                ),
+                upsert(oval_id, PropKind::Parent, PropValue::Reference(second_id)),
+            ],
+        );
+
+        let merge = merge_documents(&base, &ours, &theirs);
+        let document = &merge.document;
+        assert_eq!(
+            document.get_object_kind(&second_id),
+            Some(&ObjectKind::Frame)
+        );
+        assert_eq!(
+            document.get_id_prop(&second_id, &PropKind::Parent),
+            Some(&base.document_id())
+        );
+        assert_eq!(
+            document.get_id_prop(&new_oval_id, &PropKind::Parent),
+            Some(&second_id)
+        );
+        assert_eq!(
+            document.get_id_prop(&oval_id, &PropKind::Parent),
+            Some(&second_id)
+        );
+        assert!(document.get_object_kind(&first_id).is_some());
+        assert_eq!(merge.conflicts.len(), 2);
+        assert!(merge.conflicts.iter().all(|c| matches!(
+            c,
+            MergeConflict::EditOfDeletedObject {
+                object_id,
+                prop_kind: PropKind::Parent,
+                deleted_by: MergeSide::Ours,
+            } if object_id == &new_oval_id || object_id == &oval_id
+        )));
+    }
+}
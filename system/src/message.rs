@@ -1,4 +1,4 @@
-use crate::DocumentSnapshot;
+use crate::{DocumentSnapshot, InitChunk, MutationError};
 use serde::{Deserialize, Serialize};
 
 pub type ConnectionId = u16;
@@ -8,7 +8,7 @@ pub type TransactionId = uuid::Uuid;
 pub type ObjectId = uuid::Uuid;
 pub type FileId = uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -21,6 +21,32 @@ impl std::default::Default for Color {
     }
 }
 
+impl Color {
+    /// Parses `#rrggbb` or `#rgb`.
+    pub fn from_hex(value: &str) -> Option<Self> {
+        let hex = value.strip_prefix('#').filter(|hex| hex.is_ascii())?;
+        let channel = |s: &str| u8::from_str_radix(s, 16).ok();
+        match hex.len() {
+            3 => Some(Self {
+                r: channel(&hex[0..1])? * 17,
+                g: channel(&hex[1..2])? * 17,
+                b: channel(&hex[2..3])? * 17,
+            }),
+            6 => Some(Self {
+                r: channel(&hex[0..2])?,
+                g: channel(&hex[2..4])?,
+                b: channel(&hex[4..6])?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Formats as `#rrggbb`.
+    pub fn to_hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
 /// FatalError makes connection be closed.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FatalError {
@@ -63,6 +89,8 @@ pub enum SessionEvent {
         session_snapshot: SessionSnapshot,
         document_snapshot: DocumentSnapshot,
     },
+    /// Part of a large `Init`. See `InitAssembler`.
+    InitChunk(InitChunk),
     LivePointer(LivePointerEvent),
     SessionStateChanged(SessionSnapshot),
     SomeoneJoined(ConnectionId),
@@ -87,6 +115,7 @@ pub enum DocumentMutation {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum RollbackReason {
     Something,
+    InvalidMutation(MutationError),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -107,7 +136,7 @@ pub struct SessionSnapshot {
     pub connections: Vec<ConnectionId>,
 }
 
-#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
 pub enum PropKind {
     Parent,
     Name,
@@ -121,7 +150,7 @@ pub enum PropKind {
     FillColor,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum PropValue {
     String(String),
     Float(f32),
@@ -0,0 +1,311 @@
+use std::fmt::Write;
+
+use euclid::default::Transform2D;
+
+use crate::materialize::{Materialize, MaterializeError};
+use crate::message::*;
+use crate::scene::{build_scene, SceneNode};
+use crate::traits::{DocumentReadable, PropReadable};
+
+/// Control point distance of a cubic Bézier curve approximating a quarter of a circle.
+const KAPPA: f32 = 0.552_284_8;
+
+/// Decimals written for real numbers, which is far below a point on a page.
+const NUMBER_PRECISION: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PdfExportError {
+    Materialize(MaterializeError),
+    /// The document has no top-level frame to make a page of
+    NoPages,
+}
+
+/// Exports the document as a PDF. Each top-level frame becomes a page of the same size, in
+/// z-order. Objects which are not in any top-level frame are not exported.
+///
+/// Fails if there is no top-level frame, since a PDF needs at least one page.
+pub fn export_pdf<R, M>(materialize: &M) -> Result<Vec<u8>, PdfExportError>
+where
+    R: PropReadable + DocumentReadable,
+    M: Materialize<R>,
+{
+    let document_id = materialize.readable().document_id();
+    let pages = build_scene(materialize, &document_id)
+        .map_err(PdfExportError::Materialize)?
+        .iter()
+        .filter_map(page_content)
+        .collect::<Vec<_>>();
+    if pages.is_empty() {
+        return Err(PdfExportError::NoPages);
+    }
+
+    // Object 1 is the catalog, 2 is the page tree, and each page takes two objects after them:
+    // the page itself and its content stream.
+    let page_ids = (0..pages.len()).map(|i| 3 + i * 2).collect::<Vec<_>>();
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            page_ids
+                .iter()
+                .map(|id| format!("{} 0 R", id))
+                .collect::<Vec<_>>()
+                .join(" "),
+            pages.len()
+        ),
+    ];
+    for (page_id, (w, h, content)) in page_ids.iter().zip(pages) {
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << >> /Contents {} 0 R >>",
+            pdf_number(w),
+            pdf_number(h),
+            page_id + 1
+        ));
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{}endstream",
+            content.len(),
+            content
+        ));
+    }
+
+    let mut pdf = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        write!(pdf, "{} 0 obj\n{}\nendobj\n", i + 1, object).expect("must succeed");
+    }
+    let xref_offset = pdf.len();
+    write!(pdf, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).expect("must succeed");
+    for offset in offsets {
+        writeln!(pdf, "{:010} 00000 n ", offset).expect("must succeed");
+    }
+    write!(
+        pdf,
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref_offset
+    )
+    .expect("must succeed");
+    Ok(pdf.into_bytes())
+}
+
+/// Returns width, height and content stream of the page for a top-level frame.
+fn page_content(node: &SceneNode) -> Option<(f32, f32, String)> {
+    let (transform, w, h, children) = match node {
+        SceneNode::Frame {
+            transform,
+            w,
+            h,
+            children,
+            ..
+        } => (transform, *w, *h, children),
+        SceneNode::Oval { .. } => return None,
+    };
+    if !(w.is_finite() && h.is_finite()) {
+        return None;
+    }
+    let inverse = transform.inverse()?;
+
+    let mut content = String::new();
+    // PDF puts the origin at the bottom left, with y growing upwards.
+    writeln!(content, "1 0 0 -1 0 {} cm", pdf_number(h)).expect("must succeed");
+    writeln!(content, "{} cm", pdf_matrix(&inverse)).expect("must succeed");
+    write_frame_fill(&mut content, transform, w, h);
+    for child in children {
+        write_node(&mut content, child);
+    }
+    Some((w, h, content))
+}
+
+fn write_node(content: &mut String, node: &SceneNode) {
+    match node {
+        SceneNode::Oval {
+            transform,
+            r_h,
+            r_v,
+            fill_color,
+            ..
+        } => {
+            let (r_h, r_v) = (*r_h, *r_v);
+            let (k_h, k_v) = (r_h * KAPPA, r_v * KAPPA);
+            writeln!(content, "q\n{} cm", pdf_matrix(transform)).expect("must succeed");
+            writeln!(content, "{} rg", pdf_color(fill_color)).expect("must succeed");
+            writeln!(content, "{} m", pdf_numbers(&[r_h, 0.0])).expect("must succeed");
+            for curve in &[
+                [r_h, k_v, k_h, r_v, 0.0, r_v],
+                [-k_h, r_v, -r_h, k_v, -r_h, 0.0],
+                [-r_h, -k_v, -k_h, -r_v, 0.0, -r_v],
+                [k_h, -r_v, r_h, -k_v, r_h, 0.0],
+            ] {
+                writeln!(content, "{} c", pdf_numbers(curve)).expect("must succeed");
+            }
+            content.push_str("f\nQ\n");
+        }
+        SceneNode::Frame {
+            transform,
+            w,
+            h,
+            children,
+            ..
+        } => {
+            let inverse = match transform.inverse() {
+                Some(inverse) => inverse,
+                None => return,
+            };
+            // Children have global transforms, so the transform of the frame is reverted after
+            // clipping. The clip stays until the matching `Q`.
+            content.push_str("q\n");
+            write_frame_fill(content, transform, *w, *h);
+            writeln!(content, "{} cm", pdf_matrix(transform)).expect("must succeed");
+            writeln!(content, "0 0 {} {} re W n", pdf_number(*w), pdf_number(*h))
+                .expect("must succeed");
+            writeln!(content, "{} cm", pdf_matrix(&inverse)).expect("must succeed");
+            for child in children {
+                write_node(content, child);
+            }
+            content.push_str("Q\n");
+        }
+    }
+}
+
+fn write_frame_fill(content: &mut String, transform: &Transform2D<f32>, w: f32, h: f32) {
+    writeln!(
+        content,
+        "q\n{} cm\n1 1 1 rg\n0 0 {} {} re f\nQ",
+        pdf_matrix(transform),
+        pdf_number(w),
+        pdf_number(h)
+    )
+    .expect("must succeed");
+}
+
+fn pdf_matrix(t: &Transform2D<f32>) -> String {
+    pdf_numbers(&[t.m11, t.m12, t.m21, t.m22, t.m31, t.m32])
+}
+
+fn pdf_color(color: &Color) -> String {
+    pdf_numbers(&[
+        color.r as f32 / 255.0,
+        color.g as f32 / 255.0,
+        color.b as f32 / 255.0,
+    ])
+}
+
+fn pdf_numbers(values: &[f32]) -> String {
+    values
+        .iter()
+        .map(|value| pdf_number(*value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Writes a real number the way PDF accepts, with `NUMBER_PRECISION` decimals at most and no
+/// exponent. PDF has no NaN or infinity, so non-finite values are written as 0.
+fn pdf_number(value: f32) -> String {
+    if !value.is_finite() {
+        return "0".into();
+    }
+    let mut number = format!("{:.*}", NUMBER_PRECISION, value);
+    if number.contains('.') {
+        number.truncate(number.trim_end_matches('0').trim_end_matches('.').len());
+    }
+    if number == "-0" {
+        number.remove(0);
+    }
+    number
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Document;
+
+    fn create_frame(
+        document_id: ObjectId,
+        frame_id: ObjectId,
+        index: &str,
+        w: f32,
+        h: f32,
+    ) -> Vec<DocumentMutation> {
+        vec![
+            DocumentMutation::CreateObject(frame_id, ObjectKind::Frame),
+            DocumentMutation::UpsertProp(
+                frame_id,
+                PropKind::Parent,
+                Some(PropValue::Reference(document_id)),
+            ),
+            DocumentMutation::UpsertProp(
+                frame_id,
+                PropKind::Index,
+                Some(PropValue::String(index.into())),
+            ),
+            DocumentMutation::UpsertProp(frame_id, PropKind::PosX, Some(PropValue::Float(500.0))),
+            DocumentMutation::UpsertProp(frame_id, PropKind::Width, Some(PropValue::Float(w))),
+            DocumentMutation::UpsertProp(frame_id, PropKind::Height, Some(PropValue::Float(h))),
+        ]
+    }
+
+    #[test]
+    fn it_should_export_top_level_frames_as_pages() {
+        let mut document = Document::new();
+        let document_id = document.document_id();
+        let first_frame_id = uuid::Uuid::new_v4();
+        let second_frame_id = uuid::Uuid::new_v4();
+        let oval_id = uuid::Uuid::new_v4();
+        let mut mutations = create_frame(document_id, first_frame_id, "a", 100.0, 50.0);
+        mutations.extend(create_frame(
+            document_id,
+            second_frame_id,
+            "b",
+            300.0,
+            200.0,
+        ));
+        mutations.extend(vec![
+            DocumentMutation::CreateObject(oval_id, ObjectKind::Oval),
+            DocumentMutation::UpsertProp(
+                oval_id,
+                PropKind::Parent,
+                Some(PropValue::Reference(first_frame_id)),
+            ),
+            DocumentMutation::UpsertProp(oval_id, PropKind::RadiusH, Some(PropValue::Float(7.0))),
+        ]);
+        document
+            .process(Transaction::new(mutations))
+            .expect("should work");
+
+        let pdf = String::from_utf8(export_pdf(&document).expect("should export")).expect("");
+        assert!(pdf.starts_with("%PDF-1.4\n"));
+        assert!(pdf.ends_with("%%EOF\n"));
+        assert!(pdf.contains("/Count 2"));
+        let first_page = pdf.find("/MediaBox [0 0 100 50]").expect("first page");
+        let second_page = pdf.find("/MediaBox [0 0 300 200]").expect("second page");
+        assert!(first_page < second_page);
+        // The frame is moved to the origin of the page.
+        assert!(pdf.contains("1 0 0 1 -500 0 cm"));
+        assert!(pdf.contains("7 0 m"));
+
+        // Every entry of the cross reference table points to its object.
+        let xref = pdf.rfind("xref\n").expect("xref");
+        for (i, line) in pdf[xref..].lines().skip(3).take(6).enumerate() {
+            let offset = line[..10].parse::<usize>().expect("offset");
+            assert!(pdf[offset..].starts_with(&format!("{} 0 obj\n", i + 1)));
+        }
+    }
+
+    #[test]
+    fn it_should_write_numbers_with_fixed_precision() {
+        assert_eq!(pdf_number(12.5), "12.5");
+        assert_eq!(pdf_number(-3.0), "-3");
+        assert_eq!(pdf_number(1.0 / 3.0), "0.3333");
+        assert_eq!(pdf_number(1e-7), "0");
+        assert_eq!(pdf_number(-1e-7), "0");
+        assert_eq!(pdf_number(f32::NAN), "0");
+        assert_eq!(pdf_number(f32::INFINITY), "0");
+        assert_eq!(pdf_number(1e10), "10000000000");
+    }
+
+    #[test]
+    fn it_should_fail_without_frames() {
+        assert_eq!(export_pdf(&Document::new()), Err(PdfExportError::NoPages));
+    }
+}
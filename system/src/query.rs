@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use base95::Base95;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::message::*;
+use crate::traits::PropReadable;
+
+/// Conditions of objects to find. Every given condition must hold.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ObjectQuery {
+    #[serde(default)]
+    pub kinds: Option<Vec<ObjectKind>>,
+    /// Case-sensitive substring of the name
+    #[serde(default)]
+    pub name_contains: Option<String>,
+    #[serde(default)]
+    pub name_regex: Option<String>,
+    /// Finds only descendants of this object.
+    #[serde(default)]
+    pub ancestor: Option<ObjectId>,
+    #[serde(default)]
+    pub props: Vec<PropRange>,
+}
+
+/// Range of a float prop, e.g. `{"prop": "Width", "gt": 100}`. An object without the prop is
+/// compared with its default value, as materialized.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PropRange {
+    pub prop: PropKind,
+    #[serde(default)]
+    pub gt: Option<f32>,
+    #[serde(default)]
+    pub gte: Option<f32>,
+    #[serde(default)]
+    pub lt: Option<f32>,
+    #[serde(default)]
+    pub lte: Option<f32>,
+}
+
+impl PropRange {
+    fn contains(&self, value: f32) -> bool {
+        self.gt.is_none_or(|gt| value > gt)
+            && self.gte.is_none_or(|gte| value >= gte)
+            && self.lt.is_none_or(|lt| value < lt)
+            && self.lte.is_none_or(|lte| value <= lte)
+    }
+}
+
+#[derive(Debug)]
+pub enum QueryError {
+    InvalidRegex(String),
+    /// Prop of a range is not a float prop
+    NotFloatProp(PropKind),
+}
+
+fn default_float_prop(prop_kind: &PropKind) -> Option<f32> {
+    match prop_kind {
+        PropKind::PosX | PropKind::PosY => Some(0.0),
+        PropKind::Width | PropKind::Height | PropKind::RadiusH | PropKind::RadiusV => Some(10.0),
+        _ => None,
+    }
+}
+
+/// Returns ids of the matching objects in document order, which is depth-first with parents
+/// before their children, and siblings in z-order.
+pub fn query_objects<R: PropReadable>(
+    readable: &R,
+    query: &ObjectQuery,
+) -> Result<Vec<ObjectId>, QueryError> {
+    let name_regex = query
+        .name_regex
+        .as_ref()
+        .map(|pattern| Regex::new(pattern))
+        .transpose()
+        .map_err(|err| QueryError::InvalidRegex(err.to_string()))?;
+    if let Some(range) = query
+        .props
+        .iter()
+        .find(|range| default_float_prop(&range.prop).is_none())
+    {
+        return Err(QueryError::NotFloatProp(range.prop));
+    }
+
+    let matches = |object_id: &ObjectId| {
+        let kind_matches = query.kinds.as_ref().is_none_or(|kinds| {
+            readable
+                .get_object_kind(object_id)
+                .is_some_and(|kind| kinds.contains(kind))
+        });
+        let name = readable.get_string_prop(object_id, &PropKind::Name);
+        let name_matches = query
+            .name_contains
+            .as_ref()
+            .is_none_or(|substring| name.is_some_and(|name| name.contains(substring.as_str())))
+            && name_regex
+                .as_ref()
+                .is_none_or(|regex| name.is_some_and(|name| regex.is_match(name)));
+        let props_match = query.props.iter().all(|range| {
+            let value = readable
+                .get_float_prop(object_id, &range.prop)
+                .cloned()
+                .or_else(|| default_float_prop(&range.prop))
+                .expect("must be a float prop");
+            range.contains(value)
+        });
+        kind_matches && name_matches && props_match
+    };
+
+    // Children of every object are collected at once, rather than asking the readable for each.
+    let mut children = HashMap::<Option<ObjectId>, Vec<(Base95, ObjectId)>>::new();
+    for object_id in readable.containing_objects() {
+        if readable.is_deleted(object_id).unwrap_or(true) {
+            continue;
+        }
+        let parent_id = readable.get_id_prop(object_id, &PropKind::Parent).copied();
+        let index = readable
+            .get_string_prop(object_id, &PropKind::Index)
+            .and_then(|index| Base95::from_str(index).ok())
+            .unwrap_or_else(Base95::mid);
+        children
+            .entry(parent_id)
+            .or_default()
+            .push((index, *object_id));
+    }
+    for siblings in children.values_mut() {
+        siblings.sort();
+        siblings.dedup();
+    }
+
+    let mut result = Vec::new();
+    let mut stack: Vec<ObjectId> = children
+        .get(&query.ancestor)
+        .map(|siblings| siblings.iter().rev().map(|(_, id)| *id).collect())
+        .unwrap_or_default();
+    while let Some(object_id) = stack.pop() {
+        if matches(&object_id) {
+            result.push(object_id);
+        }
+        if let Some(siblings) = children.get(&Some(object_id)) {
+            stack.extend(siblings.iter().rev().map(|(_, id)| *id));
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Document;
+
+    fn create(
+        object_id: ObjectId,
+        kind: ObjectKind,
+        parent_id: ObjectId,
+        index: &str,
+        name: &str,
+    ) -> Vec<DocumentMutation> {
+        vec![
+            DocumentMutation::CreateObject(object_id, kind),
+            DocumentMutation::UpsertProp(
+                object_id,
+                PropKind::Parent,
+                Some(PropValue::Reference(parent_id)),
+            ),
+            DocumentMutation::UpsertProp(
+                object_id,
+                PropKind::Index,
+                Some(PropValue::String(index.into())),
+            ),
+            DocumentMutation::UpsertProp(
+                object_id,
+                PropKind::Name,
+                Some(PropValue::String(name.into())),
+            ),
+        ]
+    }
+
+    /// Document with two frames, and two ovals in the second frame.
+    fn sample_document() -> (Document, Vec<ObjectId>) {
+        let mut document = Document::new();
+        let document_id = document.document_id();
+        let ids = (0..4).map(|_| uuid::Uuid::new_v4()).collect::<Vec<_>>();
+        let mut mutations = Vec::new();
+        mutations.extend(create(
+            ids[0],
+            ObjectKind::Frame,
+            document_id,
+            "b",
+            "Header",
+        ));
+        mutations.extend(create(ids[1], ObjectKind::Frame, document_id, "a", "Body"));
+        mutations.extend(create(ids[2], ObjectKind::Oval, ids[1], "b", "Big dot"));
+        mutations.extend(create(ids[3], ObjectKind::Oval, ids[1], "a", "Dot 2"));
+        mutations.push(DocumentMutation::UpsertProp(
+            ids[2],
+            PropKind::RadiusH,
+            Some(PropValue::Float(150.0)),
+        ));
+        document
+            .process(Transaction::new(mutations))
+            .expect("should work");
+        (document, ids)
+    }
+
+    #[test]
+    fn it_should_find_objects_in_document_order() {
+        let (document, ids) = sample_document();
+
+        let all = query_objects(&document, &ObjectQuery::default()).expect("should query");
+        assert_eq!(
+            all,
+            vec![document.document_id(), ids[1], ids[3], ids[2], ids[0]]
+        );
+
+        let query = ObjectQuery {
+            kinds: Some(vec![ObjectKind::Oval]),
+            ..Default::default()
+        };
+        assert_eq!(
+            query_objects(&document, &query).expect("should query"),
+            vec![ids[3], ids[2]]
+        );
+
+        let query = ObjectQuery {
+            ancestor: Some(ids[0]),
+            ..Default::default()
+        };
+        assert!(query_objects(&document, &query)
+            .expect("should query")
+            .is_empty());
+    }
+
+    #[test]
+    fn it_should_filter_by_name_and_prop_range() {
+        let (document, ids) = sample_document();
+
+        let query = ObjectQuery {
+            name_contains: Some("Dot".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            query_objects(&document, &query).expect("should query"),
+            vec![ids[3]]
+        );
+
+        let query = ObjectQuery {
+            name_regex: Some("(?i)dot( \\d)?$".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            query_objects(&document, &query).expect("should query"),
+            vec![ids[3], ids[2]]
+        );
+
+        let query = ObjectQuery {
+            props: vec![PropRange {
+                prop: PropKind::RadiusH,
+                gt: Some(100.0),
+                gte: None,
+                lt: None,
+                lte: None,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            query_objects(&document, &query).expect("should query"),
+            vec![ids[2]]
+        );
+
+        let query = ObjectQuery {
+            name_regex: Some("(".into()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            query_objects(&document, &query),
+            Err(QueryError::InvalidRegex(_))
+        ));
+    }
+}
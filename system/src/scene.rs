@@ -0,0 +1,147 @@
+use euclid::default::{Box2D, Point2D, Transform2D};
+
+use crate::materialize::{Materialize, MaterializeError, ObjectMaterial};
+use crate::message::*;
+use crate::traits::{DocumentReadable, PropReadable};
+
+/// Drawable object in z-order, which is the common ground of exporters and renderers.
+///
+/// `transform` is the global transform of the object, so each node can be drawn without knowing
+/// its ancestors.
+#[derive(Debug, Clone)]
+pub enum SceneNode {
+    /// Ellipse centered at the local origin
+    Oval {
+        id: ObjectId,
+        transform: Transform2D<f32>,
+        r_h: f32,
+        r_v: f32,
+        fill_color: Color,
+    },
+    /// Rectangle from the local origin, which clips its children
+    Frame {
+        id: ObjectId,
+        transform: Transform2D<f32>,
+        w: f32,
+        h: f32,
+        children: Vec<SceneNode>,
+    },
+}
+
+impl SceneNode {
+    pub fn id(&self) -> &ObjectId {
+        match self {
+            SceneNode::Oval { id, .. } | SceneNode::Frame { id, .. } => id,
+        }
+    }
+
+    /// Axis-aligned bounding box in global coordinates. Children of frames are clipped, so only the
+    /// frame itself is considered.
+    pub fn bounds(&self) -> Box2D<f32> {
+        match self {
+            SceneNode::Oval {
+                transform,
+                r_h,
+                r_v,
+                ..
+            } => transformed_box(transform, -r_h, -r_v, *r_h, *r_v),
+            SceneNode::Frame {
+                transform, w, h, ..
+            } => transformed_box(transform, 0.0, 0.0, *w, *h),
+        }
+    }
+}
+
+pub(crate) fn transformed_box(
+    transform: &Transform2D<f32>,
+    min_x: f32,
+    min_y: f32,
+    max_x: f32,
+    max_y: f32,
+) -> Box2D<f32> {
+    Box2D::from_points(
+        [
+            Point2D::new(min_x, min_y),
+            Point2D::new(max_x, min_y),
+            Point2D::new(max_x, max_y),
+            Point2D::new(min_x, max_y),
+        ]
+        .iter()
+        .map(|p| transform.transform_point(*p)),
+    )
+}
+
+/// Union of bounds of the nodes. `None` if there is nothing to draw.
+pub fn scene_bounds(nodes: &[SceneNode]) -> Option<Box2D<f32>> {
+    nodes
+        .iter()
+        .map(|node| node.bounds())
+        .fold(None, |acc: Option<Box2D<f32>>, b| {
+            Some(acc.map(|acc| acc.union(&b)).unwrap_or(b))
+        })
+}
+
+/// Area to be drawn for the nodes built by `build_scene`. A single frame crops the image to
+/// itself, otherwise the image is sized to fit everything in it.
+pub fn scene_view_box(nodes: &[SceneNode]) -> Box2D<f32> {
+    match nodes {
+        [node @ SceneNode::Frame { .. }] => node.bounds(),
+        _ => scene_bounds(nodes).unwrap_or_else(Box2D::zero),
+    }
+}
+
+/// Builds scene nodes to draw the object. If the object is the document, its children are
+/// returned in z-order.
+pub fn build_scene<R, M>(
+    materialize: &M,
+    object_id: &ObjectId,
+) -> Result<Vec<SceneNode>, MaterializeError>
+where
+    R: PropReadable + DocumentReadable,
+    M: Materialize<R>,
+{
+    match materialize.materialize_object(object_id) {
+        Ok(ObjectMaterial::Document(document)) => {
+            Ok(build_children(materialize, &document.children))
+        }
+        _ => build_node(materialize, object_id)
+            .map(|node| vec![node])
+            .map_err(|()| MaterializeError::ObjectNotFound),
+    }
+}
+
+fn build_children<R, M>(materialize: &M, children: &[ObjectId]) -> Vec<SceneNode>
+where
+    R: PropReadable + DocumentReadable,
+    M: Materialize<R>,
+{
+    children
+        .iter()
+        .filter_map(|child_id| build_node(materialize, child_id).ok())
+        .collect()
+}
+
+fn build_node<R, M>(materialize: &M, object_id: &ObjectId) -> Result<SceneNode, ()>
+where
+    R: PropReadable + DocumentReadable,
+    M: Materialize<R>,
+{
+    let transform = materialize.readable().get_global_transform(object_id);
+    match materialize.materialize_object(object_id)? {
+        ObjectMaterial::Oval(oval) => Ok(SceneNode::Oval {
+            id: oval.id,
+            transform,
+            r_h: oval.r_h,
+            r_v: oval.r_v,
+            fill_color: oval.fill_color,
+        }),
+        ObjectMaterial::Frame(frame) => Ok(SceneNode::Frame {
+            id: frame.id,
+            transform,
+            w: frame.w,
+            h: frame.h,
+            children: build_children(materialize, &frame.children),
+        }),
+        ObjectMaterial::Document(_) => Err(()),
+    }
+}
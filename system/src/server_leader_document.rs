@@ -1,8 +1,8 @@
 use crate::materialize::Materialize;
-use crate::transactional_document::TransactionalDocument;
+use crate::transactional_document::{FinishError, TransactionalDocument};
 
 use super::message::*;
-use crate::document::DocumentSnapshot;
+use crate::document::{DocumentSnapshot, MutationError};
 use crate::traits::DocumentReadable;
 use crate::Document;
 use uuid::Uuid;
@@ -25,12 +25,14 @@ impl ServerLeaderDocument {
         }
     }
 
-    pub fn process_transaction(&mut self, tx: Transaction) -> Result<Transaction, ()> {
+    pub fn process_transaction(&mut self, tx: Transaction) -> Result<Transaction, MutationError> {
         let tx_id = tx.id;
         self.tx_document.begin(tx.clone());
-        // TODO: validation
-        self.tx_document.finish(&tx_id, true).expect("must finish");
-        Ok(tx)
+        match self.tx_document.finish(&tx_id, true) {
+            Ok(_) => Ok(tx),
+            Err(FinishError::InvalidMutation(error)) => Err(error),
+            Err(FinishError::UnknownTransaction) => unreachable!("transaction has just begun"),
+        }
     }
 
     pub fn document(&self) -> &Document {
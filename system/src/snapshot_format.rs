@@ -0,0 +1,210 @@
+use crate::document::Document;
+
+// Snapshot layout: MAGIC (4 bytes) | schema version (u16, little endian) | checksum | payload
+//
+// Payload of the current schema version is a bincode dump of `Document`. Snapshots written before
+// the header was introduced have no header at all, and they are regarded as schema version 0.
+// Checksum is CRC-32 of the payload (u32, little endian), and it exists since schema version 2.
+
+const MAGIC: [u8; 4] = *b"RCSD";
+const HEADER_LEN: usize = MAGIC.len() + 2;
+const CHECKSUM_LEN: usize = 4;
+const CHECKSUM_SINCE_VERSION: u16 = 2;
+
+/// Schema version of snapshots written by this build.
+pub const CURRENT_SCHEMA_VERSION: u16 = 2;
+
+type Migration = fn(Vec<u8>) -> Result<Vec<u8>, SnapshotError>;
+
+/// `MIGRATIONS[v]` converts a payload of schema version `v` into schema version `v + 1`.
+///
+/// When the layout of `Document` (or anything it contains) changes, freeze the old layout in a
+/// migration, append it here and bump `CURRENT_SCHEMA_VERSION`.
+const MIGRATIONS: [Migration; CURRENT_SCHEMA_VERSION as usize] =
+    [migrate_v0_to_v1, migrate_v1_to_v2];
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// Neither a snapshot with header nor a legacy snapshot
+    UnknownFormat,
+    /// Written by a newer build
+    UnsupportedVersion(u16),
+    /// Payload does not match the checksum in the header, e.g. the snapshot is truncated
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// Payload cannot be decoded
+    Corrupted(bincode::Error),
+}
+
+pub(crate) fn encode_document(document: &Document) -> Vec<u8> {
+    let payload = bincode::serialize(document).expect("Document must be serializable");
+    let mut content = Vec::with_capacity(HEADER_LEN + CHECKSUM_LEN + payload.len());
+    content.extend_from_slice(&MAGIC);
+    content.extend_from_slice(&CURRENT_SCHEMA_VERSION.to_le_bytes());
+    content.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    content.extend_from_slice(&payload);
+    content
+}
+
+pub(crate) fn decode_document(content: &[u8]) -> Result<Document, SnapshotError> {
+    let (version, payload) = split_header(content)?;
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+
+    let mut payload = payload.to_vec();
+    for migration in &MIGRATIONS[version as usize..] {
+        payload = migration(payload)?;
+    }
+
+    bincode::deserialize(&payload).map_err(SnapshotError::Corrupted)
+}
+
+/// Returns schema version of the snapshot, and its payload. The payload is verified against the
+/// checksum if the snapshot has one.
+pub(crate) fn split_header(content: &[u8]) -> Result<(u16, &[u8]), SnapshotError> {
+    if content.starts_with(&MAGIC) {
+        if content.len() < HEADER_LEN {
+            return Err(SnapshotError::UnknownFormat);
+        }
+        let version = u16::from_le_bytes([content[MAGIC.len()], content[MAGIC.len() + 1]]);
+        if version < CHECKSUM_SINCE_VERSION {
+            return Ok((version, &content[HEADER_LEN..]));
+        }
+
+        if content.len() < HEADER_LEN + CHECKSUM_LEN {
+            return Err(SnapshotError::UnknownFormat);
+        }
+        let mut expected = [0; CHECKSUM_LEN];
+        expected.copy_from_slice(&content[HEADER_LEN..HEADER_LEN + CHECKSUM_LEN]);
+        let expected = u32::from_le_bytes(expected);
+        let payload = &content[HEADER_LEN + CHECKSUM_LEN..];
+        let actual = crc32fast::hash(payload);
+        if actual != expected {
+            return Err(SnapshotError::ChecksumMismatch { expected, actual });
+        }
+        Ok((version, payload))
+    } else if is_legacy(content) {
+        Ok((0, content))
+    } else {
+        Err(SnapshotError::UnknownFormat)
+    }
+}
+
+/// Legacy snapshots start with `Document::document_id`, which bincode writes as a byte sequence
+/// of length 16.
+fn is_legacy(content: &[u8]) -> bool {
+    content.starts_with(&16u64.to_le_bytes())
+}
+
+/// Version 1 only introduced the header, so the payload is the same.
+fn migrate_v0_to_v1(payload: Vec<u8>) -> Result<Vec<u8>, SnapshotError> {
+    Ok(payload)
+}
+
+/// Version 2 only added the checksum to the header, so the payload is the same.
+fn migrate_v1_to_v2(payload: Vec<u8>) -> Result<Vec<u8>, SnapshotError> {
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DocumentMutation, PropKind, PropReadable, PropValue, Transaction};
+
+    fn named_document() -> Document {
+        let mut document = Document::new();
+        let document_id = document.document_id();
+        document
+            .process(Transaction::new(vec![DocumentMutation::UpsertProp(
+                document_id,
+                PropKind::Name,
+                Some(PropValue::String("name".into())),
+            )]))
+            .expect("should work");
+        document
+    }
+
+    #[test]
+    fn it_should_round_trip() {
+        let document = named_document();
+        let content = encode_document(&document);
+        assert!(content.starts_with(&MAGIC));
+
+        let decoded = decode_document(&content).expect("should decode");
+        assert_eq!(decoded.document_id(), document.document_id());
+        assert_eq!(
+            decoded.get_string_prop(&document.document_id(), &PropKind::Name),
+            Some("name")
+        );
+    }
+
+    #[test]
+    fn it_should_migrate_legacy_snapshot() {
+        let document = named_document();
+        let legacy_content = bincode::serialize(&document).expect("should serialize");
+
+        let decoded = decode_document(&legacy_content).expect("should decode");
+        assert_eq!(decoded.document_id(), document.document_id());
+        assert_eq!(
+            decoded.get_string_prop(&document.document_id(), &PropKind::Name),
+            Some("name")
+        );
+    }
+
+    #[test]
+    fn it_should_reject_unknown_format() {
+        assert!(matches!(
+            decode_document(b"not a snapshot"),
+            Err(SnapshotError::UnknownFormat)
+        ));
+        assert!(matches!(
+            decode_document(&[]),
+            Err(SnapshotError::UnknownFormat)
+        ));
+    }
+
+    #[test]
+    fn it_should_reject_newer_version() {
+        let mut content = encode_document(&Document::new());
+        content[MAGIC.len()..HEADER_LEN]
+            .copy_from_slice(&(CURRENT_SCHEMA_VERSION + 1).to_le_bytes());
+
+        assert!(matches!(
+            decode_document(&content),
+            Err(SnapshotError::UnsupportedVersion(version)) if version == CURRENT_SCHEMA_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn it_should_reject_truncated_snapshot() {
+        let content = encode_document(&named_document());
+
+        assert!(matches!(
+            decode_document(&content[..content.len() - 4]),
+            Err(SnapshotError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn it_should_reject_modified_payload() {
+        let mut content = encode_document(&named_document());
+        let last = content.len() - 1;
+        content[last] ^= 0xff;
+
+        assert!(matches!(
+            decode_document(&content),
+            Err(SnapshotError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn it_should_read_snapshot_without_checksum() {
+        let document = named_document();
+        let mut content = MAGIC.to_vec();
+        content.extend_from_slice(&1u16.to_le_bytes());
+        content.extend_from_slice(&bincode::serialize(&document).expect("should serialize"));
+
+        let decoded = decode_document(&content).expect("should decode");
+        assert_eq!(decoded.document_id(), document.document_id());
+    }
+}
@@ -0,0 +1,176 @@
+use std::fmt::Write;
+
+use euclid::default::Transform2D;
+
+use crate::materialize::{Materialize, MaterializeError};
+use crate::message::*;
+use crate::scene::{build_scene, scene_view_box, SceneNode};
+use crate::traits::{DocumentReadable, PropReadable};
+
+const FRAME_FILL: &str = "#ffffff";
+
+/// Exports the document or an object in it as an SVG image.
+///
+/// If the object is a frame, the image is cropped to the frame. Otherwise the image is sized to fit
+/// everything in it.
+pub fn export_svg<R, M>(materialize: &M, object_id: &ObjectId) -> Result<String, MaterializeError>
+where
+    R: PropReadable + DocumentReadable,
+    M: Materialize<R>,
+{
+    let nodes = build_scene(materialize, object_id)?;
+    let view_box = scene_view_box(&nodes);
+
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}" width="{}" height="{}">"#,
+        view_box.min.x,
+        view_box.min.y,
+        view_box.width(),
+        view_box.height(),
+        view_box.width(),
+        view_box.height(),
+    )
+    .expect("must succeed");
+    for node in &nodes {
+        write_node(&mut svg, node);
+    }
+    svg.push_str("</svg>\n");
+    Ok(svg)
+}
+
+fn write_node(svg: &mut String, node: &SceneNode) {
+    match node {
+        SceneNode::Oval {
+            id,
+            transform,
+            r_h,
+            r_v,
+            fill_color,
+        } => {
+            writeln!(
+                svg,
+                r#"<ellipse id="{}" rx="{}" ry="{}" transform="{}" fill="{}"/>"#,
+                id,
+                r_h,
+                r_v,
+                svg_matrix(transform),
+                fill_color.to_hex(),
+            )
+            .expect("must succeed");
+        }
+        SceneNode::Frame {
+            id,
+            transform,
+            w,
+            h,
+            children,
+        } => {
+            let rect = format!(
+                r#"width="{}" height="{}" transform="{}""#,
+                w,
+                h,
+                svg_matrix(transform)
+            );
+            writeln!(svg, r#"<g id="{}">"#, id).expect("must succeed");
+            writeln!(
+                svg,
+                r#"<clipPath id="clip-{}"><rect {}/></clipPath>"#,
+                id, rect
+            )
+            .expect("must succeed");
+            writeln!(svg, r#"<rect {} fill="{}"/>"#, rect, FRAME_FILL).expect("must succeed");
+            writeln!(svg, r#"<g clip-path="url(#clip-{})">"#, id).expect("must succeed");
+            for child in children {
+                write_node(svg, child);
+            }
+            svg.push_str("</g>\n</g>\n");
+        }
+    }
+}
+
+fn svg_matrix(t: &Transform2D<f32>) -> String {
+    format!(
+        "matrix({} {} {} {} {} {})",
+        t.m11, t.m12, t.m21, t.m22, t.m31, t.m32
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Document;
+
+    #[test]
+    fn it_should_export_nested_objects_in_global_coordinates() {
+        let mut document = Document::new();
+        let document_id = document.document_id();
+        let frame_id = uuid::Uuid::new_v4();
+        let oval_id = uuid::Uuid::new_v4();
+        document
+            .process(Transaction::new(vec![
+                DocumentMutation::CreateObject(frame_id, ObjectKind::Frame),
+                DocumentMutation::UpsertProp(
+                    frame_id,
+                    PropKind::Parent,
+                    Some(PropValue::Reference(document_id)),
+                ),
+                DocumentMutation::UpsertProp(
+                    frame_id,
+                    PropKind::PosX,
+                    Some(PropValue::Float(10.0)),
+                ),
+                DocumentMutation::UpsertProp(
+                    frame_id,
+                    PropKind::PosY,
+                    Some(PropValue::Float(20.0)),
+                ),
+                DocumentMutation::UpsertProp(
+                    frame_id,
+                    PropKind::Width,
+                    Some(PropValue::Float(100.0)),
+                ),
+                DocumentMutation::UpsertProp(
+                    frame_id,
+                    PropKind::Height,
+                    Some(PropValue::Float(50.0)),
+                ),
+                DocumentMutation::CreateObject(oval_id, ObjectKind::Oval),
+                DocumentMutation::UpsertProp(
+                    oval_id,
+                    PropKind::Parent,
+                    Some(PropValue::Reference(frame_id)),
+                ),
+                DocumentMutation::UpsertProp(oval_id, PropKind::PosX, Some(PropValue::Float(5.0))),
+                DocumentMutation::UpsertProp(oval_id, PropKind::PosY, Some(PropValue::Float(6.0))),
+                DocumentMutation::UpsertProp(
+                    oval_id,
+                    PropKind::FillColor,
+                    Some(PropValue::Color(Color {
+                        r: 255,
+                        g: 0,
+                        b: 16,
+                    })),
+                ),
+            ]))
+            .expect("should work");
+
+        let svg = export_svg(&document, &document_id).expect("should export");
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains(r#"viewBox="10 20 100 50""#));
+        assert!(svg.contains(&format!(r#"<clipPath id="clip-{}">"#, frame_id)));
+        assert!(svg.contains(r##"transform="matrix(1 0 0 1 15 26)" fill="#ff0010""##));
+
+        // The frame comes before the oval, so the oval is drawn on top of it.
+        let frame_pos = svg.find(&frame_id.to_string()).expect("");
+        let oval_pos = svg.find(&oval_id.to_string()).expect("");
+        assert!(frame_pos < oval_pos);
+
+        let frame_svg = export_svg(&document, &frame_id).expect("should export");
+        assert!(frame_svg.contains(r#"viewBox="10 20 100 50""#));
+        assert!(frame_svg.contains(&oval_id.to_string()));
+
+        assert!(export_svg(&document, &uuid::Uuid::new_v4()).is_err());
+    }
+}
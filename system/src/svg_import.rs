@@ -0,0 +1,558 @@
+use base95::Base95;
+use euclid::default::{Point2D, Transform2D, Vector2D};
+use euclid::Angle;
+use serde::Serialize;
+
+use crate::document_command_transaction::create_last_index_of_parent;
+use crate::message::*;
+use crate::traits::{DocumentReadable, PropReadable};
+
+// Objects have positions and sizes only, so a shape is imported only if its transform keeps it
+// axis-aligned. Groups are flattened, and every shape becomes a direct child of the parent.
+
+/// Result of `import_svg`. The transaction is empty if nothing could be imported.
+#[derive(Debug)]
+pub struct SvgImport {
+    pub transaction: Transaction,
+    pub warnings: Vec<SvgImportWarning>,
+}
+
+/// Part of the image which is skipped or imported differently. Elements are described by their tag
+/// names, followed by `#id` if they have one.
+#[derive(Debug, Clone, Serialize)]
+pub enum SvgImportWarning {
+    /// No kind of object matches the element, such as `path` or `text`
+    UnsupportedElement(String),
+    /// Rotated or skewed shapes
+    UnsupportedTransform(String),
+    /// Shapes without solid fills, and rects whose fills are dropped since frames are always white.
+    /// Rects without fills, such as bounding boxes of icons, are skipped.
+    UnsupportedFill(String),
+    InvalidAttribute {
+        element: String,
+        attribute: String,
+    },
+}
+
+#[derive(Debug)]
+pub enum SvgImportError {
+    Parse(String),
+    /// The root element is not `svg`
+    NotSvg,
+    ParentNotFound,
+}
+
+#[derive(Debug, Clone)]
+enum Fill {
+    None,
+    Color(Color),
+}
+
+/// Elements without anything to draw, which are skipped silently.
+const IGNORED_ELEMENTS: [&str; 5] = ["defs", "desc", "metadata", "style", "title"];
+
+/// Builds a transaction creating objects for the shapes in the SVG, as the last children of the
+/// parent. Coordinates of the image are regarded as local coordinates of the parent.
+pub fn import_svg<R: PropReadable + DocumentReadable>(
+    readable: &R,
+    parent_id: &ObjectId,
+    svg: &str,
+) -> Result<SvgImport, SvgImportError> {
+    if readable.get_object_kind(parent_id).is_none() {
+        return Err(SvgImportError::ParentNotFound);
+    }
+    let xml =
+        roxmltree::Document::parse(svg).map_err(|err| SvgImportError::Parse(err.to_string()))?;
+    let root = xml.root_element();
+    if root.tag_name().name() != "svg" {
+        return Err(SvgImportError::NotSvg);
+    }
+
+    let mut importer = Importer {
+        parent_id: *parent_id,
+        next_index: create_last_index_of_parent(readable, parent_id),
+        mutations: Vec::new(),
+        warnings: Vec::new(),
+    };
+    importer.import_children(
+        root,
+        &Transform2D::identity(),
+        Fill::Color(Color::default()),
+    );
+    Ok(SvgImport {
+        transaction: Transaction::new(importer.mutations),
+        warnings: importer.warnings,
+    })
+}
+
+struct Importer {
+    parent_id: ObjectId,
+    next_index: Base95,
+    mutations: Vec<DocumentMutation>,
+    warnings: Vec<SvgImportWarning>,
+}
+
+impl Importer {
+    fn import_children(&mut self, node: roxmltree::Node, transform: &Transform2D<f32>, fill: Fill) {
+        for child in node.children().filter(|child| child.is_element()) {
+            self.import_element(child, transform, fill.clone());
+        }
+    }
+
+    fn import_element(
+        &mut self,
+        node: roxmltree::Node,
+        parent_transform: &Transform2D<f32>,
+        parent_fill: Fill,
+    ) {
+        let tag_name = node.tag_name().name();
+        if IGNORED_ELEMENTS.contains(&tag_name) {
+            return;
+        }
+
+        let transform = match node.attribute("transform").map(parse_transform) {
+            Some(Some(transform)) => transform.then(parent_transform),
+            Some(None) => return self.invalid_attribute(node, "transform"),
+            None => *parent_transform,
+        };
+        let fill = match style_or_attribute(node, "fill").map(parse_fill) {
+            Some(Some(fill)) => fill,
+            Some(None) => return self.warn(SvgImportWarning::UnsupportedFill(describe(node))),
+            None => parent_fill,
+        };
+
+        match tag_name {
+            "svg" | "g" => self.import_children(node, &transform, fill),
+            "rect" => self.import_rect(node, &transform, fill),
+            "ellipse" | "circle" => self.import_ellipse(node, &transform, fill),
+            _ => self.warn(SvgImportWarning::UnsupportedElement(describe(node))),
+        }
+    }
+
+    fn import_rect(&mut self, node: roxmltree::Node, transform: &Transform2D<f32>, fill: Fill) {
+        let (x, y, w, h) = match (
+            self.length(node, "x"),
+            self.length(node, "y"),
+            self.length(node, "width"),
+            self.length(node, "height"),
+        ) {
+            (Some(x), Some(y), Some(w), Some(h)) => (x, y, w, h),
+            _ => return,
+        };
+        if w <= 0.0 || h <= 0.0 {
+            return;
+        }
+        if !is_axis_aligned(transform) {
+            return self.warn(SvgImportWarning::UnsupportedTransform(describe(node)));
+        }
+        match fill {
+            // A frame would cover what is behind the rect.
+            Fill::None => return self.warn(SvgImportWarning::UnsupportedFill(describe(node))),
+            Fill::Color(Color {
+                r: 255,
+                g: 255,
+                b: 255,
+            }) => {}
+            Fill::Color(_) => self.warn(SvgImportWarning::UnsupportedFill(describe(node))),
+        }
+
+        let a = transform.transform_point(Point2D::new(x, y));
+        let b = transform.transform_point(Point2D::new(x + w, y + h));
+        let id = self.create_object(node, ObjectKind::Frame);
+        self.mutations.extend(vec![
+            float_prop(id, PropKind::PosX, a.x.min(b.x)),
+            float_prop(id, PropKind::PosY, a.y.min(b.y)),
+            float_prop(id, PropKind::Width, (b.x - a.x).abs()),
+            float_prop(id, PropKind::Height, (b.y - a.y).abs()),
+        ]);
+    }
+
+    fn import_ellipse(&mut self, node: roxmltree::Node, transform: &Transform2D<f32>, fill: Fill) {
+        let (rx, ry) = if node.tag_name().name() == "circle" {
+            match self.length(node, "r") {
+                Some(r) => (r, r),
+                None => return,
+            }
+        } else {
+            match (self.length(node, "rx"), self.length(node, "ry")) {
+                (Some(rx), Some(ry)) => (rx, ry),
+                _ => return,
+            }
+        };
+        let (cx, cy) = match (self.length(node, "cx"), self.length(node, "cy")) {
+            (Some(cx), Some(cy)) => (cx, cy),
+            _ => return,
+        };
+        if rx <= 0.0 || ry <= 0.0 {
+            return;
+        }
+        if !is_axis_aligned(transform) {
+            return self.warn(SvgImportWarning::UnsupportedTransform(describe(node)));
+        }
+        let fill_color = match fill {
+            Fill::Color(color) => color,
+            Fill::None => return self.warn(SvgImportWarning::UnsupportedFill(describe(node))),
+        };
+
+        let center = transform.transform_point(Point2D::new(cx, cy));
+        let id = self.create_object(node, ObjectKind::Oval);
+        self.mutations.extend(vec![
+            float_prop(id, PropKind::PosX, center.x),
+            float_prop(id, PropKind::PosY, center.y),
+            float_prop(id, PropKind::RadiusH, rx * transform.m11.abs()),
+            float_prop(id, PropKind::RadiusV, ry * transform.m22.abs()),
+            DocumentMutation::UpsertProp(
+                id,
+                PropKind::FillColor,
+                Some(PropValue::Color(fill_color)),
+            ),
+        ]);
+    }
+
+    /// Creates an object as the last child of the parent, named after the `id` of the element.
+    fn create_object(&mut self, node: roxmltree::Node, kind: ObjectKind) -> ObjectId {
+        let id = uuid::Uuid::new_v4();
+        let index = std::mem::replace(&mut self.next_index, Base95::mid());
+        self.next_index = Base95::avg_with_one(&index);
+
+        self.mutations.extend(vec![
+            DocumentMutation::CreateObject(id, kind),
+            DocumentMutation::UpsertProp(
+                id,
+                PropKind::Parent,
+                Some(PropValue::Reference(self.parent_id)),
+            ),
+            DocumentMutation::UpsertProp(
+                id,
+                PropKind::Index,
+                Some(PropValue::String(index.to_string())),
+            ),
+        ]);
+        if let Some(name) = node.attribute("id") {
+            self.mutations.push(DocumentMutation::UpsertProp(
+                id,
+                PropKind::Name,
+                Some(PropValue::String(name.into())),
+            ));
+        }
+        id
+    }
+
+    /// Missing lengths are 0 as in SVG. Returns `None` after warning about invalid lengths.
+    fn length(&mut self, node: roxmltree::Node, attribute: &str) -> Option<f32> {
+        match node.attribute(attribute) {
+            Some(value) => {
+                let length = parse_length(value);
+                if length.is_none() {
+                    self.invalid_attribute(node, attribute);
+                }
+                length
+            }
+            None => Some(0.0),
+        }
+    }
+
+    fn invalid_attribute(&mut self, node: roxmltree::Node, attribute: &str) {
+        self.warn(SvgImportWarning::InvalidAttribute {
+            element: describe(node),
+            attribute: attribute.into(),
+        });
+    }
+
+    fn warn(&mut self, warning: SvgImportWarning) {
+        self.warnings.push(warning);
+    }
+}
+
+fn float_prop(id: ObjectId, prop_kind: PropKind, value: f32) -> DocumentMutation {
+    DocumentMutation::UpsertProp(id, prop_kind, Some(PropValue::Float(value)))
+}
+
+fn describe(node: roxmltree::Node) -> String {
+    match node.attribute("id") {
+        Some(id) => format!("{}#{}", node.tag_name().name(), id),
+        None => node.tag_name().name().into(),
+    }
+}
+
+fn is_axis_aligned(transform: &Transform2D<f32>) -> bool {
+    transform.m12 == 0.0 && transform.m21 == 0.0
+}
+
+/// Declarations in `style` take precedence over presentation attributes.
+fn style_or_attribute<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.attribute("style")
+        .and_then(|style| {
+            style.split(';').find_map(|declaration| {
+                let mut parts = declaration.splitn(2, ':');
+                match (parts.next(), parts.next()) {
+                    (Some(key), Some(value)) if key.trim() == name => Some(value.trim()),
+                    _ => None,
+                }
+            })
+        })
+        .or_else(|| node.attribute(name))
+}
+
+fn parse_length(value: &str) -> Option<f32> {
+    let value = value.trim();
+    value
+        .strip_suffix("px")
+        .unwrap_or(value)
+        .trim_end()
+        .parse::<f32>()
+        .ok()
+        .filter(|length| length.is_finite())
+}
+
+fn parse_numbers(value: &str) -> Option<Vec<f32>> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.parse::<f32>().ok())
+        .collect()
+}
+
+/// Parses a transform list such as `translate(10 20) scale(2)`.
+fn parse_transform(value: &str) -> Option<Transform2D<f32>> {
+    let mut result = Transform2D::identity();
+    let mut rest = value.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+    while !rest.is_empty() {
+        let open = rest.find('(')?;
+        let close = rest.find(')')?;
+        if close < open {
+            return None;
+        }
+        let name = rest[..open].trim();
+        let args = parse_numbers(&rest[open + 1..close])?;
+        let transform = match (name, args.as_slice()) {
+            ("matrix", &[a, b, c, d, e, f]) => Transform2D::new(a, b, c, d, e, f),
+            ("translate", &[tx]) => Transform2D::translation(tx, 0.0),
+            ("translate", &[tx, ty]) => Transform2D::translation(tx, ty),
+            ("scale", &[s]) => Transform2D::scale(s, s),
+            ("scale", &[sx, sy]) => Transform2D::scale(sx, sy),
+            ("rotate", &[a]) => Transform2D::rotation(Angle::degrees(a)),
+            ("rotate", &[a, cx, cy]) => Transform2D::translation(-cx, -cy)
+                .then_rotate(Angle::degrees(a))
+                .then_translate(Vector2D::new(cx, cy)),
+            ("skewX", &[a]) => Transform2D::new(1.0, 0.0, a.to_radians().tan(), 1.0, 0.0, 0.0),
+            ("skewY", &[a]) => Transform2D::new(1.0, a.to_radians().tan(), 0.0, 1.0, 0.0, 0.0),
+            _ => return None,
+        };
+        // The rightmost transform of the list applies to points first.
+        result = transform.then(&result);
+        rest = rest[close + 1..].trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+    }
+    Some(result)
+}
+
+/// Returns `None` if the fill is not supported, such as gradients.
+fn parse_fill(value: &str) -> Option<Fill> {
+    let value = value.trim();
+    if value == "none" || value == "transparent" {
+        return Some(Fill::None);
+    }
+    if value.starts_with('#') {
+        return Color::from_hex(value).map(Fill::Color);
+    }
+    if let Some(args) = value
+        .strip_prefix("rgb(")
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        let channels = args
+            .split(',')
+            .map(|arg| {
+                let arg = arg.trim();
+                match arg.strip_suffix('%') {
+                    Some(percent) => percent.parse::<f32>().ok().map(|p| p * 2.55),
+                    None => arg.parse::<f32>().ok(),
+                }
+                .map(|v| v.round().clamp(0.0, 255.0) as u8)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        return match channels.as_slice() {
+            &[r, g, b] => Some(Fill::Color(Color { r, g, b })),
+            _ => None,
+        };
+    }
+    let (r, g, b) = match value {
+        "black" => (0, 0, 0),
+        "white" => (255, 255, 255),
+        "red" => (255, 0, 0),
+        "lime" => (0, 255, 0),
+        "green" => (0, 128, 0),
+        "blue" => (0, 0, 255),
+        "yellow" => (255, 255, 0),
+        "cyan" | "aqua" => (0, 255, 255),
+        "magenta" | "fuchsia" => (255, 0, 255),
+        "gray" | "grey" => (128, 128, 128),
+        "orange" => (255, 165, 0),
+        "purple" => (128, 0, 128),
+        _ => return None,
+    };
+    Some(Fill::Color(Color { r, g, b }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Document;
+
+    fn import(document: &mut Document, svg: &str) -> Vec<SvgImportWarning> {
+        let document_id = document.document_id();
+        let SvgImport {
+            transaction,
+            warnings,
+        } = import_svg(document, &document_id, svg).expect("should import");
+        document.process(transaction).expect("should work");
+        warnings
+    }
+
+    fn children_of_kind(document: &Document, kind: ObjectKind) -> Vec<ObjectId> {
+        document
+            .get_children_indices(&document.document_id())
+            .into_iter()
+            .map(|(id, _)| id)
+            .filter(|id| document.get_object_kind(id) == Some(&kind))
+            .collect()
+    }
+
+    #[test]
+    fn it_should_import_shapes_with_group_transforms() {
+        let mut document = Document::new();
+        let warnings = import(
+            &mut document,
+            r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 100 100">
+                <title>icon</title>
+                <g transform="translate(10 20) scale(2)" fill="#ff0000">
+                    <rect id="box" x="1" y="2" width="3" height="4" fill="white"/>
+                    <circle cx="5" cy="5" r="1"/>
+                    <ellipse cx="0" cy="0" rx="2" ry="3" style="fill: rgb(0, 0, 255)"/>
+                </g>
+            </svg>"##,
+        );
+        assert!(warnings.is_empty(), "{:?}", warnings);
+
+        let frames = children_of_kind(&document, ObjectKind::Frame);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(
+            document.get_string_prop(&frames[0], &PropKind::Name),
+            Some("box")
+        );
+        assert_eq!(
+            document.get_float_prop(&frames[0], &PropKind::PosX),
+            Some(&12.0)
+        );
+        assert_eq!(
+            document.get_float_prop(&frames[0], &PropKind::PosY),
+            Some(&24.0)
+        );
+        assert_eq!(
+            document.get_float_prop(&frames[0], &PropKind::Height),
+            Some(&8.0)
+        );
+
+        // Ovals are in the order of the image.
+        let ovals = children_of_kind(&document, ObjectKind::Oval);
+        assert_eq!(ovals.len(), 2);
+        assert_eq!(
+            document.get_float_prop(&ovals[0], &PropKind::PosX),
+            Some(&20.0)
+        );
+        assert_eq!(
+            document.get_float_prop(&ovals[0], &PropKind::RadiusH),
+            Some(&2.0)
+        );
+        assert_eq!(
+            document
+                .get_color_prop(&ovals[0], &PropKind::FillColor)
+                .map(|c| (c.r, c.g, c.b)),
+            Some((255, 0, 0))
+        );
+        assert_eq!(
+            document.get_float_prop(&ovals[1], &PropKind::RadiusV),
+            Some(&6.0)
+        );
+        assert_eq!(
+            document
+                .get_color_prop(&ovals[1], &PropKind::FillColor)
+                .map(|c| (c.r, c.g, c.b)),
+            Some((0, 0, 255))
+        );
+    }
+
+    #[test]
+    fn it_should_warn_about_unsupported_parts() {
+        let mut document = Document::new();
+        let warnings = import(
+            &mut document,
+            r#"<svg xmlns="http://www.w3.org/2000/svg">
+                <path id="arrow" d="M 0 0 L 10 10"/>
+                <circle r="5" transform="rotate(45)"/>
+                <circle r="5" fill="none" stroke="black"/>
+                <circle r="5%"/>
+            </svg>"#,
+        );
+
+        assert!(matches!(
+            warnings.as_slice(),
+            [
+                SvgImportWarning::UnsupportedElement(path),
+                SvgImportWarning::UnsupportedTransform(_),
+                SvgImportWarning::UnsupportedFill(_),
+                SvgImportWarning::InvalidAttribute { .. },
+            ] if path == "path#arrow"
+        ));
+        assert!(children_of_kind(&document, ObjectKind::Oval).is_empty());
+    }
+
+    #[test]
+    fn it_should_skip_rects_without_fill() {
+        let mut document = Document::new();
+        let warnings = import(
+            &mut document,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24">
+                <rect id="bounds" width="24" height="24" fill="none"/>
+                <circle cx="12" cy="12" r="6" fill="black"/>
+            </svg>"#,
+        );
+
+        assert!(matches!(
+            warnings.as_slice(),
+            [SvgImportWarning::UnsupportedFill(rect)] if rect == "rect#bounds"
+        ));
+        assert!(children_of_kind(&document, ObjectKind::Frame).is_empty());
+        assert_eq!(children_of_kind(&document, ObjectKind::Oval).len(), 1);
+    }
+
+    #[test]
+    fn it_should_reject_invalid_input() {
+        let document = Document::new();
+        let document_id = document.document_id();
+        assert!(matches!(
+            import_svg(&document, &document_id, "<svg"),
+            Err(SvgImportError::Parse(_))
+        ));
+        assert!(matches!(
+            import_svg(&document, &document_id, "<html/>"),
+            Err(SvgImportError::NotSvg)
+        ));
+        assert!(matches!(
+            import_svg(&document, &uuid::Uuid::new_v4(), "<svg/>"),
+            Err(SvgImportError::ParentNotFound)
+        ));
+    }
+
+    #[test]
+    fn it_should_parse_transform_lists() {
+        let transform = parse_transform("translate(10, 20) scale(2 3)").expect("should parse");
+        let point = transform.transform_point(Point2D::new(1.0, 1.0));
+        assert_eq!((point.x, point.y), (12.0, 23.0));
+
+        let transform = parse_transform("matrix(1 0 0 1 5 6)").expect("should parse");
+        assert_eq!((transform.m31, transform.m32), (5.0, 6.0));
+
+        assert!(parse_transform("perspective(3)").is_none());
+        assert!(parse_transform("scale(1 2 3)").is_none());
+    }
+}
@@ -47,45 +47,58 @@ impl TransactionManager {
     }
 }
 
-impl PropReadable for TransactionManager {
-    fn get_prop(&self, object_id: &ObjectId, prop_kind: &PropKind) -> Option<&PropValue> {
+impl TransactionManager {
+    /// Returns `Some` if any pending transaction touches the prop. The inner value is `None` when
+    /// the prop is deleted by the pending transactions.
+    pub fn pending_prop(
+        &self,
+        object_id: &ObjectId,
+        prop_kind: &PropKind,
+    ) -> Option<Option<&PropValue>> {
         self.last_mutation(|command| match command {
             DocumentMutation::UpsertProp(can_object_id, can_prop_kind, prop_value)
                 if can_object_id == object_id && can_prop_kind == prop_kind =>
             {
-                prop_value.as_ref()
+                Some(prop_value.as_ref())
             }
             _ => None,
         })
     }
 
-    fn get_object_kind(&self, target_object_id: &ObjectId) -> Option<&ObjectKind> {
+    /// Returns `Some` if any pending transaction creates or deletes the object. The inner value is
+    /// `None` when the object is deleted by the pending transactions.
+    pub fn pending_object_kind(&self, target_object_id: &ObjectId) -> Option<Option<&ObjectKind>> {
         self.last_mutation(|command| match command {
             DocumentMutation::CreateObject(object_id, object_kind)
                 if object_id == target_object_id =>
             {
-                Some(object_kind)
+                Some(Some(object_kind))
+            }
+            DocumentMutation::DeleteObject(object_id) if object_id == target_object_id => {
+                Some(None)
             }
             _ => None,
         })
     }
+}
+
+impl PropReadable for TransactionManager {
+    fn get_prop(&self, object_id: &ObjectId, prop_kind: &PropKind) -> Option<&PropValue> {
+        self.pending_prop(object_id, prop_kind).flatten()
+    }
+
+    fn get_object_kind(&self, target_object_id: &ObjectId) -> Option<&ObjectKind> {
+        self.pending_object_kind(target_object_id).flatten()
+    }
 
     fn is_deleted(&self, object_id: &ObjectId) -> Option<bool> {
-        for tx in &self.txs {
-            for mutation in &tx.items {
-                match mutation {
-                    DocumentMutation::DeleteObject(candidate) if candidate == object_id => {
-                        return Some(true)
-                    }
-                    _ => {}
-                }
-            }
-        }
-        None
+        self.pending_object_kind(object_id)
+            .map(|object_kind_opt| object_kind_opt.is_none())
     }
 
+    /// Props deleted by the pending transactions are included with `None` value.
     fn get_all_props_of_object(&self, object_id: &ObjectId) -> Vec<(PropKind, Option<PropValue>)> {
-        let mut result = Vec::new();
+        let mut result: Vec<(PropKind, Option<PropValue>)> = Vec::new();
         for tx in &self.txs {
             for mutation in &tx.items {
                 match mutation {
@@ -94,6 +107,8 @@ impl PropReadable for TransactionManager {
                     {
                         if let Some((_, pv)) = result.iter_mut().find(|(pk, _)| pk == prop_kind) {
                             *pv = prop_value.clone();
+                        } else {
+                            result.push((*prop_kind, prop_value.clone()));
                         }
                     }
                     _ => {}
@@ -109,7 +124,8 @@ impl PropReadable for TransactionManager {
                 .iter()
                 .flat_map(|tx| tx.items.iter())
                 .filter_map(|item| match item {
-                    DocumentMutation::UpsertProp(object_id, ..) => Some(object_id),
+                    DocumentMutation::CreateObject(object_id, ..)
+                    | DocumentMutation::UpsertProp(object_id, ..) => Some(object_id),
                     _ => None,
                 }),
         )
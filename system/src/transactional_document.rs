@@ -3,6 +3,8 @@ use crate::traits::{DocumentReadable, PropReadable};
 
 use super::document::*;
 use super::transaction_manager::*;
+use crate::snapshot_format::SnapshotError;
+use std::convert::TryFrom;
 use uuid::Uuid;
 
 #[derive(Debug)]
@@ -23,11 +25,11 @@ impl TransactionalDocument {
         self.tx_manager.get(tx_id)
     }
 
-    pub fn from_snapshot(snapshot: DocumentSnapshot) -> Self {
-        Self {
-            document: (&snapshot).into(),
+    pub fn from_snapshot(snapshot: DocumentSnapshot) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            document: Document::try_from(&snapshot)?,
             tx_manager: TransactionManager::new(),
-        }
+        })
     }
 
     pub fn document(&self) -> &Document {
@@ -40,31 +42,46 @@ impl TransactionalDocument {
         self.tx_manager.push(tx.clone());
     }
 
-    pub fn finish(&mut self, tx_id: &TransactionId, commit: bool) -> Result<Transaction, ()> {
+    /// Removes the pending transaction, and applies it to the document if `commit` is true.
+    /// If the transaction cannot be applied, it is removed without touching the document.
+    pub fn finish(
+        &mut self,
+        tx_id: &TransactionId,
+        commit: bool,
+    ) -> Result<Transaction, FinishError> {
         if let Some(tx) = self.tx_manager.remove(tx_id) {
             if commit {
-                // TODO: Err
-                self.document.process(tx.clone());
+                self.document
+                    .process(tx.clone())
+                    .map_err(FinishError::InvalidMutation)?;
             }
             Ok(tx)
         } else {
             log::warn!("Tried to finish transaction but doesn't exists: {}", tx_id);
-            Err(())
+            Err(FinishError::UnknownTransaction)
         }
     }
 }
 
+#[derive(Debug)]
+pub enum FinishError {
+    UnknownTransaction,
+    InvalidMutation(MutationError),
+}
+
 impl PropReadable for TransactionalDocument {
     fn get_prop(&self, object_id: &ObjectId, prop_kind: &PropKind) -> Option<&PropValue> {
-        let from_kv = self.document.get_prop(object_id, prop_kind);
-        let from_tx = self.tx_manager.get_prop(object_id, prop_kind);
-        from_tx.or(from_kv)
+        match self.tx_manager.pending_prop(object_id, prop_kind) {
+            Some(from_tx) => from_tx,
+            None => self.document.get_prop(object_id, prop_kind),
+        }
     }
 
     fn get_object_kind(&self, object_id: &ObjectId) -> Option<&ObjectKind> {
-        let from_kv = self.document.get_object_kind(object_id);
-        let from_tx = self.tx_manager.get_object_kind(object_id);
-        from_tx.or(from_kv)
+        match self.tx_manager.pending_object_kind(object_id) {
+            Some(from_tx) => from_tx,
+            None => self.document.get_object_kind(object_id),
+        }
     }
 
     fn is_deleted(&self, object_id: &ObjectId) -> Option<bool> {
@@ -98,6 +115,9 @@ impl PropReadable for TransactionalDocument {
 
         result.append(&mut difference);
 
+        // Props deleted by pending transactions don't exist anymore.
+        result.retain(|(_, prop_value_opt)| prop_value_opt.is_some());
+
         result
     }
 
@@ -119,3 +139,163 @@ impl DocumentReadable for TransactionalDocument {
         self.document.snapshot()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document_command_transaction::convert_command_to_tx;
+    use crate::DocumentCommand;
+
+    fn create_oval_tx(object_id: ObjectId, parent_id: ObjectId) -> Transaction {
+        Transaction::new(vec![
+            DocumentMutation::CreateObject(object_id, ObjectKind::Oval),
+            DocumentMutation::UpsertProp(
+                object_id,
+                PropKind::Parent,
+                Some(PropValue::Reference(parent_id)),
+            ),
+            DocumentMutation::UpsertProp(
+                object_id,
+                PropKind::Name,
+                Some(PropValue::String("oval".into())),
+            ),
+        ])
+    }
+
+    #[test]
+    fn it_should_reflect_object_created_by_pending_transaction() {
+        let document = Document::new();
+        let document_id = document.document_id();
+        let mut tx_document = TransactionalDocument::new(document);
+        let oval_id = uuid::Uuid::new_v4();
+
+        tx_document.begin(create_oval_tx(oval_id, document_id));
+
+        assert_eq!(
+            tx_document.get_object_kind(&oval_id),
+            Some(&ObjectKind::Oval)
+        );
+        assert_eq!(tx_document.is_deleted(&oval_id), Some(false));
+        assert_eq!(tx_document.get_all_props_of_object(&oval_id).len(), 2);
+        assert!(tx_document.containing_objects().any(|id| id == &oval_id));
+        assert_eq!(
+            tx_document
+                .get_children_indices(&document_id)
+                .iter()
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>(),
+            vec![oval_id]
+        );
+    }
+
+    #[test]
+    fn it_should_delete_object_created_by_pending_transaction() {
+        let document = Document::new();
+        let document_id = document.document_id();
+        let mut tx_document = TransactionalDocument::new(document);
+        let oval_id = uuid::Uuid::new_v4();
+
+        let create_tx = create_oval_tx(oval_id, document_id);
+        let create_tx_id = create_tx.id;
+        tx_document.begin(create_tx);
+
+        let delete_tx =
+            convert_command_to_tx(&tx_document, DocumentCommand::DeleteObject { id: oval_id })
+                .expect("should work");
+        // Every prop created by the pending transaction must be deleted together.
+        assert_eq!(delete_tx.items.len(), 3);
+        let delete_tx_id = delete_tx.id;
+        tx_document.begin(delete_tx);
+
+        assert_eq!(tx_document.is_deleted(&oval_id), Some(true));
+        assert_eq!(tx_document.get_object_kind(&oval_id), None);
+        assert!(tx_document.get_prop(&oval_id, &PropKind::Name).is_none());
+        assert!(tx_document.get_all_props_of_object(&oval_id).is_empty());
+        assert!(tx_document.get_children_indices(&document_id).is_empty());
+
+        tx_document
+            .finish(&create_tx_id, true)
+            .expect("must finish");
+        tx_document
+            .finish(&delete_tx_id, true)
+            .expect("must finish");
+
+        assert_eq!(tx_document.document().get_object_kind(&oval_id), None);
+        assert!(tx_document
+            .document()
+            .get_all_props_of_object(&oval_id)
+            .is_empty());
+    }
+
+    #[test]
+    fn it_should_reflect_recreation_in_pending_transactions() {
+        let mut document = Document::new();
+        let document_id = document.document_id();
+        let oval_id = uuid::Uuid::new_v4();
+        document
+            .process(create_oval_tx(oval_id, document_id))
+            .expect("should work");
+        let mut tx_document = TransactionalDocument::new(document);
+
+        let delete_tx =
+            convert_command_to_tx(&tx_document, DocumentCommand::DeleteObject { id: oval_id })
+                .expect("should work");
+        let undo_tx = Transaction::new(delete_tx.inverted(&tx_document).items);
+        tx_document.begin(delete_tx);
+
+        assert_eq!(tx_document.is_deleted(&oval_id), Some(true));
+        assert!(tx_document.get_prop(&oval_id, &PropKind::Name).is_none());
+        assert!(tx_document.get_all_props_of_object(&oval_id).is_empty());
+
+        // Undo the deletion before the server acknowledges it.
+        let redo_tx = undo_tx.inverted(&tx_document);
+        tx_document.begin(undo_tx);
+
+        assert_eq!(tx_document.is_deleted(&oval_id), Some(false));
+        assert_eq!(
+            tx_document.get_object_kind(&oval_id),
+            Some(&ObjectKind::Oval)
+        );
+        assert_eq!(
+            tx_document.get_string_prop(&oval_id, &PropKind::Name),
+            Some("oval")
+        );
+        assert_eq!(tx_document.get_all_props_of_object(&oval_id).len(), 2);
+        assert_eq!(tx_document.get_children_indices(&document_id).len(), 1);
+
+        // Deleting the recreated object again must delete every prop without deleting twice.
+        assert!(redo_tx
+            .items
+            .iter()
+            .any(|m| matches!(m, DocumentMutation::UpsertProp(_, PropKind::Name, None))));
+        assert!(matches!(
+            redo_tx.items.last(),
+            Some(DocumentMutation::DeleteObject(_))
+        ));
+    }
+
+    #[test]
+    fn it_should_keep_document_untouched_when_commit_fails() {
+        let document = Document::new();
+        let document_id = document.document_id();
+        let mut tx_document = TransactionalDocument::new(document);
+        let oval_id = uuid::Uuid::new_v4();
+
+        let mut tx = create_oval_tx(oval_id, document_id);
+        tx.items
+            .push(DocumentMutation::UpsertProp(oval_id, PropKind::Width, None));
+        let tx_id = tx.id;
+        tx_document.begin(tx);
+
+        match tx_document.finish(&tx_id, true) {
+            Err(FinishError::InvalidMutation(error)) => {
+                assert_eq!(error.index, 3);
+                assert_eq!(error.reason, MutationErrorReason::PropNotFound);
+            }
+            _ => panic!("unexpected result"),
+        }
+        assert!(tx_document.get_tx(&tx_id).is_none());
+        assert_eq!(tx_document.get_object_kind(&oval_id), None);
+        assert!(tx_document.get_all_props_of_object(&oval_id).is_empty());
+    }
+}
@@ -8,7 +8,7 @@ use system::{
 fn it_should_materialize_oval() {
     let document = Document::new();
     let mut server = ServerLeaderDocument::new(document);
-    let mut client = ClientFollowerDocument::new(server.snapshot());
+    let mut client = ClientFollowerDocument::new(server.snapshot()).expect("");
 
     let tx_result = client
         .handle_command(DocumentCommand::CreateOval {
@@ -6,7 +6,8 @@ use wasm_bindgen::prelude::*;
 use session_state::SessionState;
 use system::{
     bincode, serde_json, uuid, CommandId, CommandResult, IdentifiableCommand, IdentifiableEvent,
-    SessionCommand, SessionEvent,
+    InitAssembler, MaterializeOptions, MaterializeTreeOptions, ObjectQuery, SessionCommand,
+    SessionEvent,
 };
 
 mod session_state;
@@ -25,28 +26,82 @@ pub struct CanvasSystem {
     session: SessionState,
 }
 
+/// Receives the first events of a connection until the document snapshot is complete.
+///
+/// A large snapshot comes in several chunks if the client connects with `init=chunked` or
+/// `init=deflate` in the query of the websocket URL. Push every event into `push` until it returns
+/// `true`, then call `build`.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct CanvasSystemReceiver {
+    assembler: InitAssembler,
+    init: Option<SessionEvent>,
+}
+
+#[wasm_bindgen]
+impl CanvasSystemReceiver {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> CanvasSystemReceiver {
+        CanvasSystemReceiver::default()
+    }
+
+    /// Returns whether the snapshot is complete.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<bool, JsValue> {
+        let event = bincode::deserialize::<IdentifiableEvent>(bytes).map_err(|_| JsValue::NULL)?;
+        match event {
+            IdentifiableEvent::BySystem {
+                session_event: init @ SessionEvent::Init { .. },
+            } => self.init = Some(init),
+            IdentifiableEvent::BySystem {
+                session_event: SessionEvent::InitChunk(chunk),
+            } => {
+                self.init = self.assembler.push(chunk).map_err(|err| {
+                    log::error!("Cannot reassemble document snapshot: {:?}", err);
+                    JsValue::NULL
+                })?
+            }
+            _ => return Err(JsValue::NULL),
+        }
+        Ok(self.init.is_some())
+    }
+
+    pub fn build(self) -> Result<CanvasSystem, JsValue> {
+        CanvasSystem::from_init(self.init.ok_or(JsValue::NULL)?)
+    }
+}
+
 #[wasm_bindgen]
 impl CanvasSystem {
     #[wasm_bindgen(constructor)]
     pub fn new(bytes: &[u8]) -> Result<CanvasSystem, JsValue> {
+        let event = bincode::deserialize::<IdentifiableEvent>(bytes).map_err(|_| JsValue::NULL)?;
+        match event {
+            IdentifiableEvent::BySystem { session_event } => Self::from_init(session_event),
+            _ => Err(JsValue::NULL),
+        }
+    }
+
+    fn from_init(session_event: SessionEvent) -> Result<CanvasSystem, JsValue> {
         utils::set_panic_hook();
         console_log::init_with_level(log::Level::Trace).map_err(|_| JsValue::NULL)?;
 
-        let event = bincode::deserialize::<IdentifiableEvent>(bytes).map_err(|_| JsValue::NULL)?;
-        log::trace!("Initializing: {:?}", event);
-        if let IdentifiableEvent::BySystem {
-            session_event:
-                SessionEvent::Init {
-                    document_snapshot,
-                    session_snapshot,
-                    ..
-                },
-        } = event
+        log::trace!("Initializing: {:?}", session_event);
+        if let SessionEvent::Init {
+            document_snapshot,
+            session_snapshot,
+            ..
+        } = session_event
         {
+            let session =
+                SessionState::new(document_snapshot, session_snapshot).map_err(|err| {
+                    let message = format!("Cannot read document snapshot: {:?}", err);
+                    log::error!("{}", message);
+                    JsValue::from_str(&message)
+                })?;
             Ok(CanvasSystem {
                 command_id_source: Wrapping(0),
                 pending_identifiable_commands: VecDeque::new(),
-                session: SessionState::new(document_snapshot, session_snapshot),
+                session,
             })
         } else {
             Err(JsValue::NULL)
@@ -109,6 +164,32 @@ impl CanvasSystem {
         }
     }
 
+    /// Imports shapes of the SVG as the last children of the object, or of the document if
+    /// `parent_id` is not given. Returns warnings about the parts not imported as JSON.
+    pub fn import_svg(
+        &mut self,
+        svg: String,
+        parent_id: Option<String>,
+    ) -> Result<String, JsValue> {
+        let parent_id = parent_id
+            .map(|uuid_str| uuid::Uuid::parse_str(&uuid_str))
+            .transpose()
+            .map_err(|_| JsValue::NULL)?;
+        let (tx, warnings) = self.session.import_svg(parent_id, &svg).map_err(|err| {
+            log::error!("Cannot import SVG: {:?}", err);
+            JsValue::NULL
+        })?;
+        if let Some(tx) = tx {
+            let command_id = self.new_command_id();
+            self.pending_identifiable_commands
+                .push_back(IdentifiableCommand {
+                    command_id,
+                    session_command: SessionCommand::Transaction(tx),
+                });
+        }
+        serde_json::to_string(&warnings).map_err(|_| JsValue::NULL)
+    }
+
     pub fn undo(&mut self) -> Result<(), JsValue> {
         self.session
             .undo()
@@ -141,6 +222,11 @@ impl CanvasSystem {
         self.session.consume_invalidated_object_ids()
     }
 
+    /// Returns JSON of `MaterialChange`s of the objects invalidated since the last call.
+    pub fn consume_material_changes(&mut self) -> String {
+        self.session.consume_material_changes()
+    }
+
     pub fn consume_pending_identifiable_command(&mut self) -> Option<Box<[u8]>> {
         self.pending_identifiable_commands
             .pop_front()
@@ -156,6 +242,10 @@ impl CanvasSystem {
         self.session.materialize_document()
     }
 
+    pub fn export_document_json(&self) -> String {
+        self.session.export_document_json()
+    }
+
     pub fn materialize_session(&self) -> String {
         self.session.materialize_session()
     }
@@ -167,6 +257,52 @@ impl CanvasSystem {
             .ok_or(JsValue::NULL)
     }
 
+    /// `options_json` is JSON of `MaterializeOptions`, e.g. `{"global": true}` to include global
+    /// transform, bounding box and depth.
+    pub fn materialize_object_with_options(
+        &self,
+        uuid_str: String,
+        options_json: String,
+    ) -> Result<String, JsValue> {
+        let object_id = uuid::Uuid::parse_str(&uuid_str).map_err(|_| JsValue::NULL)?;
+        let options =
+            serde_json::from_str::<MaterializeOptions>(&options_json).map_err(|_| JsValue::NULL)?;
+        self.session
+            .materialize_object_with_options(&object_id, &options)
+            .ok_or(JsValue::NULL)
+    }
+
+    /// Returns JSON of the `MaterialNode` tree from the object, or from the document if `root_id`
+    /// is not given. `options_json` is JSON of `MaterializeTreeOptions`, e.g.
+    /// `{"max_depth": 2, "kinds": ["Frame"]}`.
+    pub fn materialize_tree(
+        &self,
+        root_id: Option<String>,
+        options_json: String,
+    ) -> Result<String, JsValue> {
+        let root_id = match root_id {
+            Some(uuid_str) => Some(uuid::Uuid::parse_str(&uuid_str).map_err(|_| JsValue::NULL)?),
+            None => None,
+        };
+        let options = serde_json::from_str::<MaterializeTreeOptions>(&options_json)
+            .map_err(|_| JsValue::NULL)?;
+        self.session
+            .materialize_tree(root_id, &options)
+            .ok_or(JsValue::NULL)
+    }
+
+    /// `query_json` is JSON of `ObjectQuery`. Returns JSON of ids of the matching objects in
+    /// document order.
+    pub fn query_objects(&self, query_json: String) -> Result<String, JsValue> {
+        let query = serde_json::from_str::<ObjectQuery>(&query_json)
+            .map_err(|err| JsValue::from_str(&err.to_string()))?;
+        let object_ids = self
+            .session
+            .query_objects(&query)
+            .map_err(|err| JsValue::from_str(&format!("{:?}", err)))?;
+        serde_json::to_string(&object_ids).map_err(|_| JsValue::NULL)
+    }
+
     pub fn consume_latest_session_snapshot(&mut self) -> Option<String> {
         self.session.consume_latest_session_snapshot()
     }
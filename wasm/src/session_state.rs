@@ -2,29 +2,37 @@ use wasm_bindgen::__rt::std::collections::HashSet;
 
 use std::collections::VecDeque;
 use system::{
-    serde_json, ClientFollowerDocument, DocumentCommand, DocumentSnapshot, LivePointerEvent,
-    Materialize, ObjectId, SessionEvent, SessionSnapshot, Transaction,
+    import_svg, query_objects, serde_json, ClientFollowerDocument, DocumentCommand, DocumentJson,
+    DocumentReadable, DocumentSnapshot, LivePointerEvent, MaterialCache, Materialize,
+    MaterializeOptions, MaterializeTreeOptions, ObjectId, ObjectQuery, QueryError, SessionEvent,
+    SessionSnapshot, SnapshotError, SvgImport, SvgImportError, SvgImportWarning, Transaction,
 };
 
 pub struct SessionState {
     session_snapshot: SessionSnapshot,
     session_snapshot_invalidated: bool,
     document: ClientFollowerDocument,
+    material_cache: MaterialCache,
     invalidated_object_ids: HashSet<ObjectId>,
     pending_live_pointer_events: VecDeque<LivePointerEvent>,
     terminated: bool,
 }
 
 impl SessionState {
-    pub fn new(document_snapshot: DocumentSnapshot, session_snapshot: SessionSnapshot) -> Self {
-        Self {
-            document: ClientFollowerDocument::new(document_snapshot),
+    pub fn new(
+        document_snapshot: DocumentSnapshot,
+        session_snapshot: SessionSnapshot,
+    ) -> Result<Self, SnapshotError> {
+        let document = ClientFollowerDocument::new(document_snapshot)?;
+        Ok(Self {
+            material_cache: MaterialCache::from_materialize(&document),
+            document,
             session_snapshot,
             session_snapshot_invalidated: true,
             invalidated_object_ids: HashSet::new(),
             pending_live_pointer_events: VecDeque::new(),
             terminated: false,
-        }
+        })
     }
 
     pub fn handle_session_event(&mut self, event: SessionEvent) -> Result<(), ()> {
@@ -73,6 +81,30 @@ impl SessionState {
         }
     }
 
+    /// Imports into the document if `parent_id` is not given. The transaction is `None` if there
+    /// was nothing to import.
+    pub fn import_svg(
+        &mut self,
+        parent_id: Option<ObjectId>,
+        svg: &str,
+    ) -> Result<(Option<Transaction>, Vec<SvgImportWarning>), SvgImportError> {
+        let readable = self.document.readable();
+        let parent_id = parent_id.unwrap_or_else(|| readable.document_id());
+        let SvgImport {
+            transaction,
+            warnings,
+        } = import_svg(readable, &parent_id, svg)?;
+        if transaction.items.is_empty() {
+            return Ok((None, warnings));
+        }
+
+        let result = self.document.handle_local_transaction(transaction);
+        for invalidated_object_id in result.invalidated_object_ids {
+            self.invalidated_object_ids.insert(invalidated_object_id);
+        }
+        Ok((Some(result.transaction), warnings))
+    }
+
     pub fn undo(&mut self) -> Result<Transaction, ()> {
         // TODO: Err
         if let Ok(result) = self.document.undo() {
@@ -107,6 +139,16 @@ impl SessionState {
         result
     }
 
+    /// Consumes invalidated objects like `consume_invalidated_object_ids`, but returns how they
+    /// changed since the last call instead of their ids.
+    pub fn consume_material_changes(&mut self) -> String {
+        let changes = self
+            .document
+            .materialize_changes(&mut self.material_cache, &self.invalidated_object_ids);
+        self.invalidated_object_ids.clear();
+        serde_json::to_string(&changes).expect("must succeed")
+    }
+
     pub fn consume_latest_session_snapshot(&mut self) -> Option<String> {
         if self.session_snapshot_invalidated {
             serde_json::to_string(&self.session_snapshot).ok()
@@ -130,6 +172,12 @@ impl SessionState {
         serde_json::to_string(&document).expect("must succeed")
     }
 
+    /// Includes changes of transactions not acknowledged yet.
+    pub fn export_document_json(&self) -> String {
+        let document_json = DocumentJson::from_readable(self.document.readable());
+        serde_json::to_string_pretty(&document_json).expect("must succeed")
+    }
+
     pub fn materialize_session(&self) -> String {
         serde_json::to_string(&self.session_snapshot).expect("must succeed")
     }
@@ -142,6 +190,35 @@ impl SessionState {
             .ok()
     }
 
+    pub fn materialize_object_with_options(
+        &self,
+        object_id: &ObjectId,
+        options: &MaterializeOptions,
+    ) -> Option<String> {
+        self.document
+            .materialize_object_with_options(object_id, options)
+            .map(|m| serde_json::to_string(&m).expect("must succeed"))
+            .ok()
+    }
+
+    /// Materializes the document if `root_id` is not given.
+    pub fn materialize_tree(
+        &self,
+        root_id: Option<ObjectId>,
+        options: &MaterializeTreeOptions,
+    ) -> Option<String> {
+        let root_id = root_id.unwrap_or_else(|| self.document.readable().document_id());
+        self.document
+            .materialize_tree(&root_id, options)
+            .map(|m| serde_json::to_string(&m).expect("must succeed"))
+            .ok()
+    }
+
+    /// Includes changes of transactions not acknowledged yet.
+    pub fn query_objects(&self, query: &ObjectQuery) -> Result<Vec<ObjectId>, QueryError> {
+        query_objects(self.document.readable(), query)
+    }
+
     pub fn terminated(&self) -> bool {
         self.terminated
     }